@@ -0,0 +1,12 @@
+# Makes the entity it is attached to a button that launches it upwards when used.
+# Attach with: world.entity_with_name("Duck").attach_script("scripts/button.py")
+
+presses = 0
+
+self.set_usable("Press")
+
+def on_use(user):
+  global presses
+  presses += 1
+  log("info", "pressed {} times".format(presses))
+  self.apply_impulse(0.0, 5.0, 0.0)
@@ -0,0 +1,10 @@
+# Pushes the entity it is attached to upwards while it is below a target height.
+# Attach with: world.entity_with_name("Duck").attach_script("scripts/thruster.py")
+
+target_height = 10.0
+strength = 0.5
+
+def update(dt):
+  x, y, z = self.transform().position().to_list()
+  if y < target_height:
+    self.apply_impulse(0.0, strength, 0.0)
@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in float v_Depth;
+
+layout(location = 0) out vec4 o_Target;
+
+layout(set = 2, binding = 0) uniform FogMaterial_color {
+    vec4 Color;
+};
+
+layout(set = 2, binding = 1) uniform FogMaterial_distance {
+    float Distance;
+};
+
+void main() {
+    if (Distance <= 0.0) {
+        discard;
+    }
+    // Starts a quarter of the way out, and hides everything at the fog distance
+    float fog = clamp((v_Depth - Distance * 0.25) / (Distance * 0.75), 0.0, 1.0);
+    o_Target = vec4(Color.rgb, fog);
+}
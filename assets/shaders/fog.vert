@@ -0,0 +1,22 @@
+#version 450
+
+layout(location = 0) in vec3 Vertex_Position;
+
+layout(location = 0) out float v_Depth;
+
+layout(set = 0, binding = 0) uniform CameraViewProj {
+    mat4 ViewProj;
+};
+
+layout(set = 1, binding = 0) uniform Transform {
+    mat4 Model;
+};
+
+void main() {
+    // Transformed the same way as in the PBR shader, so the depth test passes exactly where the
+    // mesh was drawn
+    vec4 world_position = Model * vec4(Vertex_Position, 1.0);
+    gl_Position = ViewProj * world_position;
+    // Distance in front of the camera
+    v_Depth = gl_Position.w;
+}
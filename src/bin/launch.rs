@@ -22,16 +22,24 @@ fn main() {
   }
 
   app
-    .insert_resource(Msaa { samples: 4 })
+    // Has to go before DefaultPlugins, since it sets up Msaa from the user's config
+    .add_plugin(cvars::CvarsPlugin)
     .add_plugins(DefaultPlugins)
+    .add_plugin(console::ConsolePlugin)
     .add_plugin(shaders::ShadersPlugin)
     .add_plugin(physics::PhysicsPlugin)
     .add_plugin(player::PlayerControllerPlugin)
     .add_plugin(tools::ToolPlugin)
+    .add_plugin(interaction::InteractionPlugin)
+    .add_plugin(health::HealthPlugin)
+    .add_plugin(vehicles::VehiclePlugin)
     .add_plugin(map::MapPlugin)
+    .add_plugin(environment::EnvironmentPlugin)
+    .add_plugin(terrain::TerrainPlugin)
     .add_plugin(ui::UiPlugin)
     .add_plugin(serde::SerdePlugin)
     .add_plugin(models::ModelsPlugin)
+    .add_plugin(save::SavePlugin)
     .add_plugin(scripts::ScriptsPlugin);
 
   #[cfg(target_arch = "wasm32")]
@@ -0,0 +1,405 @@
+use crate::{
+  cvars::{CvarValue, Cvars},
+  prelude::*,
+  scripts::pymod::{ScriptOutputEvent, ScriptOutputLevel},
+};
+use anyhow::{anyhow, bail};
+use bevy::app::ManualEventReader;
+use std::sync::Arc;
+
+/// How an argument of a console command is parsed and completed.
+pub enum ArgKind {
+  Int,
+  Float,
+  Bool,
+  Text,
+  /// Text completed from the names returned by the function, e.g. the models that can be spawned.
+  Choice(fn(&mut World) -> Vec<String>),
+}
+
+pub struct CommandArg {
+  pub name: &'static str,
+  pub kind: ArgKind,
+  pub optional: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum ArgValue {
+  Int(i64),
+  Float(f32),
+  Bool(bool),
+  Text(String),
+}
+
+/// Parsed arguments of a command, in the order of its `args`. Optional arguments that weren't
+/// given are missing from the end.
+pub struct CommandArgs(pub Vec<ArgValue>);
+
+impl CommandArgs {
+  pub fn int(&self, index: usize) -> Option<i64> {
+    match self.0.get(index)? {
+      ArgValue::Int(value) => Some(*value),
+      _ => None,
+    }
+  }
+
+  pub fn float(&self, index: usize) -> Option<f32> {
+    match self.0.get(index)? {
+      ArgValue::Float(value) => Some(*value),
+      ArgValue::Int(value) => Some(*value as f32),
+      _ => None,
+    }
+  }
+
+  pub fn bool(&self, index: usize) -> Option<bool> {
+    match self.0.get(index)? {
+      ArgValue::Bool(value) => Some(*value),
+      _ => None,
+    }
+  }
+
+  pub fn text(&self, index: usize) -> Option<&str> {
+    match self.0.get(index)? {
+      ArgValue::Text(value) => Some(value),
+      _ => None,
+    }
+  }
+}
+
+/// Runs a command, returning text to print in the terminal.
+pub type CommandHandler =
+  Arc<dyn Fn(&mut World, &CommandArgs) -> anyhow::Result<Option<String>> + Send + Sync>;
+
+/// A command typed into the terminal as `/name arg1 arg2`.
+pub struct ConsoleCommand {
+  pub name: &'static str,
+  pub args: Vec<CommandArg>,
+  pub help: &'static str,
+  pub handler: CommandHandler,
+}
+
+impl ConsoleCommand {
+  pub fn new(
+    name: &'static str,
+    help: &'static str,
+    handler: impl Fn(&mut World, &CommandArgs) -> anyhow::Result<Option<String>> + Send + Sync + 'static,
+  ) -> Self {
+    ConsoleCommand {
+      name,
+      args: vec![],
+      help,
+      handler: Arc::new(handler),
+    }
+  }
+
+  pub fn arg(mut self, name: &'static str, kind: ArgKind) -> Self {
+    self.args.push(CommandArg {
+      name,
+      kind,
+      optional: false,
+    });
+    self
+  }
+
+  pub fn optional_arg(mut self, name: &'static str, kind: ArgKind) -> Self {
+    self.args.push(CommandArg {
+      name,
+      kind,
+      optional: true,
+    });
+    self
+  }
+
+  pub fn usage(&self) -> String {
+    let mut usage = format!("/{}", self.name);
+    for arg in self.args.iter() {
+      if arg.optional {
+        usage += &format!(" [{}]", arg.name);
+      } else {
+        usage += &format!(" <{}>", arg.name);
+      }
+    }
+    usage
+  }
+
+  fn parse_args(&self, words: &[&str]) -> anyhow::Result<CommandArgs> {
+    if words.len() > self.args.len() {
+      bail!("too many arguments, usage: {}", self.usage());
+    }
+
+    let mut values = vec![];
+    for (i, arg) in self.args.iter().enumerate() {
+      let word = match words.get(i) {
+        Some(word) => *word,
+        None if arg.optional => break,
+        None => bail!("missing <{}>, usage: {}", arg.name, self.usage()),
+      };
+
+      let invalid = || anyhow!("invalid <{}> '{}', usage: {}", arg.name, word, self.usage());
+      let value = match arg.kind {
+        ArgKind::Int => ArgValue::Int(word.parse().map_err(|_| invalid())?),
+        ArgKind::Float => ArgValue::Float(word.parse().map_err(|_| invalid())?),
+        ArgKind::Bool => ArgValue::Bool(parse_bool(word).ok_or_else(invalid)?),
+        ArgKind::Text | ArgKind::Choice(_) => ArgValue::Text(word.to_owned()),
+      };
+      values.push(value);
+    }
+    Ok(CommandArgs(values))
+  }
+}
+
+fn parse_bool(word: &str) -> Option<bool> {
+  match word.to_lowercase().as_str() {
+    "1" | "true" | "on" | "yes" => Some(true),
+    "0" | "false" | "off" | "no" => Some(false),
+    _ => None,
+  }
+}
+
+#[derive(Default)]
+pub struct ConsoleCommands(Vec<ConsoleCommand>);
+
+impl ConsoleCommands {
+  pub fn register(&mut self, command: ConsoleCommand) {
+    self.0.retain(|existing| existing.name != command.name);
+    self.0.push(command);
+    self.0.sort_by_key(|command| command.name);
+  }
+
+  pub fn get(&self, name: &str) -> Option<&ConsoleCommand> {
+    self.0.iter().find(|command| command.name == name)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &ConsoleCommand> {
+    self.0.iter()
+  }
+}
+
+/// Lets plugins register console commands while building the app.
+pub trait AppConsoleExt {
+  fn add_console_command(&mut self, command: ConsoleCommand) -> &mut Self;
+}
+
+impl AppConsoleExt for App {
+  fn add_console_command(&mut self, command: ConsoleCommand) -> &mut Self {
+    self
+      .world
+      .get_resource_or_insert_with(ConsoleCommands::default)
+      .register(command);
+    self
+  }
+}
+
+/// A line typed into the terminal after a `/`, e.g. `spawn Duck 3`.
+pub struct ConsoleCommandEvent {
+  pub line: String,
+}
+
+/// Asks for completions of the last word of `line`, which doesn't include the leading `/`.
+pub struct ConsoleCompletionRequestEvent {
+  pub line: String,
+}
+
+/// Completions for the most recent `ConsoleCompletionRequestEvent`.
+#[derive(Default)]
+pub struct ConsoleCompletions {
+  pub line: Option<String>,
+  /// Replacements for the last word of the line.
+  pub candidates: Vec<String>,
+}
+
+#[derive(Default)]
+struct ConsoleEventReaders {
+  commands: ManualEventReader<ConsoleCommandEvent>,
+  completions: ManualEventReader<ConsoleCompletionRequestEvent>,
+}
+
+fn print(world: &mut World, level: ScriptOutputLevel, output: String) {
+  world
+    .get_resource_mut::<Events<ScriptOutputEvent>>()
+    .unwrap()
+    .send(ScriptOutputEvent { output, level });
+}
+
+fn run_command(world: &mut World, line: &str) -> anyhow::Result<Option<String>> {
+  let words = line.split_whitespace().collect::<Vec<_>>();
+  let name = match words.first() {
+    Some(name) => *name,
+    None => return Ok(None),
+  };
+
+  // `/name` shows a cvar and `/name value` sets it
+  let cvar = world
+    .get_resource::<Cvars>()
+    .and_then(|cvars| cvars.get(name))
+    .map(|cvar| {
+      format!(
+        "{} = {} (default {})\n  {}",
+        cvar.name,
+        cvar.value(),
+        cvar.default,
+        cvar.help
+      )
+    });
+  if let Some(description) = cvar {
+    if words.len() == 1 {
+      return Ok(Some(description));
+    }
+    let mut cvars = world.get_resource_mut::<Cvars>().unwrap();
+    cvars.set_str(name, &words[1..].join(" "))?;
+    return Ok(Some(format!(
+      "{} = {}",
+      name,
+      cvars.get(name).unwrap().value()
+    )));
+  }
+
+  // Handlers get the whole world, so only the handler is kept from the registry while it runs
+  let (handler, args) = {
+    let commands = world.get_resource::<ConsoleCommands>().unwrap();
+    let command = commands.get(name).ok_or_else(|| {
+      anyhow!(
+        "unknown command /{}, type /help for a list of commands",
+        name
+      )
+    })?;
+    (command.handler.clone(), command.parse_args(&words[1..])?)
+  };
+  handler(world, &args)
+}
+
+fn complete_command(world: &mut World, line: &str) -> Vec<String> {
+  let mut words = line.split_whitespace().collect::<Vec<_>>();
+  if line.is_empty() || line.ends_with(char::is_whitespace) {
+    words.push("");
+  }
+  let prefix = words.last().unwrap().to_lowercase();
+
+  let (names, choices, is_bool) = {
+    let commands = world.get_resource::<ConsoleCommands>().unwrap();
+    let kind = commands
+      .get(words[0])
+      .and_then(|command| command.args.get(words.len().wrapping_sub(2)))
+      .map(|arg| &arg.kind);
+    let choices = match kind {
+      Some(ArgKind::Choice(choices)) => Some(*choices),
+      _ => None,
+    };
+    let mut names = command_names(commands);
+    let mut is_bool = matches!(kind, Some(ArgKind::Bool));
+    if let Some(cvars) = world.get_resource::<Cvars>() {
+      names.extend(cvars.iter().map(|cvar| cvar.name.to_owned()));
+      is_bool |= words.len() == 2
+        && matches!(
+          cvars.get(words[0]).map(|cvar| cvar.value()),
+          Some(CvarValue::Bool(_))
+        );
+    }
+    (names, choices, is_bool)
+  };
+
+  let candidates = if words.len() == 1 {
+    names
+  } else if let Some(choices) = choices {
+    choices(world)
+  } else if is_bool {
+    vec!["true".to_owned(), "false".to_owned()]
+  } else {
+    vec![]
+  };
+
+  candidates
+    .into_iter()
+    .filter(|candidate| candidate.to_lowercase().starts_with(&prefix))
+    .collect()
+}
+
+fn run_console_commands(world: &mut World) {
+  let (lines, completion_line) =
+    world.resource_scope(|world, mut readers: Mut<ConsoleEventReaders>| {
+      let lines = readers
+        .commands
+        .iter(world.get_resource::<Events<ConsoleCommandEvent>>().unwrap())
+        .map(|event| event.line.clone())
+        .collect::<Vec<_>>();
+      let completion_line = readers
+        .completions
+        .iter(
+          world
+            .get_resource::<Events<ConsoleCompletionRequestEvent>>()
+            .unwrap(),
+        )
+        .last()
+        .map(|event| event.line.clone());
+      (lines, completion_line)
+    });
+
+  for line in lines {
+    match run_command(world, &line) {
+      Ok(Some(output)) => print(world, ScriptOutputLevel::Output, output + "\n"),
+      Ok(None) => {}
+      Err(e) => print(world, ScriptOutputLevel::Error, format!("{}\n", e)),
+    }
+  }
+
+  if let Some(line) = completion_line {
+    let candidates = complete_command(world, &line);
+    world.insert_resource(ConsoleCompletions {
+      line: Some(line),
+      candidates,
+    });
+  }
+}
+
+fn command_names(commands: &ConsoleCommands) -> Vec<String> {
+  commands
+    .iter()
+    .map(|command| command.name.to_owned())
+    .collect()
+}
+
+fn help_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "help",
+    "Lists commands, or describes one command",
+    |world, args| {
+      let commands = world.get_resource::<ConsoleCommands>().unwrap();
+      let help = match args.text(0) {
+        Some(name) => {
+          let command = commands
+            .get(name.trim_start_matches('/'))
+            .ok_or_else(|| anyhow!("unknown command /{}", name))?;
+          format!("{}\n  {}", command.usage(), command.help)
+        }
+        None => commands
+          .iter()
+          .map(|command| format!("{:<28} {}", command.usage(), command.help))
+          .collect::<Vec<_>>()
+          .join("\n"),
+      };
+      Ok(Some(help))
+    },
+  )
+  .optional_arg(
+    "command",
+    ArgKind::Choice(|world| command_names(world.get_resource::<ConsoleCommands>().unwrap())),
+  )
+}
+
+pub struct ConsolePlugin;
+impl Plugin for ConsolePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<ConsoleCommands>()
+      .init_resource::<ConsoleCompletions>()
+      .init_resource::<ConsoleEventReaders>()
+      .add_console_command(help_command())
+      .add_event::<ConsoleCommandEvent>()
+      .add_event::<ConsoleCompletionRequestEvent>()
+      // Commands run before Update so that events they send are seen by this frame's systems
+      .add_system_to_stage(
+        CoreStage::PreUpdate,
+        run_console_commands.exclusive_system(),
+      );
+  }
+}
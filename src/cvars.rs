@@ -0,0 +1,372 @@
+use crate::{
+  console::{AppConsoleExt, ConsoleCommand},
+  prelude::*,
+};
+use anyhow::{anyhow, bail};
+use bevy::app::AppExit;
+use std::{
+  collections::HashMap,
+  fmt,
+  path::{Path, PathBuf},
+};
+
+const USER_CONFIG_PATH: &str = "config/user.cfg";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CvarValue {
+  Int(i64),
+  Float(f32),
+  Bool(bool),
+}
+
+impl CvarValue {
+  /// Parses `text` as the same type as `self`.
+  fn parse_like(&self, text: &str) -> anyhow::Result<CvarValue> {
+    let text = text.trim();
+    let invalid = || anyhow!("'{}' is not a valid {}", text, self.type_name());
+    Ok(match self {
+      CvarValue::Int(_) => CvarValue::Int(text.parse().map_err(|_| invalid())?),
+      CvarValue::Float(_) => CvarValue::Float(text.parse().map_err(|_| invalid())?),
+      CvarValue::Bool(_) => CvarValue::Bool(match text.to_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => true,
+        "0" | "false" | "off" | "no" => false,
+        _ => return Err(invalid()),
+      }),
+    })
+  }
+
+  fn type_name(&self) -> &'static str {
+    match self {
+      CvarValue::Int(_) => "int",
+      CvarValue::Float(_) => "float",
+      CvarValue::Bool(_) => "bool",
+    }
+  }
+}
+
+impl fmt::Display for CvarValue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CvarValue::Int(value) => write!(f, "{}", value),
+      CvarValue::Float(value) => write!(f, "{}", value),
+      CvarValue::Bool(value) => write!(f, "{}", value),
+    }
+  }
+}
+
+/// A console variable, a named setting that can be changed from the terminal and from Python
+/// and is persisted between runs.
+pub struct Cvar {
+  pub name: &'static str,
+  pub help: &'static str,
+  pub default: CvarValue,
+  value: CvarValue,
+  range: Option<(f32, f32)>,
+  choices: Option<&'static [f32]>,
+}
+
+impl Cvar {
+  fn new(name: &'static str, help: &'static str, default: CvarValue) -> Self {
+    Cvar {
+      name,
+      help,
+      value: default.clone(),
+      default,
+      range: None,
+      choices: None,
+    }
+  }
+
+  pub fn int(name: &'static str, help: &'static str, default: i64) -> Self {
+    Cvar::new(name, help, CvarValue::Int(default))
+  }
+
+  pub fn float(name: &'static str, help: &'static str, default: f32) -> Self {
+    Cvar::new(name, help, CvarValue::Float(default))
+  }
+
+  pub fn bool(name: &'static str, help: &'static str, default: bool) -> Self {
+    Cvar::new(name, help, CvarValue::Bool(default))
+  }
+
+  /// Limits a numeric cvar to `min..=max`.
+  pub fn range(mut self, min: f32, max: f32) -> Self {
+    self.range = Some((min, max));
+    self
+  }
+
+  /// Limits a numeric cvar to the values in `choices`.
+  pub fn choices(mut self, choices: &'static [f32]) -> Self {
+    self.choices = Some(choices);
+    self
+  }
+
+  pub fn value(&self) -> &CvarValue {
+    &self.value
+  }
+
+  /// Parses text typed by the user, e.g. `0.5` or `on`, as a value of this cvar.
+  pub fn parse(&self, text: &str) -> anyhow::Result<CvarValue> {
+    self.check(self.default.parse_like(text)?)
+  }
+
+  /// Checks that `value` has the right type and is in range, converting ints for float cvars.
+  fn check(&self, value: CvarValue) -> anyhow::Result<CvarValue> {
+    let value = match (&self.default, value) {
+      (CvarValue::Float(_), CvarValue::Int(value)) => CvarValue::Float(value as f32),
+      (default, value) if default.type_name() == value.type_name() => value,
+      (default, value) => bail!(
+        "{} is a {}, not a {}",
+        self.name,
+        default.type_name(),
+        value.type_name()
+      ),
+    };
+
+    let number = match value {
+      CvarValue::Int(value) => Some(value as f32),
+      CvarValue::Float(value) => Some(value),
+      CvarValue::Bool(_) => None,
+    };
+    if let (Some(number), Some((min, max))) = (number, self.range) {
+      if number < min || number > max {
+        bail!("{} must be between {} and {}", self.name, min, max);
+      }
+    }
+    if let (Some(number), Some(choices)) = (number, self.choices) {
+      if !choices.contains(&number) {
+        let choices = choices
+          .iter()
+          .map(|choice| choice.to_string())
+          .collect::<Vec<_>>();
+        bail!("{} must be one of {}", self.name, choices.join(", "));
+      }
+    }
+    Ok(value)
+  }
+}
+
+/// Every registered cvar. Subsystems read their settings from here, and can use
+/// `is_changed` or `CvarChangedEvent` to react to changes.
+pub struct Cvars {
+  cvars: Vec<Cvar>,
+  /// Values from the config file for cvars that haven't been registered yet.
+  unregistered: HashMap<String, String>,
+  /// Cvars changed since `CvarChangedEvent`s were last sent.
+  changed: Vec<&'static str>,
+  path: PathBuf,
+}
+
+impl Default for Cvars {
+  fn default() -> Self {
+    Cvars::load(Path::new(USER_CONFIG_PATH))
+  }
+}
+
+impl Cvars {
+  /// Reads a config file with a `name value` pair per line. Values are applied as the cvars get
+  /// registered, since plugins register them while the app is being built.
+  pub fn load(path: &Path) -> Self {
+    #[cfg(not(target_arch = "wasm32"))]
+    let contents = std::fs::read_to_string(path).unwrap_or_default();
+
+    #[cfg(target_arch = "wasm32")]
+    let contents = String::new();
+
+    let unregistered = contents
+      .lines()
+      .map(|line| line.trim())
+      .filter(|line| !line.is_empty() && !line.starts_with("//"))
+      .filter_map(|line| {
+        let mut parts = line.splitn(2, char::is_whitespace);
+        Some((parts.next()?.to_owned(), parts.next()?.trim().to_owned()))
+      })
+      .collect();
+
+    Cvars {
+      cvars: vec![],
+      unregistered,
+      changed: vec![],
+      path: path.to_owned(),
+    }
+  }
+
+  /// Writes the cvars that differ from their defaults.
+  pub fn save(&self) -> anyhow::Result<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      if let Some(dir) = self.path.parent() {
+        std::fs::create_dir_all(dir)?;
+      }
+      let mut lines = self
+        .cvars
+        .iter()
+        .filter(|cvar| cvar.value != cvar.default)
+        .map(|cvar| format!("{} {}", cvar.name, cvar.value))
+        .collect::<Vec<_>>();
+      // Keep settings of cvars that weren't registered this run
+      lines.extend(
+        self
+          .unregistered
+          .iter()
+          .map(|(name, value)| format!("{} {}", name, value)),
+      );
+      lines.sort();
+      std::fs::write(&self.path, lines.join("\n"))?;
+    }
+    Ok(())
+  }
+
+  pub fn register(&mut self, mut cvar: Cvar) {
+    if let Some(text) = self.unregistered.remove(cvar.name) {
+      match cvar.parse(&text) {
+        Ok(value) => cvar.value = value,
+        Err(e) => warn!("ignoring {} from {:?}: {}", cvar.name, self.path, e),
+      }
+    }
+    self.cvars.retain(|existing| existing.name != cvar.name);
+    self.cvars.push(cvar);
+    self.cvars.sort_by_key(|cvar| cvar.name);
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Cvar> {
+    self.cvars.iter().find(|cvar| cvar.name == name)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = &Cvar> {
+    self.cvars.iter()
+  }
+
+  pub fn set(&mut self, name: &str, value: CvarValue) -> anyhow::Result<()> {
+    let cvar = self
+      .cvars
+      .iter_mut()
+      .find(|cvar| cvar.name == name)
+      .ok_or_else(|| anyhow!("no cvar named {}", name))?;
+    let value = cvar.check(value)?;
+    if cvar.value != value {
+      cvar.value = value;
+      self.changed.push(cvar.name);
+    }
+    Ok(())
+  }
+
+  /// Sets a cvar from text typed by the user, e.g. `0.5` or `on`.
+  pub fn set_str(&mut self, name: &str, text: &str) -> anyhow::Result<()> {
+    let value = self
+      .get(name)
+      .ok_or_else(|| anyhow!("no cvar named {}", name))?
+      .parse(text)?;
+    self.set(name, value)
+  }
+
+  fn value(&self, name: &str) -> &CvarValue {
+    match self.get(name) {
+      Some(cvar) => &cvar.value,
+      None => panic!("no cvar named {}", name),
+    }
+  }
+
+  pub fn float(&self, name: &str) -> f32 {
+    match self.value(name) {
+      CvarValue::Float(value) => *value,
+      CvarValue::Int(value) => *value as f32,
+      CvarValue::Bool(_) => panic!("cvar {} is not a number", name),
+    }
+  }
+
+  pub fn int(&self, name: &str) -> i64 {
+    match self.value(name) {
+      CvarValue::Int(value) => *value,
+      _ => panic!("cvar {} is not an int", name),
+    }
+  }
+
+  pub fn bool(&self, name: &str) -> bool {
+    match self.value(name) {
+      CvarValue::Bool(value) => *value,
+      _ => panic!("cvar {} is not a bool", name),
+    }
+  }
+}
+
+/// Lets plugins register cvars while building the app.
+pub trait AppCvarsExt {
+  fn add_cvar(&mut self, cvar: Cvar) -> &mut Self;
+}
+
+impl AppCvarsExt for App {
+  fn add_cvar(&mut self, cvar: Cvar) -> &mut Self {
+    self
+      .world
+      .get_resource_or_insert_with(Cvars::default)
+      .register(cvar);
+    self
+  }
+}
+
+/// Sent after a cvar gets a new value.
+pub struct CvarChangedEvent {
+  pub name: &'static str,
+  pub value: CvarValue,
+}
+
+fn send_cvar_changed_events(mut cvars: ResMut<Cvars>, mut events: EventWriter<CvarChangedEvent>) {
+  // Only borrow mutably when needed, so `is_changed` stays meaningful for readers
+  if cvars.changed.is_empty() {
+    return;
+  }
+  let changed = std::mem::take(&mut cvars.changed);
+  for name in changed {
+    let value = cvars.value(name).clone();
+    events.send(CvarChangedEvent { name, value });
+  }
+}
+
+fn save_on_exit(cvars: Res<Cvars>, mut exit_events: EventReader<AppExit>) {
+  if exit_events.iter().count() > 0 {
+    if let Err(e) = cvars.save() {
+      error!("failed to save {:?}: {}", cvars.path, e);
+    }
+  }
+}
+
+fn cvarlist_command() -> ConsoleCommand {
+  ConsoleCommand::new("cvarlist", "Lists cvars and their values", |world, _| {
+    let cvars = world.get_resource::<Cvars>().unwrap();
+    let list = cvars
+      .iter()
+      .map(|cvar| format!("{:<24} {:<8} {}", cvar.name, cvar.value, cvar.help))
+      .collect::<Vec<_>>()
+      .join("\n");
+    Ok(Some(list))
+  })
+}
+
+pub const RENDER_MSAA: &str = "render.msaa";
+
+pub struct CvarsPlugin;
+impl Plugin for CvarsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_cvar(
+        Cvar::int(
+          RENDER_MSAA,
+          "Multisample anti-aliasing samples, 1 or 4, applied on restart",
+          4,
+        )
+        .choices(&[1., 4.]),
+      )
+      .add_event::<CvarChangedEvent>()
+      .add_system_to_stage(CoreStage::PostUpdate, send_cvar_changed_events.system())
+      .add_system_to_stage(CoreStage::Last, save_on_exit.system())
+      .add_console_command(cvarlist_command());
+
+    // The render pipelines are created with the sample count, so it can only be set up front
+    let samples = match app.world.get_resource::<Cvars>().unwrap().int(RENDER_MSAA) {
+      1 => 1,
+      _ => 4,
+    };
+    app.insert_resource(Msaa { samples });
+  }
+}
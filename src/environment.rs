@@ -0,0 +1,418 @@
+use crate::{player::spawn::Player, prelude::*};
+use bevy::{
+  reflect::TypeUuid,
+  render::{
+    camera::PerspectiveProjection,
+    mesh::Indices,
+    pipeline::{CompareFunction, PipelineDescriptor, RenderPipeline},
+    render_graph::{base::node::MAIN_PASS, AssetRenderResourcesNode, RenderGraph},
+    renderer::RenderResources,
+    shader::{ShaderStage, ShaderStages},
+    texture::{Extent3d, TextureDimension, TextureFormat},
+  },
+};
+use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+/// Rows of the texture the gradient sky is drawn from, from straight up to straight down.
+const SKY_TEXTURE_HEIGHT: u32 = 64;
+/// How far away the sky is drawn without fog, inside the default far plane of the camera.
+const SKY_DISTANCE: f32 = 900.;
+const DEFAULT_FAR: f32 = 1000.;
+const FOG_MATERIAL_NODE: &str = "fog_material";
+
+fn sun_elevation_default() -> f32 {
+  50.
+}
+
+fn sun_azimuth_default() -> f32 {
+  30.
+}
+
+fn white() -> [f32; 3] {
+  [1., 1., 1.]
+}
+
+fn sun_intensity_default() -> f32 {
+  30000.
+}
+
+fn ambient_brightness_default() -> f32 {
+  0.1
+}
+
+fn sky_color_default() -> [f32; 3] {
+  [0.4, 0.4, 0.4]
+}
+
+/// Lighting and sky of the world. Maps set it when they load, and it can be changed from Python
+/// and the debugger.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Environment {
+  /// Degrees above the horizon. Below it the sun gives no light, and the sky and ambient light dim.
+  #[serde(default = "sun_elevation_default")]
+  pub sun_elevation: f32,
+  /// Degrees clockwise from -Z.
+  #[serde(default = "sun_azimuth_default")]
+  pub sun_azimuth: f32,
+  #[serde(default = "white")]
+  pub sun_color: [f32; 3],
+  /// Illuminance in lux.
+  #[serde(default = "sun_intensity_default")]
+  pub sun_intensity: f32,
+  #[serde(default = "white")]
+  pub ambient_color: [f32; 3],
+  #[serde(default = "ambient_brightness_default")]
+  pub ambient_brightness: f32,
+  /// Color of the sky, or of the sky overhead if there's a horizon color.
+  #[serde(default = "sky_color_default")]
+  pub sky_color: [f32; 3],
+  /// Color the sky fades to at the horizon, for a gradient sky instead of a plain one.
+  #[serde(default)]
+  pub horizon_color: Option<[f32; 3]>,
+  /// Distance at which fog hides the world completely, or 0 for no fog. It starts a quarter of the
+  /// way out and fades to the sky color at the horizon.
+  #[serde(default)]
+  pub fog_distance: f32,
+  /// Seconds for the sun to go once around the world, or 0 to keep it still.
+  #[serde(default)]
+  pub day_length: f32,
+}
+
+impl Default for Environment {
+  fn default() -> Self {
+    Environment {
+      sun_elevation: sun_elevation_default(),
+      sun_azimuth: sun_azimuth_default(),
+      sun_color: white(),
+      sun_intensity: sun_intensity_default(),
+      ambient_color: white(),
+      ambient_brightness: ambient_brightness_default(),
+      sky_color: sky_color_default(),
+      horizon_color: None,
+      fog_distance: 0.,
+      day_length: 0.,
+    }
+  }
+}
+
+fn scaled_color([r, g, b]: [f32; 3], scale: f32) -> Color {
+  Color::rgb(r * scale, g * scale, b * scale)
+}
+
+impl Environment {
+  /// Direction from the world towards the sun.
+  pub fn sun_direction(&self) -> Vec3 {
+    let (elevation, azimuth) = (
+      self.sun_elevation.to_radians(),
+      self.sun_azimuth.to_radians(),
+    );
+    Vec3::new(
+      azimuth.sin() * elevation.cos(),
+      elevation.sin(),
+      -azimuth.cos() * elevation.cos(),
+    )
+  }
+
+  /// How much of the day's light there is, fading out as the sun sets.
+  fn daylight(&self) -> f32 {
+    (self.sun_direction().y * 4.).clamp(0., 1.)
+  }
+
+  /// Pixels of the gradient sky texture, blending from the sky color overhead to the horizon color
+  /// and darkening below it.
+  fn sky_texture_data(&self, horizon_color: [f32; 3], brightness: f32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SKY_TEXTURE_HEIGHT as usize * 4);
+    for row in 0..SKY_TEXTURE_HEIGHT {
+      let height = 1. - 2. * row as f32 / (SKY_TEXTURE_HEIGHT - 1) as f32;
+      let (from, to, t) = if height >= 0. {
+        (horizon_color, self.sky_color, height.sqrt())
+      } else {
+        let below = [
+          horizon_color[0] * 0.5,
+          horizon_color[1] * 0.5,
+          horizon_color[2] * 0.5,
+        ];
+        (horizon_color, below, -height)
+      };
+      for channel in 0..3 {
+        let value = (from[channel] + (to[channel] - from[channel]) * t) * brightness;
+        data.push((value.clamp(0., 1.) * 255.) as u8);
+      }
+      data.push(255);
+    }
+    data
+  }
+
+  /// Sliders for the debugger.
+  pub fn ui(&mut self, ui: &mut egui::Ui) {
+    ui.add(egui::Slider::new(&mut self.sun_elevation, -180.0..=180.0).text("sun elevation"));
+    ui.add(egui::Slider::new(&mut self.sun_azimuth, 0.0..=360.0).text("sun azimuth"));
+    ui.add(egui::Slider::new(&mut self.sun_intensity, 0.0..=100000.0).text("sun intensity"));
+    ui.horizontal(|ui| {
+      ui.color_edit_button_rgb(&mut self.sun_color);
+      ui.label("sun color");
+    });
+    ui.add(egui::Slider::new(&mut self.ambient_brightness, 0.0..=1.0).text("ambient brightness"));
+    ui.horizontal(|ui| {
+      ui.color_edit_button_rgb(&mut self.ambient_color);
+      ui.label("ambient color");
+    });
+    ui.horizontal(|ui| {
+      ui.color_edit_button_rgb(&mut self.sky_color);
+      ui.label("sky color");
+    });
+    let mut gradient = self.horizon_color.is_some();
+    ui.checkbox(&mut gradient, "gradient sky");
+    match (gradient, &mut self.horizon_color) {
+      (true, Some(horizon_color)) => {
+        ui.horizontal(|ui| {
+          ui.color_edit_button_rgb(horizon_color);
+          ui.label("horizon color");
+        });
+      }
+      (true, None) => self.horizon_color = Some(self.sky_color),
+      (false, _) => self.horizon_color = None,
+    }
+    ui.add(egui::Slider::new(&mut self.fog_distance, 0.0..=DEFAULT_FAR).text("fog distance"));
+    ui.add(egui::Slider::new(&mut self.day_length, 0.0..=600.0).text("day length"));
+  }
+}
+
+struct Sun;
+
+struct SkyDome;
+
+struct SkyTexture(Handle<Texture>);
+
+/// Uniforms of the fog shader, which is drawn over every lit mesh.
+#[derive(RenderResources, Default, TypeUuid)]
+#[uuid = "5b3c4f0e-8d2a-4e61-b7f9-2c1d6a9e4f38"]
+struct FogMaterial {
+  color: Color,
+  distance: f32,
+}
+
+struct Fog {
+  pipeline: Handle<PipelineDescriptor>,
+  material: Handle<FogMaterial>,
+}
+
+fn init_environment(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+  mut textures: ResMut<Assets<Texture>>,
+) {
+  commands.spawn_bundle((
+    DirectionalLight::new(Color::WHITE, 0., -Vec3::Y),
+    Sun,
+    Name::new("sun"),
+  ));
+
+  // The sky is seen from inside, so flip the sphere's triangles to face in
+  let mut mesh = Mesh::from(shape::UVSphere {
+    radius: 1.,
+    sectors: 32,
+    stacks: 16,
+  });
+  if let Some(Indices::U32(indices)) = mesh.indices_mut() {
+    for triangle in indices.chunks_mut(3) {
+      triangle.swap(1, 2);
+    }
+  }
+
+  let texture = textures.add(Texture::new(
+    Extent3d::new(1, SKY_TEXTURE_HEIGHT, 1),
+    TextureDimension::D2,
+    vec![255; SKY_TEXTURE_HEIGHT as usize * 4],
+    TextureFormat::Rgba8UnormSrgb,
+  ));
+  commands
+    .spawn_bundle(PbrBundle {
+      mesh: meshes.add(mesh),
+      material: materials.add(StandardMaterial {
+        base_color_texture: Some(texture.clone()),
+        unlit: true,
+        ..Default::default()
+      }),
+      visible: Visible {
+        is_visible: false,
+        ..Default::default()
+      },
+      ..Default::default()
+    })
+    .insert_bundle((SkyDome, Name::new("sky")));
+  commands.insert_resource(SkyTexture(texture));
+}
+
+fn init_fog(
+  mut commands: Commands,
+  mut pipelines: ResMut<Assets<PipelineDescriptor>>,
+  mut shaders: ResMut<Assets<Shader>>,
+  mut fog_materials: ResMut<Assets<FogMaterial>>,
+  mut render_graph: ResMut<RenderGraph>,
+) {
+  render_graph.add_system_node(
+    FOG_MATERIAL_NODE,
+    AssetRenderResourcesNode::<FogMaterial>::new(true),
+  );
+  render_graph
+    .add_node_edge(FOG_MATERIAL_NODE, MAIN_PASS)
+    .unwrap();
+
+  let mut pipeline = PipelineDescriptor {
+    name: Some("fog".into()),
+    ..PipelineDescriptor::default_config(ShaderStages {
+      vertex: shaders.add(Shader::from_glsl(
+        ShaderStage::Vertex,
+        include_str!("../assets/shaders/fog.vert"),
+      )),
+      fragment: Some(shaders.add(Shader::from_glsl(
+        ShaderStage::Fragment,
+        include_str!("../assets/shaders/fog.frag"),
+      ))),
+    })
+  };
+  // Fog is blended over the mesh it's drawn with, so it has to pass the depth test right where
+  // the mesh was drawn
+  if let Some(depth_stencil) = pipeline.depth_stencil.as_mut() {
+    depth_stencil.depth_write_enabled = false;
+    depth_stencil.depth_compare = CompareFunction::LessEqual;
+  }
+
+  commands.insert_resource(Fog {
+    pipeline: pipelines.add(pipeline),
+    material: fog_materials.add(FogMaterial::default()),
+  });
+}
+
+/// Draws the fog over lit meshes as they're spawned. The sky stays clear, since it's what the fog
+/// fades to.
+fn add_fog(
+  mut commands: Commands,
+  fog: Res<Fog>,
+  mut mesh_query: Query<
+    (Entity, &mut RenderPipelines),
+    (
+      With<Handle<StandardMaterial>>,
+      Without<Handle<FogMaterial>>,
+      Without<SkyDome>,
+    ),
+  >,
+) {
+  for (entity, mut render_pipelines) in mesh_query.iter_mut() {
+    let specialization = match render_pipelines.pipelines.first() {
+      Some(pipeline) => pipeline.specialization.clone(),
+      None => continue,
+    };
+    render_pipelines.pipelines.push(RenderPipeline::specialized(
+      fog.pipeline.clone(),
+      specialization,
+    ));
+    commands.entity(entity).insert(fog.material.clone());
+  }
+}
+
+/// Moves the sun around when there's a day/night cycle.
+fn cycle_day(time: Res<Time>, mut environment: ResMut<Environment>) {
+  if environment.day_length <= 0. {
+    return;
+  }
+  let step = 360. * time.delta_seconds() / environment.day_length;
+  environment.sun_elevation = (environment.sun_elevation + step + 180.).rem_euclid(360.) - 180.;
+}
+
+fn apply_environment(
+  environment: Res<Environment>,
+  player: Res<Player>,
+  sky_texture: Res<SkyTexture>,
+  fog: Res<Fog>,
+  mut clear_color: ResMut<ClearColor>,
+  mut ambient_light: ResMut<AmbientLight>,
+  mut textures: ResMut<Assets<Texture>>,
+  mut fog_materials: ResMut<Assets<FogMaterial>>,
+  mut sun_query: Query<&mut DirectionalLight, With<Sun>>,
+  mut sky_query: Query<(&mut Transform, &mut Visible), With<SkyDome>>,
+  mut projection_query: Query<&mut PerspectiveProjection>,
+) {
+  if !environment.is_changed() {
+    return;
+  }
+
+  let daylight = environment.daylight();
+  for mut sun in sun_query.iter_mut() {
+    sun.set_direction(-environment.sun_direction());
+    sun.color = scaled_color(environment.sun_color, 1.);
+    sun.illuminance = environment.sun_intensity * daylight;
+  }
+
+  // Nights aren't pitch black
+  ambient_light.color = scaled_color(environment.ambient_color, 1.);
+  ambient_light.brightness = environment.ambient_brightness * (0.2 + 0.8 * daylight);
+  let sky_brightness = 0.1 + 0.9 * daylight;
+  clear_color.0 = scaled_color(environment.sky_color, sky_brightness);
+
+  let foggy = environment.fog_distance > 0.;
+  for (mut transform, mut visible) in sky_query.iter_mut() {
+    visible.is_visible = environment.horizon_color.is_some();
+    transform.scale = Vec3::splat(if foggy {
+      environment.fog_distance
+    } else {
+      SKY_DISTANCE
+    });
+  }
+  if let Some(horizon_color) = environment.horizon_color {
+    if let Some(texture) = textures.get_mut(&sky_texture.0) {
+      texture.data = environment.sky_texture_data(horizon_color, sky_brightness);
+    }
+  }
+  // Far away things fade into the sky where it meets the ground
+  if let Some(material) = fog_materials.get_mut(&fog.material) {
+    let color = environment.horizon_color.unwrap_or(environment.sky_color);
+    material.color = scaled_color(color, sky_brightness);
+    material.distance = environment.fog_distance;
+  }
+
+  // Nothing can be seen through the fog, so leave just a little room past it for the sky itself
+  if let Ok(mut projection) = projection_query.get_mut(player.camera) {
+    let far = if foggy {
+      environment.fog_distance * 1.1
+    } else {
+      DEFAULT_FAR
+    };
+    if projection.far != far {
+      projection.far = far;
+    }
+  }
+}
+
+/// Keeps the sky centered on the camera, so it always looks infinitely far away.
+fn follow_camera(
+  player: Res<Player>,
+  camera_query: Query<&GlobalTransform>,
+  mut sky_query: Query<&mut Transform, With<SkyDome>>,
+) {
+  let camera = match camera_query.get(player.camera) {
+    Ok(camera) => camera.translation,
+    Err(_) => return,
+  };
+  for mut transform in sky_query.iter_mut() {
+    transform.translation = camera;
+  }
+}
+
+pub struct EnvironmentPlugin;
+impl Plugin for EnvironmentPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Environment>()
+      .add_asset::<FogMaterial>()
+      .add_startup_system(init_environment.system())
+      .add_startup_system(init_fog.system())
+      .add_system(cycle_day.system())
+      .add_system(apply_environment.system())
+      .add_system(add_fog.system())
+      .add_system(follow_camera.system());
+  }
+}
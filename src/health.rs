@@ -0,0 +1,354 @@
+use crate::{
+  console::{AppConsoleExt, ConsoleCommand},
+  cvars::{AppCvarsExt, Cvar, Cvars},
+  player::{
+    controller::CharacterController,
+    movement::MovementMode,
+    spawn::{self, Player, SpawnPoint},
+  },
+  prelude::*,
+};
+use bevy::utils::HashSet;
+use bevy_rapier3d::{
+  na::{Isometry3, Vector3},
+  prelude::*,
+  rapier::{dynamics::BodyStatus, geometry::NarrowPhase},
+};
+
+pub const PLAYER_MAX_HEALTH: f32 = 100.;
+
+/// Hit points of the player or a prop. Props are despawned when it runs out, and the player
+/// respawns.
+#[derive(Clone, Copy, Debug)]
+pub struct Health {
+  pub current: f32,
+  pub max: f32,
+}
+
+impl Health {
+  pub fn new(max: f32) -> Self {
+    Health { current: max, max }
+  }
+
+  pub fn is_dead(&self) -> bool {
+    self.current <= 0.
+  }
+}
+
+/// Takes `amount` of health from an entity with `Health`.
+pub struct DamageEvent {
+  pub entity: Entity,
+  pub amount: f32,
+}
+
+/// Sent when a prop runs out of health, right before it is despawned.
+pub struct BreakEvent {
+  pub entity: Entity,
+}
+
+/// How strongly the screen flashes red after the player is hurt, from 0 to 1.
+#[derive(Default)]
+struct DamageFlash(f32);
+
+struct HealthBarTag;
+struct DamageFlashTag;
+
+/// Label of the system that despawns props that run out of health. Systems reading `BreakEvent`
+/// that need the prop's components run after it.
+pub const APPLY_DAMAGE: &str = "apply_damage";
+
+pub const IMPACT_THRESHOLD: &str = "health.impact_threshold";
+pub const IMPACT_DAMAGE: &str = "health.impact_damage";
+pub const FALL_THRESHOLD: &str = "health.fall_threshold";
+pub const FALL_DAMAGE: &str = "health.fall_damage";
+
+/// Seconds for the damage flash to fade out completely.
+const FLASH_FADE_TIME: f32 = 0.5;
+/// Damage that flashes the screen at full strength.
+const FLASH_FULL_DAMAGE: f32 = 30.;
+
+/// Damages bodies with `Health` that get hit hard enough to change their velocity by more than
+/// the impact threshold in one step. Only the step in which two colliders start touching counts,
+/// so things resting on each other don't keep hurting.
+fn impact_damage(
+  cvars: Res<Cvars>,
+  player: Res<Player>,
+  narrow_phase: Res<NarrowPhase>,
+  collider_parent_query: Query<&ColliderParent>,
+  body_query: Query<(&RigidBodyType, &RigidBodyMassProps)>,
+  health_query: Query<&Health>,
+  mut damage_events: EventWriter<DamageEvent>,
+  mut touching: Local<HashSet<(Entity, Entity)>>,
+) {
+  let body_of = |collider: Entity| {
+    collider_parent_query
+      .get(collider)
+      .map_or(collider, |parent| parent.handle.entity())
+  };
+
+  let touched = std::mem::take(&mut *touching);
+  for pair in narrow_phase.contact_pairs() {
+    if !pair.has_any_active_contact {
+      continue;
+    }
+    let colliders = (pair.collider1.entity(), pair.collider2.entity());
+    touching.insert(colliders);
+    if touched.contains(&colliders) {
+      continue;
+    }
+    let impulse = pair
+      .manifolds
+      .iter()
+      .flat_map(|manifold| manifold.points.iter())
+      .map(|point| point.data.impulse)
+      .sum::<f32>();
+    let body1 = body_of(pair.collider1.entity());
+    let body2 = body_of(pair.collider2.entity());
+
+    for (body, other) in [(body1, body2), (body2, body1)].iter().copied() {
+      if health_query.get(body).is_err() {
+        continue;
+      }
+      // Landing on the ground is handled by fall damage, so only things thrown at the player hurt
+      let other_dynamic = body_query
+        .get(other)
+        .map_or(false, |(body_type, _)| *body_type == BodyStatus::Dynamic);
+      if body == player.body && !other_dynamic {
+        continue;
+      }
+      let mass = match body_query.get(body) {
+        Ok((_, mass_props)) if mass_props.mass() > 0. => mass_props.mass(),
+        _ => continue,
+      };
+
+      let excess = impulse / mass - cvars.float(IMPACT_THRESHOLD);
+      if excess > 0. {
+        damage_events.send(DamageEvent {
+          entity: body,
+          amount: excess * cvars.float(IMPACT_DAMAGE),
+        });
+      }
+    }
+  }
+}
+
+/// Damages the player when they land faster than the fall threshold.
+fn fall_damage(
+  cvars: Res<Cvars>,
+  player: Res<Player>,
+  controller: Res<CharacterController>,
+  mut damage_events: EventWriter<DamageEvent>,
+  mut fall_speed: Local<f32>,
+) {
+  if controller.fly || controller.mode != MovementMode::Walk {
+    *fall_speed = 0.;
+    return;
+  }
+  if !controller.grounded {
+    *fall_speed = (-controller.velocity.y).max(0.);
+    return;
+  }
+
+  let excess = *fall_speed - cvars.float(FALL_THRESHOLD);
+  *fall_speed = 0.;
+  if excess > 0. {
+    damage_events.send(DamageEvent {
+      entity: player.body,
+      amount: excess * cvars.float(FALL_DAMAGE),
+    });
+  }
+}
+
+fn apply_damage(
+  mut commands: Commands,
+  player: Res<Player>,
+  mut flash: ResMut<DamageFlash>,
+  mut damage_events: EventReader<DamageEvent>,
+  mut break_events: EventWriter<BreakEvent>,
+  mut health_query: Query<&mut Health>,
+) {
+  for event in damage_events.iter() {
+    let mut health = match health_query.get_mut(event.entity) {
+      Ok(health) => health,
+      Err(_) => continue,
+    };
+    if health.is_dead() || event.amount <= 0. {
+      continue;
+    }
+    health.current = (health.current - event.amount).max(0.);
+
+    if event.entity == player.body {
+      flash.0 = (flash.0 + event.amount / FLASH_FULL_DAMAGE).min(1.);
+    } else if health.is_dead() {
+      break_events.send(BreakEvent {
+        entity: event.entity,
+      });
+      commands.entity(event.entity).despawn_recursive();
+    }
+  }
+}
+
+/// Moves the player to the next spawn point once they run out of health.
+fn respawn_player(
+  player: Res<Player>,
+  mut controller: ResMut<CharacterController>,
+  spawn_point_query: Query<&GlobalTransform, With<SpawnPoint>>,
+  mut body_query: Query<(&mut Health, &mut RigidBodyPosition, &mut RigidBodyVelocity)>,
+  mut next_spawn_point: Local<usize>,
+) {
+  let (mut health, mut position, mut velocity) = match body_query.get_mut(player.body) {
+    Ok(body) => body,
+    Err(_) => return,
+  };
+  if !health.is_dead() {
+    return;
+  }
+
+  // Take turns between the spawn points, falling back to where the player first spawned
+  let spawn_points = spawn_point_query.iter().collect::<Vec<_>>();
+  let translation = if spawn_points.is_empty() {
+    spawn::start_position(&controller)
+  } else {
+    *next_spawn_point = (*next_spawn_point + 1) % spawn_points.len();
+    spawn_points[*next_spawn_point].translation
+  };
+  info!("Respawning player at {:?}", translation);
+
+  let isometry = Isometry3::from_parts(translation.to_na_translation(), position.position.rotation);
+  position.position = isometry;
+  position.next_position = isometry;
+  velocity.linvel = Vector3::zeros();
+  velocity.angvel = Vector3::zeros();
+  controller.velocity = Vec3::ZERO;
+  *health = Health::new(health.max);
+}
+
+fn init_health_hud(mut commands: Commands, mut materials: ResMut<Assets<ColorMaterial>>) {
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+        position_type: PositionType::Absolute,
+        ..Default::default()
+      },
+      material: materials.add(Color::rgba(1.0, 0.0, 0.0, 0.0).into()),
+      ..Default::default()
+    })
+    .insert_bundle((DamageFlashTag, Name::new("damage flash")));
+
+  commands
+    .spawn_bundle(NodeBundle {
+      style: Style {
+        size: Size::new(Val::Px(200.0), Val::Px(16.0)),
+        position_type: PositionType::Absolute,
+        position: Rect {
+          left: Val::Px(20.0),
+          bottom: Val::Px(20.0),
+          ..Default::default()
+        },
+        padding: Rect::all(Val::Px(2.0)),
+        ..Default::default()
+      },
+      material: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.5).into()),
+      ..Default::default()
+    })
+    .insert(Name::new("health bar"))
+    .with_children(|parent| {
+      parent
+        .spawn_bundle(NodeBundle {
+          style: Style {
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            ..Default::default()
+          },
+          material: materials.add(Color::rgb(0.8, 0.1, 0.1).into()),
+          ..Default::default()
+        })
+        .insert(HealthBarTag);
+    });
+}
+
+fn update_health_hud(
+  time: Res<Time>,
+  player: Res<Player>,
+  mut flash: ResMut<DamageFlash>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  health_query: Query<&Health>,
+  mut bar_query: Query<&mut Style, With<HealthBarTag>>,
+  flash_query: Query<&Handle<ColorMaterial>, With<DamageFlashTag>>,
+) {
+  if let Ok(health) = health_query.get(player.body) {
+    for mut style in bar_query.iter_mut() {
+      style.size.width = Val::Percent(100. * health.current / health.max);
+    }
+  }
+
+  flash.0 = (flash.0 - time.delta_seconds() / FLASH_FADE_TIME).max(0.);
+  for handle in flash_query.iter() {
+    if let Some(material) = materials.get_mut(handle) {
+      material.color.set_a(0.5 * flash.0);
+    }
+  }
+}
+
+fn kill_command() -> ConsoleCommand {
+  ConsoleCommand::new("kill", "Kills the player, who respawns", |world, _args| {
+    let body = world.get_resource::<Player>().unwrap().body;
+    world
+      .get_resource_mut::<Events<DamageEvent>>()
+      .unwrap()
+      .send(DamageEvent {
+        entity: body,
+        amount: f32::INFINITY,
+      });
+    Ok(None)
+  })
+}
+
+pub struct HealthPlugin;
+impl Plugin for HealthPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_event::<DamageEvent>()
+      .add_event::<BreakEvent>()
+      .init_resource::<DamageFlash>()
+      .add_cvar(
+        Cvar::float(
+          IMPACT_THRESHOLD,
+          "Change in speed from a single hit, in m/s, above which it does damage",
+          8.,
+        )
+        .range(0., 1000.),
+      )
+      .add_cvar(
+        Cvar::float(
+          IMPACT_DAMAGE,
+          "Damage per m/s of an impact above the threshold",
+          5.,
+        )
+        .range(0., 1000.),
+      )
+      .add_cvar(
+        Cvar::float(
+          FALL_THRESHOLD,
+          "Landing speed, in m/s, above which falls hurt the player",
+          12.,
+        )
+        .range(0., 1000.),
+      )
+      .add_cvar(
+        Cvar::float(
+          FALL_DAMAGE,
+          "Damage per m/s of landing speed above the threshold",
+          8.,
+        )
+        .range(0., 1000.),
+      )
+      .add_startup_system(init_health_hud.system())
+      .add_system(impact_damage.system())
+      .add_system(fall_damage.system())
+      .add_system(apply_damage.system().label(APPLY_DAMAGE))
+      .add_system(respawn_player.system())
+      .add_system(update_health_hud.system())
+      .add_console_command(kill_command());
+  }
+}
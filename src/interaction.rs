@@ -0,0 +1,262 @@
+use crate::{
+  cvars::{AppCvarsExt, Cvar, Cvars},
+  player::{
+    camera::CameraMode,
+    input_map::{Action, ActionState, InputMap},
+    raycast::ViewInfo,
+    spawn::{Player, RAPIER_PLAYER_GROUP},
+  },
+  prelude::*,
+  ui::UiWindowManager,
+};
+use bevy_egui::{egui, EguiContext};
+use bevy_rapier3d::{
+  prelude::*,
+  rapier::{dynamics::BodyStatus, geometry::InteractionGroups},
+};
+
+/// Sent when the player presses use while looking at something within reach.
+pub struct UseEvent {
+  pub user: Entity,
+  pub target: Entity,
+  /// Where the view ray hit the target.
+  pub hit: Vec3,
+}
+
+/// Something the player can use, e.g. a button or a door. The prompt is shown under the crosshair
+/// while looking at it.
+pub struct Usable {
+  pub prompt: String,
+}
+
+impl Usable {
+  pub fn new(prompt: impl Into<String>) -> Self {
+    Usable {
+      prompt: prompt.into(),
+    }
+  }
+}
+
+pub const USE_DISTANCE: &str = "player.use_distance";
+
+/// Label of the system sending `UseEvent`s.
+pub const USE_SYSTEM: &str = "use_system";
+
+/// The entity the player is looking at and where, if it is close enough to use.
+pub fn use_target(view_info: &ViewInfo, cvars: &Cvars) -> Option<(Entity, Vec3)> {
+  let hit = view_info.hit.as_ref()?;
+  if hit.intersection.toi > cvars.float(USE_DISTANCE) {
+    return None;
+  }
+  Some((hit.entity, view_info.hit_point()?.to_glam_vec3()))
+}
+
+fn use_system(
+  actions: Res<ActionState>,
+  cvars: Res<Cvars>,
+  player: Res<Player>,
+  view_info: Res<ViewInfo>,
+  camera_mode: Res<CameraMode>,
+  ui_window_manager: Res<UiWindowManager>,
+  mut use_events: EventWriter<UseEvent>,
+) {
+  // The view ray comes from the body, which the player can't use things with while away from it
+  if ui_window_manager.is_showing()
+    || *camera_mode != CameraMode::Attached
+    || !actions.just_pressed(Action::Use)
+  {
+    return;
+  }
+  if let Some((target, hit)) = use_target(&view_info, &cvars) {
+    use_events.send(UseEvent {
+      user: player.body,
+      target,
+      hit,
+    });
+  }
+}
+
+fn use_prompt_system(
+  cvars: Res<Cvars>,
+  input_map: Res<InputMap>,
+  view_info: Res<ViewInfo>,
+  camera_mode: Res<CameraMode>,
+  ui_window_manager: Res<UiWindowManager>,
+  mut egui_context: ResMut<EguiContext>,
+  usable_query: Query<&Usable>,
+) {
+  if ui_window_manager.is_showing() || *camera_mode != CameraMode::Attached {
+    return;
+  }
+  let usable =
+    match use_target(&view_info, &cvars).and_then(|(target, _)| usable_query.get(target).ok()) {
+      Some(usable) => usable,
+      None => return,
+    };
+
+  let prompt = match input_map.bindings(Action::Use).next() {
+    Some(binding) => format!("[{}] {}", binding, usable.prompt),
+    None => usable.prompt.clone(),
+  };
+  egui::Area::new("use prompt")
+    .anchor(egui::Align2::CENTER_CENTER, [0., 40.])
+    .show(egui_context.ctx(), |ui| {
+      ui.label(prompt);
+    });
+}
+
+pub const CARRY_MAX_MASS: &str = "carry.max_mass";
+pub const CARRY_DISTANCE: &str = "carry.distance";
+pub const THROW_SPEED: &str = "carry.throw_speed";
+
+/// How quickly a carried object catches up with where it should be, per second.
+const CARRY_STIFFNESS: f32 = 15.;
+const CARRY_MAX_SPEED: f32 = 20.;
+/// A carried object stuck this far from where it should be is dropped.
+const CARRY_BREAK_DISTANCE: f32 = 3.;
+
+struct CarryStateInner {
+  body: Entity,
+  /// Collision groups of the body's colliders from before it was picked up.
+  collision_groups: Vec<(Entity, InteractionGroups)>,
+}
+
+/// The object the player is carrying in their hands, if any.
+#[derive(Default)]
+pub struct CarryState(Option<CarryStateInner>);
+
+impl CarryState {
+  pub fn is_carrying(&self) -> bool {
+    self.0.is_some()
+  }
+}
+
+fn carry_system(
+  time: Res<Time>,
+  actions: Res<ActionState>,
+  cvars: Res<Cvars>,
+  view_info: Res<ViewInfo>,
+  mut carry_state: ResMut<CarryState>,
+  mut use_events: EventReader<UseEvent>,
+  usable_query: Query<&Usable>,
+  mut body_query: Query<(
+    &RigidBodyType,
+    &RigidBodyMassProps,
+    &RigidBodyPosition,
+    &mut RigidBodyVelocity,
+    &mut RigidBodyActivation,
+  )>,
+  mut collider_query: Query<(Entity, Option<&ColliderParent>, &mut ColliderFlags)>,
+) {
+  let used = use_events
+    .iter()
+    .map(|event| event.target)
+    .collect::<Vec<_>>();
+
+  // Pick up light dynamic bodies that don't do anything else when used
+  if !carry_state.is_carrying() {
+    for target in used {
+      if usable_query.get(target).is_ok() {
+        continue;
+      }
+      let liftable = match body_query.get_mut(target) {
+        Ok((body_type, mass_props, ..)) => {
+          *body_type == BodyStatus::Dynamic && mass_props.mass() <= cvars.float(CARRY_MAX_MASS)
+        }
+        Err(_) => false,
+      };
+      if !liftable {
+        continue;
+      }
+
+      // The player shouldn't bump into what they are carrying
+      let collision_groups = collider_query
+        .iter_mut()
+        .filter(|(entity, parent, _)| {
+          *entity == target || parent.map_or(false, |parent| parent.handle.entity() == target)
+        })
+        .map(|(entity, _, mut flags)| {
+          let groups = flags.collision_groups;
+          flags.collision_groups = groups.with_filter(groups.filter & !RAPIER_PLAYER_GROUP);
+          (entity, groups)
+        })
+        .collect();
+      carry_state.0 = Some(CarryStateInner {
+        body: target,
+        collision_groups,
+      });
+      break;
+    }
+    return;
+  }
+
+  let inner = carry_state.0.as_ref().unwrap();
+  let throw = actions.just_pressed(Action::Grab);
+  let mut drop = throw || !actions.pressed(Action::Use);
+  match body_query.get_mut(inner.body) {
+    Ok((_, _, position, mut velocity, mut activation)) => {
+      let target = view_info.ray.point_at(cvars.float(CARRY_DISTANCE)).coords;
+      let offset = target - position.position.translation.vector;
+      if offset.norm() > CARRY_BREAK_DISTANCE {
+        drop = true;
+      }
+
+      if throw {
+        velocity.linvel = view_info.ray.dir.normalize() * cvars.float(THROW_SPEED);
+      } else if !drop {
+        // Pull it towards the hold point instead of teleporting it, so it still gets blocked by
+        // walls and pushes other objects out of the way
+        let mut linvel = offset * CARRY_STIFFNESS;
+        if linvel.norm() > CARRY_MAX_SPEED {
+          linvel = linvel.normalize() * CARRY_MAX_SPEED;
+        }
+        velocity.linvel = linvel;
+        velocity.angvel *= (1. - 5. * time.delta_seconds()).max(0.);
+      }
+      activation.wake_up(true);
+    }
+    // It was despawned
+    Err(_) => drop = true,
+  }
+
+  if drop {
+    let inner = carry_state.0.take().unwrap();
+    for (entity, groups) in inner.collision_groups {
+      if let Ok((_, _, mut flags)) = collider_query.get_mut(entity) {
+        flags.collision_groups = groups;
+      }
+    }
+  }
+}
+
+pub struct InteractionPlugin;
+impl Plugin for InteractionPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_event::<UseEvent>()
+      .init_resource::<CarryState>()
+      .add_cvar(
+        Cvar::float(USE_DISTANCE, "How far away things can be used from", 4.).range(0., 100.),
+      )
+      .add_cvar(
+        Cvar::float(
+          CARRY_MAX_MASS,
+          "Heaviest object that can be picked up with the use key",
+          20.,
+        )
+        .range(0., 10000.),
+      )
+      .add_cvar(
+        Cvar::float(
+          CARRY_DISTANCE,
+          "How far in front of the camera carried objects are held",
+          3.,
+        )
+        .range(0., 20.),
+      )
+      .add_cvar(Cvar::float(THROW_SPEED, "Speed of thrown objects", 15.).range(0., 100.))
+      .add_system(use_system.system().label(USE_SYSTEM))
+      .add_system(use_prompt_system.system())
+      .add_system(carry_system.system());
+  }
+}
@@ -1,17 +1,25 @@
 #![allow(warnings)]
 
+pub mod console;
+pub mod cvars;
+pub mod environment;
+pub mod health;
+pub mod interaction;
 pub mod map;
 pub mod math;
 pub mod models;
 pub mod physics;
 pub mod player;
 pub mod prelude;
+pub mod save;
 pub mod scripts;
 pub mod serde;
 pub mod shaders;
+pub mod terrain;
 pub mod tools;
 pub mod ui;
 pub mod utils;
+pub mod vehicles;
 
 /* TODOS
 - web support [sub todo: figure out mouse capture]
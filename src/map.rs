@@ -1,98 +1,621 @@
-use crate::{models::*, physics::ColliderParams, prelude::*};
+use crate::{
+  console::{AppConsoleExt, ArgKind, ConsoleCommand},
+  environment::Environment,
+  models::*,
+  physics::ColliderParams,
+  player::{
+    controller::CharacterController,
+    movement::MovementVolume,
+    spawn::{self, Player, SpawnPoint},
+  },
+  prelude::*,
+  save::print,
+  scripts::pymod::ScriptOutputEvent,
+  serde::RawData,
+  terrain::{spawn_terrain, Terrain},
+  tools::Frozen,
+};
+use anyhow::{anyhow, bail};
+use bevy::asset::LoadState;
 use bevy_rapier3d::{
-  na::{Isometry3, Translation3, UnitQuaternion, Vector3},
+  na::{Isometry3, Vector3},
+  physics::{JointBuilderComponent, JointHandleComponent},
+  prelude::*,
   rapier::dynamics::BodyStatus,
 };
+use serde::{Deserialize, Serialize};
 
-fn init_map(
-  mut commands: Commands,
-  mut meshes: ResMut<Assets<Mesh>>,
-  mut materials: ResMut<Assets<StandardMaterial>>,
-  mut spawn_model_events: ResMut<Events<SpawnModelEvent>>,
-  model_query: Query<(Entity, &ModelInfo), With<ModelParams>>,
-  mut done: Local<bool>,
-) {
-  if *done {
-    return;
+const MAPS_DIR: &str = "maps";
+pub const DEFAULT_MAP: &str = "default";
+
+fn white() -> [f32; 3] {
+  [1., 1., 1.]
+}
+
+fn gravity_default() -> Vec3 {
+  Vec3::new(0., -9.81, 0.)
+}
+
+fn intensity_default() -> f32 {
+  200.
+}
+
+fn range_default() -> f32 {
+  20.
+}
+
+fn dynamic() -> BodyStatus {
+  BodyStatus::Dynamic
+}
+
+/// Rotation from angles in degrees around the X, Y and Z axes, applied in that order.
+pub fn euler_rotation(degrees: Vec3) -> Quat {
+  Quat::from_rotation_z(degrees.z.to_radians())
+    * Quat::from_rotation_y(degrees.y.to_radians())
+    * Quat::from_rotation_x(degrees.x.to_radians())
+}
+
+/// Inverse of `euler_rotation`.
+pub fn euler_degrees(rotation: Quat) -> Vec3 {
+  let (x, y, z) = rotation.to_na_unit_quat().euler_angles();
+  Vec3::new(x.to_degrees(), y.to_degrees(), z.to_degrees())
+}
+
+/// A static box, e.g. the ground.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MapBox {
+  pub position: Vec3,
+  pub size: Vec3,
+  /// Degrees around the X, Y and Z axes.
+  #[serde(default)]
+  pub rotation: Vec3,
+  #[serde(default = "white")]
+  pub color: [f32; 3],
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MapLight {
+  pub position: Vec3,
+  #[serde(default = "white")]
+  pub color: [f32; 3],
+  #[serde(default = "intensity_default")]
+  pub intensity: f32,
+  #[serde(default = "range_default")]
+  pub range: f32,
+}
+
+/// An instance of a model, static for map geometry or dynamic for props to play with.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MapProp {
+  /// Name of the model, see `ModelInfo::name`.
+  pub model: String,
+  pub position: Vec3,
+  /// Degrees around the X, Y and Z axes.
+  #[serde(default)]
+  pub rotation: Vec3,
+  #[serde(default = "dynamic")]
+  pub body_status: BodyStatus,
+}
+
+/// A map as stored in `assets/maps/<name>.map.json`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MapFile {
+  /// Asset paths of the models to load, e.g. `models/Duck/Duck.gltf#Scene0`.
+  #[serde(default)]
+  pub models: Vec<String>,
+  #[serde(default)]
+  pub boxes: Vec<MapBox>,
+  /// Ground generated from noise, see `Terrain`.
+  #[serde(default)]
+  pub terrain: Option<Terrain>,
+  #[serde(default)]
+  pub lights: Vec<MapLight>,
+  /// Sun, ambient light and sky, see `Environment`.
+  #[serde(default)]
+  pub environment: Environment,
+  #[serde(default = "gravity_default")]
+  pub gravity: Vec3,
+  #[serde(default)]
+  pub spawn_points: Vec<Vec3>,
+  #[serde(default)]
+  pub props: Vec<MapProp>,
+}
+
+impl MapFile {
+  /// Checks what can't be caught while parsing, so broken maps are reported instead of loaded.
+  pub fn validate(&self) -> anyhow::Result<()> {
+    if let Some(terrain) = &self.terrain {
+      terrain.validate()?;
+    }
+    // Props would wait forever for a model that never loads
+    for prop in self.props.iter() {
+      if !self
+        .models
+        .iter()
+        .any(|path| ModelInfo::name_from_path(path) == prop.model)
+      {
+        bail!("prop model {} isn't one of the map's models", prop.model);
+      }
+    }
+    Ok(())
   }
+}
 
-  let model = match model_query
-    .iter()
-    .find(|(_, info)| info.name == "WebsiteTerrain")
+/// Belongs to the loaded map, and is despawned when another one is loaded.
+pub struct MapEntity;
+
+/// A box of the map, along with the color it was built with so it can be saved again.
+pub struct MapBoxColor(pub [f32; 3]);
+
+/// Name of the loaded map.
+#[derive(Default)]
+pub struct CurrentMap(pub String);
+
+pub struct LoadMapEvent {
+  pub name: String,
+}
+
+/// Writes the loaded map, including any changes made to it since, to `assets/maps/<name>.map.json`.
+pub struct SaveMapEvent {
+  pub name: String,
+}
+
+/// Map file that is still loading, dropped along with the map entity if another map is loaded
+/// first.
+struct PendingMap {
+  name: String,
+  handle: Handle<RawData>,
+}
+
+/// Props of the map whose model hasn't loaded yet.
+struct PendingProps(Vec<MapProp>);
+
+pub fn map_path(name: &str) -> anyhow::Result<String> {
+  if name.is_empty() || name.contains(|c| c == '/' || c == '\\') || name.starts_with('.') {
+    bail!("'{}' is not a valid map name", name);
+  }
+  Ok(format!("{}/{}.map.json", MAPS_DIR, name))
+}
+
+fn write_map(name: &str, map_file: &MapFile) -> anyhow::Result<()> {
+  let path = map_path(name)?;
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    std::fs::create_dir_all(format!("assets/{}", MAPS_DIR))?;
+    std::fs::write(
+      format!("assets/{}", path),
+      serde_json::to_string_pretty(map_file)?,
+    )?;
+    return Ok(());
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  bail!("saving maps isn't supported on the web");
+}
+
+pub fn spawn_map_box(
+  commands: &mut Commands,
+  meshes: &mut Assets<Mesh>,
+  materials: &mut Assets<StandardMaterial>,
+  map_box: &MapBox,
+) -> Entity {
+  let [r, g, b] = map_box.color;
+  commands
+    .spawn_bundle(PbrBundle {
+      mesh: meshes.add(Mesh::from(shape::Cube { size: 2.0 })),
+      transform: Transform {
+        translation: map_box.position,
+        rotation: euler_rotation(map_box.rotation),
+        scale: map_box.size * 0.5,
+      },
+      material: materials.add(Color::rgb(r, g, b).into()),
+      ..Default::default()
+    })
+    .insert_bundle((
+      ColliderParams {
+        body_status: BodyStatus::Static,
+        mass: 10000.0,
+      },
+      MapBoxColor(map_box.color),
+      MapEntity,
+      Name::new("box"),
+    ))
+    .id()
+}
+
+pub fn spawn_map_light(commands: &mut Commands, light: &MapLight) -> Entity {
+  let [r, g, b] = light.color;
+  commands
+    .spawn_bundle(PointLightBundle {
+      point_light: PointLight {
+        color: Color::rgb(r, g, b),
+        intensity: light.intensity,
+        range: light.range,
+        ..Default::default()
+      },
+      transform: Transform::from_translation(light.position),
+      ..Default::default()
+    })
+    .insert_bundle((MapEntity, Name::new("light")))
+    .id()
+}
+
+pub fn spawn_spawn_point(commands: &mut Commands, position: Vec3) -> Entity {
+  commands
+    .spawn_bundle((
+      SpawnPoint,
+      Transform::from_translation(position),
+      GlobalTransform::from_translation(position),
+      MapEntity,
+      Name::new("spawn point"),
+    ))
+    .id()
+}
+
+/// Names of the maps in the assets directory.
+fn map_names(_world: &mut World) -> Vec<String> {
+  #[cfg(not(target_arch = "wasm32"))]
   {
-    Some((model, _)) => model,
-    None => {
+    let mut names = std::fs::read_dir(format!("assets/{}", MAPS_DIR))
+      .map(|entries| {
+        entries
+          .filter_map(|entry| entry.ok())
+          .filter_map(|entry| entry.file_name().into_string().ok())
+          .filter_map(|name| name.strip_suffix(".map.json").map(|name| name.to_owned()))
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_default();
+    names.sort();
+    names
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  vec![DEFAULT_MAP.to_owned()]
+}
+
+fn load_default_map(mut events: EventWriter<LoadMapEvent>) {
+  events.send(LoadMapEvent {
+    name: DEFAULT_MAP.to_owned(),
+  });
+}
+
+/// Despawns the current map along with everything placed in it, and starts loading the next one.
+fn load_map(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  player: Res<Player>,
+  mut current_map: ResMut<CurrentMap>,
+  mut events: EventReader<LoadMapEvent>,
+  unload_query: Query<
+    Entity,
+    Or<(
+      With<MapEntity>,
+      With<MovementVolume>,
+      With<ModelInstance>,
+      With<JointBuilderComponent>,
+      With<JointHandleComponent>,
+    )>,
+  >,
+  body_query: Query<Entity, (With<RigidBodyType>, Without<Parent>)>,
+) {
+  let name = match events.iter().last() {
+    Some(event) => event.name.clone(),
+    None => return,
+  };
+  let path = match map_path(&name) {
+    Ok(path) => path,
+    Err(e) => {
+      warn!("{}", e);
       return;
     }
   };
+  info!("Loading map {}", name);
 
-  info!("Initializing map");
-  *done = true;
-
-  let lights = vec![PointLightBundle {
-    transform: Transform::from_translation(Vec3::new(4.0, 5.0, 4.0)),
-    ..Default::default()
-  }];
-  for (i, light) in lights.into_iter().enumerate() {
-    commands
-      .spawn_bundle(light)
-      .insert(Name::new(format!("light {}", i)));
+  // Props, volumes, vehicle parts and debris aren't part of the map, but go away with it too
+  let mut unload = unload_query
+    .iter()
+    .chain(body_query.iter())
+    .filter(|entity| *entity != player.body)
+    .collect::<Vec<_>>();
+  unload.sort();
+  unload.dedup();
+  for entity in unload {
+    commands.entity(entity).despawn_recursive();
   }
 
-  let ground_size = 200.1;
-  let ground_height = 1.0;
-  let extents = Vec3::new(0.5 * ground_size, 0.5 * ground_height, 0.5 * ground_size);
-  let cube = meshes.add(Mesh::from(shape::Cube { size: 2.0 }));
-  let color = Color::rgb(
-    0xF3 as f32 / 255.0,
-    0xD9 as f32 / 255.0,
-    0xB1 as f32 / 255.0,
-  );
-  let ground = PbrBundle {
-    mesh: cube.clone(),
-    transform: Transform::from_scale(extents),
-    material: materials.add(color.into()),
-    ..Default::default()
-  };
-  commands.spawn_bundle(ground).insert_bundle((
-    ColliderParams {
-      body_status: BodyStatus::Static,
-      mass: 10000.0,
+  commands.spawn_bundle((
+    MapEntity,
+    Name::new(format!("map {}", name)),
+    PendingMap {
+      name: name.clone(),
+      handle: asset_server.load(path.as_str()),
     },
-    Name::new("ground"),
   ));
+  current_map.0 = name;
+}
 
-  let position = Isometry3::from_parts(
-    Translation3::from(Vector3::new(0., 0., 0.)),
-    UnitQuaternion::identity(),
-  );
-  spawn_model_events.send(SpawnModelEvent {
-    model,
-    position,
-    body_status: BodyStatus::Static,
-  });
+/// Parses map files once they have loaded, reporting the ones that are broken or missing.
+fn parse_map(
+  mut commands: Commands,
+  asset_server: Res<AssetServer>,
+  raw_data: Res<Assets<RawData>>,
+  mut output_events: ResMut<Events<ScriptOutputEvent>>,
+  map_query: Query<(Entity, &PendingMap)>,
+) {
+  for (map, pending) in map_query.iter() {
+    let data = match raw_data.get(&pending.handle) {
+      Some(data) => data,
+      None => {
+        if asset_server.get_load_state(&pending.handle) == LoadState::Failed {
+          commands.entity(map).remove::<PendingMap>();
+          print(
+            &mut output_events,
+            Err(anyhow!("map {} couldn't be loaded", pending.name)),
+          );
+        }
+        continue;
+      }
+    };
+    let mut map_commands = commands.entity(map);
+    map_commands.remove::<PendingMap>();
+    let map_file = serde_json::from_slice::<MapFile>(data.bytes())
+      .map_err(anyhow::Error::from)
+      .and_then(|map_file| map_file.validate().map(|_| map_file));
+    match map_file {
+      Ok(map_file) => {
+        map_commands.insert(map_file);
+      }
+      Err(e) => print(
+        &mut output_events,
+        Err(anyhow!("map {} is invalid: {}", pending.name, e)),
+      ),
+    }
+  }
 }
 
-fn load_map_assets(mut events: ResMut<Events<LoadModelEvent>>) {
-  let model_paths = vec![
-    "models/monkey/Monkey.gltf#Scene0",
-    //"models/car/car.gltf#Scene0",
-    "models/FlightHelmet/FlightHelmet.gltf#Scene0",
-    "models/Duck/Duck.gltf#Scene0",
-    "models/website-terrain/WebsiteTerrain.gltf#Scene0",
-  ];
+/// Builds the map once its file has loaded.
+fn spawn_map(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+  mut environment: ResMut<Environment>,
+  mut rapier_config: ResMut<RapierConfiguration>,
+  mut load_model_events: EventWriter<LoadModelEvent>,
+  player: Res<Player>,
+  controller: Res<CharacterController>,
+  map_query: Query<(Entity, &MapFile), Added<MapFile>>,
+  model_query: Query<&ModelInfo>,
+  mut body_query: Query<(&mut RigidBodyPosition, &mut RigidBodyVelocity)>,
+) {
+  for (map, map_file) in map_query.iter() {
+    for path in map_file.models.iter() {
+      if !model_query
+        .iter()
+        .any(|model_info| model_info.path == *path)
+      {
+        load_model_events.send(LoadModelEvent { path: path.clone() });
+      }
+    }
 
-  for path in model_paths {
-    let path = path.to_string();
-    events.send(LoadModelEvent { path });
+    for map_box in map_file.boxes.iter() {
+      spawn_map_box(&mut commands, &mut meshes, &mut materials, map_box);
+    }
+    if let Some(terrain) = &map_file.terrain {
+      spawn_terrain(&mut commands, terrain);
+    }
+    for light in map_file.lights.iter() {
+      spawn_map_light(&mut commands, light);
+    }
+    for position in map_file.spawn_points.iter() {
+      spawn_spawn_point(&mut commands, *position);
+    }
+
+    *environment = map_file.environment.clone();
+    rapier_config.gravity = map_file.gravity.to_na_vector3();
+
+    // Start the player out at the first spawn point
+    let start = map_file
+      .spawn_points
+      .first()
+      .copied()
+      .unwrap_or_else(|| spawn::start_position(&controller));
+    if let Ok((mut position, mut velocity)) = body_query.get_mut(player.body) {
+      let isometry = Isometry3::from_parts(start.to_na_translation(), position.position.rotation);
+      position.position = isometry;
+      position.next_position = isometry;
+      velocity.linvel = Vector3::zeros();
+    }
+
+    commands
+      .entity(map)
+      .insert(PendingProps(map_file.props.clone()));
+  }
+}
+
+/// Spawns the props of the map as their models finish loading.
+fn spawn_map_props(
+  mut commands: Commands,
+  mut map_query: Query<(Entity, &mut PendingProps)>,
+  model_query: Query<(Entity, &ModelInfo, &ModelParams, &Handle<Scene>)>,
+) {
+  for (map, mut pending) in map_query.iter_mut() {
+    pending.0.retain(|prop| {
+      let model = model_query
+        .iter()
+        .find(|(_, model_info, _, _)| model_info.name == prop.model);
+      let (model, model_info, params, scene_handle) = match model {
+        Some(model) => model,
+        None => return true,
+      };
+      let position = Isometry3::from_parts(
+        prop.position.to_na_translation(),
+        euler_rotation(prop.rotation).to_na_unit_quat(),
+      );
+      let entity = spawn_model(
+        &mut commands,
+        model,
+        (model_info, params, scene_handle),
+        &position,
+        prop.body_status,
+      );
+      commands.entity(entity).insert(MapEntity);
+      false
+    });
+
+    if pending.0.is_empty() {
+      commands.entity(map).remove::<PendingProps>();
+    }
   }
 }
 
+fn color_array(color: Color) -> [f32; 3] {
+  [color.r(), color.g(), color.b()]
+}
+
+/// Collects the map back into a `MapFile`. Props are saved if they came with the map or are static,
+/// so ones placed with the editor are kept but ones the player froze aren't.
+fn save_map(
+  mut events: EventReader<SaveMapEvent>,
+  mut output_events: ResMut<Events<ScriptOutputEvent>>,
+  environment: Res<Environment>,
+  rapier_config: Res<RapierConfiguration>,
+  map_query: Query<&MapFile>,
+  box_query: Query<(&Transform, &MapBoxColor)>,
+  terrain_query: Query<(&Transform, &Terrain)>,
+  light_query: Query<(&Transform, &PointLight), With<MapEntity>>,
+  spawn_point_query: Query<&Transform, With<SpawnPoint>>,
+  prop_query: Query<(
+    &ModelInstance,
+    &RigidBodyPosition,
+    &RigidBodyType,
+    Option<&MapEntity>,
+    Option<&Frozen>,
+  )>,
+  model_query: Query<&ModelInfo>,
+) {
+  for SaveMapEvent { name } in events.iter() {
+    let mut models = map_query
+      .iter()
+      .next()
+      .map(|map_file| map_file.models.clone())
+      .unwrap_or_default();
+
+    let props = prop_query
+      .iter()
+      .filter(|(_, _, body_status, map_entity, frozen)| {
+        map_entity.is_some() || (**body_status == BodyStatus::Static && frozen.is_none())
+      })
+      .filter_map(|(instance, position, body_status, _, _)| {
+        let model_info = model_query.get(instance.0).ok()?;
+        if !models.contains(&model_info.path) {
+          models.push(model_info.path.clone());
+        }
+        Some(MapProp {
+          model: model_info.name.clone(),
+          position: position.position.translation.vector.to_glam_vec3(),
+          rotation: euler_degrees(position.position.rotation.to_glam_quat()),
+          body_status: *body_status,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let map_file = MapFile {
+      models,
+      boxes: box_query
+        .iter()
+        .map(|(transform, color)| MapBox {
+          position: transform.translation,
+          size: transform.scale * 2.,
+          rotation: euler_degrees(transform.rotation),
+          color: color.0,
+        })
+        .collect(),
+      terrain: terrain_query
+        .iter()
+        .next()
+        .map(|(transform, terrain)| Terrain {
+          position: transform.translation,
+          ..terrain.clone()
+        }),
+      lights: light_query
+        .iter()
+        .map(|(transform, light)| MapLight {
+          position: transform.translation,
+          color: color_array(light.color),
+          intensity: light.intensity,
+          range: light.range,
+        })
+        .collect(),
+      environment: environment.clone(),
+      gravity: rapier_config.gravity.to_glam_vec3(),
+      spawn_points: spawn_point_query
+        .iter()
+        .map(|transform| transform.translation)
+        .collect(),
+      props,
+    };
+    let result = write_map(name, &map_file).map(|_| format!("saved map {}", name));
+    print(&mut output_events, result);
+  }
+}
+
+fn map_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "map",
+    "Unloads the current map and everything in it, and loads another one",
+    |world, args| {
+      let name = args.text(0).unwrap().to_owned();
+      let path = map_path(&name)?;
+      #[cfg(not(target_arch = "wasm32"))]
+      {
+        if !std::path::Path::new("assets").join(&path).exists() {
+          bail!("there is no map named {}", name);
+        }
+      }
+      world
+        .get_resource_mut::<Events<LoadMapEvent>>()
+        .unwrap()
+        .send(LoadMapEvent { name });
+      Ok(None)
+    },
+  )
+  .arg("name", ArgKind::Choice(map_names))
+}
+
+fn save_map_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "savemap",
+    "Saves the current map, or saves it under another name",
+    |world, args| {
+      let name = match args.text(0) {
+        Some(name) => name.to_owned(),
+        None => world.get_resource::<CurrentMap>().unwrap().0.clone(),
+      };
+      map_path(&name)?;
+      world
+        .get_resource_mut::<Events<SaveMapEvent>>()
+        .unwrap()
+        .send(SaveMapEvent { name });
+      Ok(None)
+    },
+  )
+  .optional_arg("name", ArgKind::Choice(map_names))
+}
+
 pub struct MapPlugin;
 impl Plugin for MapPlugin {
   fn build(&self, app: &mut App) {
     app
-      .add_startup_system(load_map_assets.system())
-      .add_system(init_map.system());
+      .add_event::<LoadMapEvent>()
+      .add_event::<SaveMapEvent>()
+      .init_resource::<CurrentMap>()
+      .add_startup_system(load_default_map.system())
+      .add_system(load_map.system())
+      .add_system(parse_map.system())
+      .add_system(spawn_map.system())
+      .add_system(spawn_map_props.system())
+      .add_system(save_map.system())
+      .add_console_command(map_command())
+      .add_console_command(save_map_command());
   }
 }
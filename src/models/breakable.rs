@@ -0,0 +1,133 @@
+use crate::{
+  health::BreakEvent,
+  physics::{ColliderChildren, NORMAL_ATTRIBUTE, POSITION_ATTRIBUTE},
+  prelude::*,
+};
+use bevy::render::{mesh::Indices, pipeline::PrimitiveTopology};
+use bevy_rapier3d::{
+  na::{Isometry3, Point3, Vector3},
+  prelude::*,
+  rapier::dynamics::BodyStatus,
+};
+
+/// A prop that shatters into the convex pieces of its collider when it runs out of health, see
+/// `ModelParams::breakable`.
+pub struct Breakable;
+
+/// Flat shaded mesh of a convex hull.
+fn hull_mesh(vertices: &[Point3<f32>], indices: &[[u32; 3]]) -> Mesh {
+  let mut positions = Vec::with_capacity(indices.len() * 3);
+  let mut normals = Vec::with_capacity(indices.len() * 3);
+  for triangle in indices {
+    let a = vertices[triangle[0] as usize];
+    let b = vertices[triangle[1] as usize];
+    let c = vertices[triangle[2] as usize];
+    let normal = (b - a)
+      .cross(&(c - a))
+      .try_normalize(1E-12)
+      .unwrap_or_else(Vector3::y);
+    for point in [a, b, c].iter() {
+      positions.push([point.x, point.y, point.z]);
+      normals.push([normal.x, normal.y, normal.z]);
+    }
+  }
+  let uvs = vec![[0., 0.]; positions.len()];
+  let indices = (0..positions.len() as u32).collect();
+
+  let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+  mesh.set_attribute(POSITION_ATTRIBUTE, positions);
+  mesh.set_attribute(NORMAL_ATTRIBUTE, normals);
+  mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+  mesh.set_indices(Some(Indices::U32(indices)));
+  mesh
+}
+
+/// Replaces broken props with a rigid body for each convex hull of their colliders. Runs before
+/// the despawn of the prop is applied, so its components are still around.
+pub fn shatter(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut break_events: EventReader<BreakEvent>,
+  prop_query: Query<
+    (
+      Option<&ColliderChildren>,
+      &RigidBodyVelocity,
+      &RigidBodyMassProps,
+      Option<&Name>,
+    ),
+    With<Breakable>,
+  >,
+  collider_query: Query<(
+    &ColliderShape,
+    &ColliderPosition,
+    Option<&Handle<StandardMaterial>>,
+  )>,
+) {
+  for event in break_events.iter() {
+    let (children, velocity, mass_props, name) = match prop_query.get(event.entity) {
+      Ok(prop) => prop,
+      Err(_) => continue,
+    };
+    let name = name.map_or("prop", |name| name.as_str());
+    let colliders = children.map_or_else(|| vec![event.entity], |children| children.0.clone());
+
+    let mut pieces = vec![];
+    for collider in colliders {
+      let (shape, collider_position, material) = match collider_query.get(collider) {
+        Ok(collider) => collider,
+        Err(_) => continue,
+      };
+      let compound = match shape.as_compound() {
+        Some(compound) => compound,
+        None => continue,
+      };
+      for (offset, piece) in compound.shapes() {
+        if piece.as_convex_polyhedron().is_some() {
+          pieces.push((piece, collider_position.0 * offset, material));
+        }
+      }
+    }
+
+    // The pieces split the mass of the prop between them by volume
+    let volume = pieces
+      .iter()
+      .map(|(piece, _, _)| piece.mass_properties(1.0).mass())
+      .sum::<f32>();
+    let density = if volume > 0. && mass_props.mass() > 0. {
+      mass_props.mass() / volume
+    } else {
+      1.0
+    };
+
+    for (piece, position, material) in pieces {
+      let (vertices, indices) = piece.as_convex_polyhedron().unwrap().to_trimesh();
+
+      // Keep moving the way this part of the prop was moving
+      let arm = position.translation.vector - mass_props.world_com.coords;
+      let piece_velocity = RigidBodyVelocity {
+        linvel: velocity.linvel + velocity.angvel.cross(&arm),
+        angvel: velocity.angvel,
+      };
+
+      commands
+        .spawn_bundle(PbrBundle {
+          mesh: meshes.add(hull_mesh(&vertices, &indices)),
+          material: material.cloned().unwrap_or_default(),
+          ..Default::default()
+        })
+        .insert_bundle(RigidBodyBundle {
+          body_type: BodyStatus::Dynamic,
+          position: position.into(),
+          velocity: piece_velocity,
+          ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+          shape: piece.clone(),
+          mass_properties: ColliderMassProps::Density(density),
+          ..Default::default()
+        })
+        .insert(RigidBodyPositionSync::Discrete)
+        .insert(Name::new(format!("{} piece", name)));
+    }
+  }
+}
@@ -1,12 +1,19 @@
 use crate::{
+  console::{AppConsoleExt, ArgKind, ConsoleCommand},
+  health::{Health, APPLY_DAMAGE},
   physics::{ColliderParams, SceneDecomposition},
+  player::raycast::ViewInfo,
   prelude::*,
   serde::{JsonLoader, RmpLoader},
 };
-use bevy_rapier3d::{na::Isometry3, rapier::dynamics::BodyStatus};
+use bevy_rapier3d::{
+  na::{Isometry3, Translation3, UnitQuaternion, Vector3},
+  rapier::dynamics::BodyStatus,
+};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+pub mod breakable;
 // mod decomposition;
 pub mod mesh_wrapper;
 mod thumbnail;
@@ -22,12 +29,22 @@ fn mass_default() -> f32 {
   1.
 }
 
+fn strength_default() -> f32 {
+  50.
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ModelParams {
   #[serde(default = "scale_default")]
   pub scale: Vec3,
   #[serde(default = "mass_default")]
   pub mass: f32,
+  /// Whether the model shatters into its convex pieces once it takes `strength` damage from
+  /// impacts.
+  #[serde(default)]
+  pub breakable: bool,
+  #[serde(default = "strength_default")]
+  pub strength: f32,
 }
 
 impl Default for ModelParams {
@@ -35,6 +52,8 @@ impl Default for ModelParams {
     ModelParams {
       scale: scale_default(),
       mass: mass_default(),
+      breakable: false,
+      strength: strength_default(),
     }
   }
 }
@@ -48,6 +67,14 @@ pub struct ModelInfo {
 }
 
 impl ModelInfo {
+  /// The name a model loaded from `path` goes by, e.g. `Duck` for
+  /// `models/Duck/Duck.gltf#Scene0`.
+  pub fn name_from_path(path: &str) -> String {
+    Path::new(path)
+      .file_stem()
+      .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned())
+  }
+
   fn dir(&self) -> &Path {
     Path::new(&self.path).parent().unwrap()
   }
@@ -87,12 +114,7 @@ fn listen_for_load_models(
   // let io = asset_server.io();
   for LoadModelEvent { path } in event_reader.iter() {
     let path = path.to_string();
-    let name = Path::new(&path)
-      .file_stem()
-      .unwrap()
-      .to_str()
-      .unwrap()
-      .to_string();
+    let name = ModelInfo::name_from_path(&path);
 
     let scene: Handle<Scene> = asset_server.load(path.as_str());
     let model_info = ModelInfo { name, path };
@@ -127,6 +149,42 @@ pub struct SpawnModelEvent {
   pub body_status: BodyStatus,
 }
 
+/// Spawns an instance of `model`, returning the new entity.
+pub fn spawn_model(
+  commands: &mut Commands,
+  model: Entity,
+  (model_info, params, scene_handle): (&ModelInfo, &ModelParams, &Handle<Scene>),
+  position: &Isometry3<f32>,
+  body_status: BodyStatus,
+) -> Entity {
+  info!("spawning {:?}", model_info.name);
+  let entity = commands
+    .spawn_bundle((
+      Transform::from_matrix(Mat4::from_scale_rotation_translation(
+        params.scale,
+        position.rotation.to_glam_quat(),
+        position.translation.vector.to_glam_vec3(),
+      )),
+      GlobalTransform::identity(),
+      ColliderParams {
+        body_status,
+        mass: params.mass,
+      },
+      ModelInstance(model),
+      Name::new(model_info.name.clone()),
+    ))
+    .with_children(|parent| {
+      parent.spawn_scene(scene_handle.clone());
+    })
+    .id();
+  if params.breakable && body_status == BodyStatus::Dynamic {
+    commands
+      .entity(entity)
+      .insert_bundle((Health::new(params.strength), breakable::Breakable));
+  }
+  entity
+}
+
 fn listen_for_spawn_models(
   mut commands: Commands,
   mut event_reader: EventReader<SpawnModelEvent>,
@@ -138,29 +196,76 @@ fn listen_for_spawn_models(
       position,
       body_status,
     } = &event;
-    let (model_info, params, scene_handle) = query.get(*model).unwrap();
-    info!("spawning {:?}", model_info.name);
-    commands
-      .spawn_bundle((
-        Transform::from_matrix(Mat4::from_scale_rotation_translation(
-          params.scale,
-          position.rotation.to_glam_quat(),
-          position.translation.vector.to_glam_vec3(),
-        )),
-        GlobalTransform::identity(),
-        ColliderParams {
-          body_status: *body_status,
-          mass: params.mass,
-        },
-        ModelInstance(*model),
-        Name::new(model_info.name.clone()),
-      ))
-      .with_children(|parent| {
-        parent.spawn_scene(scene_handle.clone());
-      });
+    spawn_model(
+      &mut commands,
+      *model,
+      query.get(*model).unwrap(),
+      position,
+      *body_status,
+    );
   }
 }
 
+/// Names of the models that have finished loading and can be spawned.
+pub fn model_names(world: &mut World) -> Vec<String> {
+  let mut names = world
+    .query_filtered::<&ModelInfo, With<ModelParams>>()
+    .iter(world)
+    .map(|model_info| model_info.name.clone())
+    .collect::<Vec<_>>();
+  names.sort();
+  names
+}
+
+/// Most copies `/spawn` makes at once, since each is a full physics body.
+const MAX_SPAWN_COUNT: usize = 100;
+
+fn spawn_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "spawn",
+    "Spawns models where you are looking",
+    |world, args| {
+      let name = args.text(0).unwrap();
+      let requested = args.int(1).unwrap_or(1).max(0) as usize;
+      let count = requested.min(MAX_SPAWN_COUNT);
+      let model = world
+        .query_filtered::<(Entity, &ModelInfo), With<ModelParams>>()
+        .iter(world)
+        .find(|(_, model_info)| model_info.name.eq_ignore_ascii_case(name))
+        .map(|(model, _)| model)
+        .ok_or_else(|| anyhow::anyhow!("no model named {}", name))?;
+
+      // Stack copies on top of each other so they don't spawn inside one another
+      let half_height = 5.;
+      let view_info = world.get_resource::<ViewInfo>().unwrap();
+      let base = view_info
+        .hit_point()
+        .unwrap_or_else(|| view_info.ray.point_at(half_height));
+      let mut events = world.get_resource_mut::<Events<SpawnModelEvent>>().unwrap();
+      for i in 0..count {
+        let translation = base + Vector3::new(0., half_height * (2 * i + 1) as f32, 0.);
+        events.send(SpawnModelEvent {
+          model,
+          position: Isometry3::from_parts(
+            Translation3::from(translation.coords),
+            UnitQuaternion::identity(),
+          ),
+          body_status: BodyStatus::Dynamic,
+        });
+      }
+      if count < requested {
+        return Ok(Some(format!(
+          "spawned {} {}, the most /spawn does at once",
+          count, name
+        )));
+      }
+      Ok(Some(format!("spawned {} {}", count, name)))
+    },
+  )
+  .arg("model", ArgKind::Choice(model_names))
+  .optional_arg("count", ArgKind::Int)
+}
+
 pub struct ModelsPlugin;
 impl Plugin for ModelsPlugin {
   fn build(&self, app: &mut App) {
@@ -172,6 +277,8 @@ impl Plugin for ModelsPlugin {
       .add_system(thumbnail::load_thumbnail.system())
       // .add_system(decomposition::load_decomp.system())
       .add_system(listen_for_spawn_models.system())
-      .add_system(listen_for_load_models.system());
+      .add_system(breakable::shatter.system().after(APPLY_DAMAGE))
+      .add_system(listen_for_load_models.system())
+      .add_console_command(spawn_command());
   }
 }
@@ -1,4 +1,5 @@
 use crate::{
+  console::{AppConsoleExt, ArgKind, ConsoleCommand},
   models::{mesh_wrapper::MeshWrapper, ModelInstance},
   prelude::*,
   utils,
@@ -187,11 +188,55 @@ impl AABBExt for AABB {
   }
 }
 
+fn timescale_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "timescale",
+    "Speeds up or slows down physics, e.g. 0.5 for half speed",
+    |world, args| {
+      let scale = args.float(0).unwrap();
+      if scale <= 0. {
+        anyhow::bail!("the time scale must be positive");
+      }
+      let mut integration_parameters = world.get_resource_mut::<IntegrationParameters>().unwrap();
+      integration_parameters.dt = IntegrationParameters::default().dt * scale;
+      Ok(Some(format!("timescale {}", scale)))
+    },
+  )
+  .arg("scale", ArgKind::Float)
+}
+
+fn gravity_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "gravity",
+    "Sets the direction and strength of gravity",
+    |world, args| {
+      let gravity = Vector3::new(
+        args.float(0).unwrap(),
+        args.float(1).unwrap(),
+        args.float(2).unwrap(),
+      );
+      world
+        .get_resource_mut::<RapierConfiguration>()
+        .unwrap()
+        .gravity = gravity;
+      Ok(Some(format!(
+        "gravity {} {} {}",
+        gravity.x, gravity.y, gravity.z
+      )))
+    },
+  )
+  .arg("x", ArgKind::Float)
+  .arg("y", ArgKind::Float)
+  .arg("z", ArgKind::Float)
+}
+
 pub struct PhysicsPlugin;
 impl Plugin for PhysicsPlugin {
   fn build(&self, app: &mut App) {
     app
       .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-      .add_system(attach_collider.system());
+      .add_system(attach_collider.system())
+      .add_console_command(timescale_command())
+      .add_console_command(gravity_command());
   }
 }
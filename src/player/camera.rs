@@ -0,0 +1,251 @@
+use super::{
+  controller::Perspective,
+  input_map::{Action, ActionState},
+  look::{LookDirection, MouseSettings},
+  movement::solid_groups,
+  raycast::ViewInfo,
+  spawn::Player,
+};
+use crate::{
+  console::ConsoleCommand,
+  cvars::{Cvar, Cvars},
+  prelude::*,
+  ui::UiWindowManager,
+};
+use bevy::input::mouse::MouseWheel;
+use bevy_rapier3d::{
+  prelude::*,
+  rapier::{geometry::Ray, pipeline::QueryPipeline},
+};
+
+/// Where the player's camera is, which decides whether movement input moves the body.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+  /// On the player's head, seen through the camera's `Perspective`.
+  Attached,
+  /// Flies around on its own, leaving the body behind.
+  Spectator,
+  /// Circles around an entity.
+  Orbit { target: Entity, distance: f32 },
+  /// Circles around a vehicle the player is driving from `seat`.
+  Driving { seat: Entity, distance: f32 },
+}
+
+impl Default for CameraMode {
+  fn default() -> Self {
+    CameraMode::Attached
+  }
+}
+
+/// Closest the third person camera gets to a wall behind the player.
+const WALL_MARGIN: f32 = 0.2;
+const MIN_ORBIT_DISTANCE: f32 = 1.;
+
+pub const SPECTATOR_SPEED: &str = "camera.spectator_speed";
+pub const ORBIT_DISTANCE: &str = "camera.orbit_distance";
+
+pub fn cvars() -> Vec<Cvar> {
+  vec![
+    Cvar::float(
+      SPECTATOR_SPEED,
+      "Speed of the free-flying spectator camera",
+      10.,
+    )
+    .range(0., 200.),
+    Cvar::float(
+      ORBIT_DISTANCE,
+      "Starting distance of the orbit camera from its target",
+      10.,
+    )
+    .range(MIN_ORBIT_DISTANCE, 200.),
+  ]
+}
+
+/// Moves the camera between the player's head and the world when the camera mode changes.
+pub fn attach_camera(
+  mut commands: Commands,
+  camera_mode: Res<CameraMode>,
+  player: Res<Player>,
+  mut camera_query: Query<(&mut Transform, &GlobalTransform, &Perspective)>,
+  mut detached: Local<bool>,
+) {
+  let detach = *camera_mode != CameraMode::Attached;
+  if detach == *detached {
+    return;
+  }
+  *detached = detach;
+
+  let (mut transform, global_transform, perspective) = camera_query.get_mut(player.camera).unwrap();
+  if !detach {
+    commands.entity(player.head).push_children(&[player.camera]);
+    *transform = perspective.to_transform();
+  } else {
+    // Start out from where the camera was, instead of jumping to the origin
+    commands.entity(player.camera).remove::<Parent>();
+    *transform = Transform {
+      translation: global_transform.translation,
+      rotation: global_transform.rotation,
+      scale: Vec3::ONE,
+    };
+  }
+}
+
+pub fn spectator_move(
+  time: Res<Time>,
+  cvars: Res<Cvars>,
+  actions: Res<ActionState>,
+  camera_mode: Res<CameraMode>,
+  settings: Res<MouseSettings>,
+  player: Res<Player>,
+  ui_window_manager: Res<UiWindowManager>,
+  mut camera_query: Query<(&mut Transform, &LookDirection)>,
+) {
+  if *camera_mode != CameraMode::Spectator || ui_window_manager.is_showing() {
+    return;
+  }
+  let (mut transform, look) = camera_query.get_mut(player.camera).unwrap();
+
+  let mut direction = look.forward * actions.axis(Action::Forward, Action::Backward)
+    + look.right * actions.axis(Action::Right, Action::Left);
+  if actions.pressed(Action::Jump) {
+    direction += Vec3::Y;
+  }
+  if actions.pressed(Action::Crouch) {
+    direction -= Vec3::Y;
+  }
+  let mut speed = cvars.float(SPECTATOR_SPEED);
+  if actions.pressed(Action::Run) {
+    speed *= 2.;
+  }
+  if direction.length_squared() > 1. {
+    direction = direction.normalize();
+  }
+
+  transform.translation += direction * speed * time.delta_seconds();
+  transform.rotation = Quat::from_rotation_ypr(
+    settings.yaw_pitch_roll.x,
+    settings.yaw_pitch_roll.y,
+    settings.yaw_pitch_roll.z,
+  );
+}
+
+pub fn orbit_camera(
+  mut camera_mode: ResMut<CameraMode>,
+  player: Res<Player>,
+  mut mouse_wheel_reader: EventReader<MouseWheel>,
+  target_query: Query<&GlobalTransform>,
+  mut camera_query: Query<(&mut Transform, &LookDirection)>,
+) {
+  let (target, mut distance) = match *camera_mode {
+    CameraMode::Orbit { target, distance } => (target, distance),
+    CameraMode::Driving { seat, distance } => (seat, distance),
+    _ => return,
+  };
+  let center = match target_query.get(target) {
+    Ok(transform) => transform.translation,
+    Err(_) => {
+      // The target was despawned
+      *camera_mode = CameraMode::Attached;
+      return;
+    }
+  };
+
+  // Scroll to zoom in and out
+  let mut zoomed = false;
+  for event in mouse_wheel_reader.iter() {
+    distance = (distance * (1. - event.y.signum() * 0.1)).max(MIN_ORBIT_DISTANCE);
+    zoomed = true;
+  }
+  if zoomed {
+    match &mut *camera_mode {
+      CameraMode::Orbit { distance: zoom, .. } | CameraMode::Driving { distance: zoom, .. } => {
+        *zoom = distance
+      }
+      _ => {}
+    }
+  }
+
+  let (mut transform, look) = camera_query.get_mut(player.camera).unwrap();
+  *transform =
+    Transform::from_translation(center - look.forward * distance).looking_at(center, Vec3::Y);
+}
+
+/// Pulls the third person camera in front of walls that are between it and the player's head.
+pub fn third_person_collision(
+  camera_mode: Res<CameraMode>,
+  player: Res<Player>,
+  pipeline: Res<QueryPipeline>,
+  collider_query: QueryPipelineColliderComponentsQuery,
+  head_query: Query<&GlobalTransform>,
+  mut camera_query: Query<(&mut Transform, &Perspective)>,
+) {
+  if *camera_mode != CameraMode::Attached {
+    return;
+  }
+  let (mut transform, perspective) = camera_query.get_mut(player.camera).unwrap();
+  if let Perspective::FirstPerson = perspective {
+    return;
+  }
+
+  let offset = perspective.to_transform().translation;
+  let distance = offset.length();
+  let head = head_query.get(player.head).unwrap();
+  let direction = head.rotation * offset / distance;
+  let ray = Ray::new(head.translation.to_na_point3(), direction.to_na_vector3());
+  let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+  let free = pipeline
+    .cast_ray(&colliders, &ray, distance, true, solid_groups(), None)
+    .map_or(distance, |(_, toi)| (toi - WALL_MARGIN).max(0.));
+
+  transform.translation = offset * (free / distance);
+}
+
+pub fn spectate_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "spectate",
+    "Toggles a free camera that leaves your body behind",
+    |world, _args| {
+      let mut camera_mode = world.get_resource_mut::<CameraMode>().unwrap();
+      *camera_mode = if *camera_mode == CameraMode::Spectator {
+        CameraMode::Attached
+      } else {
+        CameraMode::Spectator
+      };
+      Ok(Some(format!(
+        "spectate {}",
+        if *camera_mode == CameraMode::Spectator {
+          "on"
+        } else {
+          "off"
+        }
+      )))
+    },
+  )
+}
+
+pub fn orbit_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "orbit",
+    "Circles the camera around what you are looking at, or returns it to you",
+    |world, _args| {
+      if let CameraMode::Orbit { .. } = *world.get_resource::<CameraMode>().unwrap() {
+        *world.get_resource_mut::<CameraMode>().unwrap() = CameraMode::Attached;
+        return Ok(Some("orbit off".to_owned()));
+      }
+
+      let target = world
+        .get_resource::<ViewInfo>()
+        .unwrap()
+        .hit
+        .as_ref()
+        .map(|hit| hit.entity)
+        .ok_or_else(|| anyhow::anyhow!("look at something to orbit it"))?;
+      let distance = world.get_resource::<Cvars>().unwrap().float(ORBIT_DISTANCE);
+      *world.get_resource_mut::<CameraMode>().unwrap() = CameraMode::Orbit { target, distance };
+      let name = world
+        .get::<Name>(target)
+        .map_or_else(|| format!("{:?}", target), |name| name.as_str().to_owned());
+      Ok(Some(format!("orbiting {}", name)))
+    },
+  )
+}
@@ -1,15 +1,20 @@
+use crate::console::ConsoleCommand;
+use crate::cvars::{Cvar, Cvars};
 use crate::prelude::*;
 use crate::ui::UiWindowManager;
 
 use super::{
+  camera::CameraMode,
   events::{ForceEvent, ImpulseEvent, PitchEvent, TranslationEvent, YawEvent},
-  input_map::InputMap,
+  input_map::{Action, ActionState},
   look::{LookDirection, LookEntity},
+  movement::{self, MovementMode},
 };
 
 use bevy_rapier3d::prelude::*;
 
 pub struct BodyTag;
+pub struct BodyModelTag;
 pub struct YawTag;
 pub struct HeadTag;
 pub struct CameraTag;
@@ -22,16 +27,42 @@ pub struct InputState {
   pub right: bool,
   pub run: bool,
   pub jump: bool,
+  pub crouch: bool,
+  /// Analog movement from -1 to 1, with x to the right and y forward, e.g. from a stick. Added
+  /// to the digital directions above.
+  pub movement: Vec2,
 }
 
 pub struct CharacterController {
-  pub input_map: InputMap,
   pub fly: bool,
+  /// Flying without colliding with anything.
+  pub noclip: bool,
+  /// Move with shape casts instead of pushing a dynamic body around.
+  pub kinematic: bool,
   pub walk_speed: f32,
   pub run_speed: f32,
   pub jump_speed: f32,
+  pub crouch_speed: f32,
+  pub swim_speed: f32,
+  pub climb_speed: f32,
+  /// Steepest slope that can be walked up, in degrees.
+  pub max_slope: f32,
+  /// Tallest ledge that is stepped onto instead of blocking the player.
+  pub step_height: f32,
+  pub height: f32,
+  pub crouch_height: f32,
+  pub half_width: f32,
   pub velocity: Vec3,
   pub jumping: bool,
+  pub mode: MovementMode,
+  pub grounded: bool,
+  /// Normal of the surface below the body, or zero when there is nothing there.
+  pub ground_normal: Vec3,
+  /// Velocity of whatever the body is standing on, e.g. a moving platform.
+  pub ground_velocity: Vec3,
+  pub wants_crouch: bool,
+  pub crouched: bool,
+  pub ladder_detach: f32,
   pub dt: f32,
   pub sim_to_render: f32,
   pub input_state: InputState,
@@ -40,13 +71,29 @@ pub struct CharacterController {
 impl Default for CharacterController {
   fn default() -> Self {
     Self {
-      input_map: InputMap::default(),
       fly: false,
+      noclip: false,
+      kinematic: false,
       walk_speed: 5.0,
       run_speed: 8.0,
       jump_speed: 6.0,
+      crouch_speed: 2.5,
+      swim_speed: 4.0,
+      climb_speed: 3.0,
+      max_slope: 45.0,
+      step_height: 0.6,
+      height: 3.0,
+      crouch_height: 1.8,
+      half_width: 1.0,
       velocity: Vec3::ZERO,
       jumping: false,
+      mode: MovementMode::Walk,
+      grounded: false,
+      ground_normal: Vec3::ZERO,
+      ground_velocity: Vec3::ZERO,
+      wants_crouch: false,
+      crouched: false,
+      ladder_detach: 0.0,
       dt: 1.0 / 60.0,
       sim_to_render: 0.0,
       input_state: InputState::default(),
@@ -54,12 +101,94 @@ impl Default for CharacterController {
   }
 }
 
+impl CharacterController {
+  /// Half extents of the body collider, which is shorter while crouched.
+  pub fn half_extents(&self) -> Vec3 {
+    let height = if self.crouched {
+      self.crouch_height
+    } else {
+      self.height
+    };
+    Vec3::new(self.half_width, height / 2., self.half_width)
+  }
+
+  /// Whether a surface with this normal is flat enough to stand on.
+  pub fn is_walkable(&self, normal: Vec3) -> bool {
+    normal != Vec3::ZERO && normal.y >= self.max_slope.to_radians().cos()
+  }
+}
+
+pub const KINEMATIC: &str = "player.kinematic";
+pub const WALK_SPEED: &str = "player.walk_speed";
+pub const RUN_SPEED: &str = "player.run_speed";
+pub const JUMP_SPEED: &str = "player.jump_speed";
+pub const CROUCH_SPEED: &str = "player.crouch_speed";
+pub const SWIM_SPEED: &str = "player.swim_speed";
+pub const CLIMB_SPEED: &str = "player.climb_speed";
+pub const MAX_SLOPE: &str = "player.max_slope";
+pub const STEP_HEIGHT: &str = "player.step_height";
+
+pub fn cvars() -> Vec<Cvar> {
+  let defaults = CharacterController::default();
+  vec![
+    Cvar::bool(
+      KINEMATIC,
+      "Move the player with shape casts instead of physics impulses",
+      defaults.kinematic,
+    ),
+    Cvar::float(WALK_SPEED, "Walking speed", defaults.walk_speed).range(0., 100.),
+    Cvar::float(RUN_SPEED, "Running speed", defaults.run_speed).range(0., 100.),
+    Cvar::float(JUMP_SPEED, "Upward speed of jumps", defaults.jump_speed).range(0., 100.),
+    Cvar::float(
+      CROUCH_SPEED,
+      "Crouched walking speed",
+      defaults.crouch_speed,
+    )
+    .range(0., 100.),
+    Cvar::float(SWIM_SPEED, "Swimming speed", defaults.swim_speed).range(0., 100.),
+    Cvar::float(CLIMB_SPEED, "Ladder climbing speed", defaults.climb_speed).range(0., 100.),
+    Cvar::float(
+      MAX_SLOPE,
+      "Steepest slope that can be walked up, in degrees",
+      defaults.max_slope,
+    )
+    .range(0., 90.),
+    Cvar::float(
+      STEP_HEIGHT,
+      "Tallest ledge that is stepped onto",
+      defaults.step_height,
+    )
+    .range(0., 3.),
+  ]
+}
+
+pub fn apply_cvars(cvars: Res<Cvars>, mut controller: ResMut<CharacterController>) {
+  if cvars.is_changed() {
+    controller.kinematic = cvars.bool(KINEMATIC);
+    controller.walk_speed = cvars.float(WALK_SPEED);
+    controller.run_speed = cvars.float(RUN_SPEED);
+    controller.jump_speed = cvars.float(JUMP_SPEED);
+    controller.crouch_speed = cvars.float(CROUCH_SPEED);
+    controller.swim_speed = cvars.float(SWIM_SPEED);
+    controller.climb_speed = cvars.float(CLIMB_SPEED);
+    controller.max_slope = cvars.float(MAX_SLOPE);
+    controller.step_height = cvars.float(STEP_HEIGHT);
+  }
+}
+
 pub enum Perspective {
   FirstPerson,
   ThirdPerson,
 }
 
 impl Perspective {
+  pub fn toggled(&self) -> Self {
+    match self {
+      Perspective::FirstPerson => Perspective::ThirdPerson,
+      Perspective::ThirdPerson => Perspective::FirstPerson,
+    }
+  }
+
   pub fn to_transform(&self) -> Transform {
     let (eye, center) = match self {
       Perspective::FirstPerson => (Vec3::ZERO, -Vec3::Z),
@@ -72,7 +201,7 @@ impl Perspective {
 
 pub fn input_to_events(
   time: Res<Time>,
-  keyboard_input: Res<Input<KeyCode>>,
+  actions: Res<ActionState>,
   mut translation_events: EventWriter<TranslationEvent>,
   mut impulse_events: EventWriter<ImpulseEvent>,
   mut force_events: EventWriter<ForceEvent>,
@@ -81,44 +210,45 @@ pub fn input_to_events(
   look_direction_query: Query<&LookDirection>,
   mut transform_query: Query<(&mut Transform, &mut Perspective)>,
   ui_window_manager: Res<UiWindowManager>,
+  camera_mode: Res<CameraMode>,
+  rapier_config: Res<RapierConfiguration>,
 ) {
-  if ui_window_manager.is_showing() {
+  // The body stays put while the camera is off on its own
+  if *camera_mode != CameraMode::Attached {
+    controller.input_state = InputState::default();
     return;
   }
+  // Windows have the keyboard to themselves, but scripts can still move the player
+  let use_actions = !ui_window_manager.is_showing();
 
   let xz = Vec3::new(1.0, 0.0, 1.0);
   for (mass_props, look_entity) in controller_query.iter_mut() {
     let camera_entity = look_entity.0;
     controller.sim_to_render += time.delta_seconds();
 
-    if keyboard_input.pressed(controller.input_map.key_forward) {
-      controller.input_state.forward = true;
-    }
-    if keyboard_input.pressed(controller.input_map.key_backward) {
-      controller.input_state.backward = true;
-    }
-    if keyboard_input.pressed(controller.input_map.key_right) {
-      controller.input_state.right = true;
-    }
-    if keyboard_input.pressed(controller.input_map.key_left) {
-      controller.input_state.left = true;
-    }
-    if keyboard_input.pressed(controller.input_map.key_run) {
-      controller.input_state.run = true;
-    }
-    if keyboard_input.just_pressed(controller.input_map.key_jump) {
-      controller.input_state.jump = true;
-    }
-    if keyboard_input.just_pressed(controller.input_map.key_toggle_fly) {
-      controller.fly = !controller.fly;
-    }
-    if keyboard_input.just_pressed(controller.input_map.key_toggle_camera_view) {
-      let (mut transform, mut perspective) = transform_query.get_mut(camera_entity).unwrap();
-      *perspective = match *perspective {
-        Perspective::FirstPerson => Perspective::ThirdPerson,
-        Perspective::ThirdPerson => Perspective::FirstPerson,
-      };
-      *transform = perspective.to_transform();
+    if use_actions {
+      controller.input_state.movement = Vec2::new(
+        actions.axis(Action::Right, Action::Left),
+        actions.axis(Action::Forward, Action::Backward),
+      );
+      if actions.pressed(Action::Run) {
+        controller.input_state.run = true;
+      }
+      if actions.just_pressed(Action::Jump) {
+        controller.input_state.jump = true;
+      }
+      if actions.pressed(Action::Crouch) {
+        controller.input_state.crouch = true;
+      }
+      if actions.just_pressed(Action::ToggleFly) {
+        controller.fly = !controller.fly;
+        controller.noclip = false;
+      }
+      if actions.just_pressed(Action::ToggleCameraView) {
+        let (mut transform, mut perspective) = transform_query.get_mut(camera_entity).unwrap();
+        *perspective = perspective.toggled();
+        *transform = perspective.to_transform();
+      }
     }
 
     if controller.sim_to_render < controller.dt {
@@ -132,58 +262,120 @@ pub fn input_to_events(
       .get_component::<LookDirection>(camera_entity)
       .expect("Failed to get LookDirection from Entity");
 
+    controller.wants_crouch = controller.input_state.crouch;
+    let mode = if controller.fly {
+      MovementMode::Walk
+    } else {
+      controller.mode
+    };
+    let flat_forward = (look.forward * xz).normalize();
+    let flat_right = (look.right * xz).normalize();
+
     // Calculate forward / right / up vectors
-    let (forward, right, _up) = if controller.fly {
+    let (forward, right, _up) = if controller.fly || mode == MovementMode::Swim {
       (look.forward, look.right, look.up)
+    } else if mode == MovementMode::Ladder {
+      // Looking down climbs down
+      let up = if look.forward.y < -0.5 {
+        -Vec3::Y
+      } else {
+        Vec3::Y
+      };
+      (up, flat_right, Vec3::Y)
     } else {
-      (
-        (look.forward * xz).normalize(),
-        (look.right * xz).normalize(),
-        Vec3::Y,
-      )
+      (flat_forward, flat_right, Vec3::Y)
     };
 
     // Calculate the desired velocity based on input
-    let mut desired_velocity = Vec3::ZERO;
-    if controller.input_state.forward {
-      desired_velocity += forward;
+    let input_state = &controller.input_state;
+    let mut movement = input_state.movement;
+    if input_state.forward {
+      movement.y += 1.;
     }
-    if controller.input_state.backward {
-      desired_velocity -= forward;
+    if input_state.backward {
+      movement.y -= 1.;
     }
-    if controller.input_state.right {
-      desired_velocity += right;
+    if input_state.right {
+      movement.x += 1.;
     }
-    if controller.input_state.left {
-      desired_velocity -= right;
+    if input_state.left {
+      movement.x -= 1.;
     }
+    // Sticks that are only pushed partway move slower
+    let throttle = movement.length().min(1.);
+    let mut desired_velocity = right * movement.x + forward * movement.y;
 
-    // Limit x/z velocity to walk/run speed
-    let speed = if controller.input_state.run {
-      controller.run_speed
+    // Limit velocity to the speed of the current movement mode
+    let speed = match mode {
+      MovementMode::Swim => controller.swim_speed,
+      MovementMode::Ladder => controller.climb_speed,
+      MovementMode::Walk if controller.crouched => controller.crouch_speed,
+      MovementMode::Walk if controller.input_state.run => controller.run_speed,
+      MovementMode::Walk => controller.walk_speed,
+    };
+    // Swimming and climbing aren't affected by gravity, so vertical velocity is controlled too
+    let controlled = if mode == MovementMode::Walk {
+      xz
+    } else {
+      Vec3::ONE
+    };
+    // Movement is relative to whatever the body is standing on
+    let ground_velocity = if mode == MovementMode::Walk && controller.grounded {
+      controller.ground_velocity * xz
     } else {
-      controller.walk_speed
+      Vec3::ZERO
     };
     desired_velocity = if desired_velocity.length_squared() > 1E-6 {
-      desired_velocity.normalize() * speed
+      desired_velocity.normalize() * speed * throttle
     } else {
-      // No input - apply damping to the x/z of the current velocity
-      controller.velocity * 0.5 * xz
+      // No input - apply damping to the controlled part of the current velocity
+      (controller.velocity - ground_velocity) * 0.5 * controlled
     };
+    desired_velocity += ground_velocity;
+
+    // Standing on a slope that is too steep - don't allow walking up it
+    if mode == MovementMode::Walk && !controller.grounded && controller.ground_normal != Vec3::ZERO
+    {
+      let downhill = controller.ground_normal * xz;
+      if downhill.length_squared() > 1E-6 {
+        let downhill = downhill.normalize();
+        let uphill_speed = desired_velocity.dot(downhill).min(0.);
+        desired_velocity -= downhill * uphill_speed;
+      }
+    }
 
     // Handle jumping
     let was_jumping = controller.jumping && !controller.fly;
-    if !controller.fly {
-      desired_velocity.y = if controller.input_state.jump {
-        controller.jumping = true;
-        controller.jump_speed
-      } else {
-        0.0
-      };
+    match mode {
+      _ if controller.fly => {}
+      MovementMode::Walk => {
+        desired_velocity.y = if controller.input_state.jump && controller.grounded {
+          controller.jumping = true;
+          controller.jump_speed
+        } else {
+          0.0
+        };
+      }
+      MovementMode::Swim => {
+        if (use_actions && actions.pressed(Action::Jump)) || controller.input_state.jump {
+          desired_velocity.y += controller.swim_speed;
+        }
+        if controller.input_state.crouch {
+          desired_velocity.y -= controller.swim_speed;
+        }
+      }
+      MovementMode::Ladder => {
+        if controller.input_state.jump {
+          // Push off away from the ladder
+          desired_velocity =
+            -flat_forward * controller.walk_speed + Vec3::Y * controller.jump_speed * 0.5;
+          movement::detach_from_ladder(&mut controller);
+        }
+      }
     }
 
     // Calculate impulse - the desired momentum change for the time period
-    let delta_velocity = desired_velocity - controller.velocity * xz;
+    let delta_velocity = desired_velocity - controller.velocity * controlled;
     let impulse = delta_velocity * 1.0 / mass_props.effective_inv_mass;
     if impulse.length_squared() > 1E-6 {
       impulse_events.send(ImpulseEvent(impulse.clone()));
@@ -197,9 +389,19 @@ pub fn input_to_events(
 
     controller.velocity.x = desired_velocity.x;
     controller.velocity.z = desired_velocity.z;
-    controller.velocity.y = if was_jumping {
+    controller.velocity.y = if controller.kinematic
+      && !controller.fly
+      && mode == MovementMode::Walk
+      && desired_velocity.y == 0.0
+    {
+      // Falling is simulated by the kinematic controller
+      controller.velocity.y
+    } else if was_jumping {
       // Apply gravity for kinematic simulation
-      (-9.81f32).mul_add(controller.dt, controller.velocity.y)
+      rapier_config
+        .gravity
+        .y
+        .mul_add(controller.dt, controller.velocity.y)
     } else {
       desired_velocity.y
     };
@@ -234,3 +436,15 @@ pub fn controller_to_pitch(
     }
   }
 }
+
+pub fn noclip_command() -> ConsoleCommand {
+  ConsoleCommand::new("noclip", "Toggles flying through walls", |world, _args| {
+    let mut controller = world.get_resource_mut::<CharacterController>().unwrap();
+    controller.noclip = !controller.noclip;
+    controller.fly = controller.noclip;
+    Ok(Some(format!(
+      "noclip {}",
+      if controller.noclip { "on" } else { "off" }
+    )))
+  })
+}
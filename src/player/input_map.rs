@@ -1,44 +1,542 @@
-use bevy::input::keyboard::KeyCode;
+use crate::{
+  cvars::{Cvar, Cvars},
+  prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fmt,
+  path::{Path, PathBuf},
+};
 
-// TODO: modularize this so each component registers its own keys
-pub struct InputMap {
-  pub key_forward: KeyCode,
-  pub key_backward: KeyCode,
-  pub key_left: KeyCode,
-  pub key_right: KeyCode,
-  pub key_jump: KeyCode,
-  pub key_run: KeyCode,
-  pub key_crouch: KeyCode,
-
-  pub key_toggle_camera_view: KeyCode,
-  pub key_toggle_fly: KeyCode,
-  pub key_show_ui: KeyCode,
-  pub key_toggle_world_visualizer: KeyCode,
-  pub key_rotate_toolgun: KeyCode,
-  pub key_lock_rotation: KeyCode,
-  pub key_toggle_terminal: KeyCode,
+pub const INPUT_MAP_PATH: &str = "config/controls.json";
+
+/// How many bindings each action can have, e.g. one on the keyboard and one on a gamepad.
+pub const BINDING_SLOTS: usize = 2;
+
+/// How far an analog input has to go for its action to count as pressed.
+const PRESS_THRESHOLD: f32 = 0.5;
+
+pub const GAMEPAD_DEADZONE: &str = "gamepad.deadzone";
+pub const GAMEPAD_CURVE: &str = "gamepad.curve";
+
+pub fn cvars() -> Vec<Cvar> {
+  vec![
+    Cvar::float(
+      GAMEPAD_DEADZONE,
+      "Stick and trigger travel that is ignored, from 0 to 1",
+      0.15,
+    )
+    .range(0., 0.9),
+    Cvar::float(
+      GAMEPAD_CURVE,
+      "Exponent applied to stick input, higher values give finer control near the center",
+      2.,
+    )
+    .range(0.1, 5.),
+  ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+  Forward,
+  Backward,
+  Left,
+  Right,
+  Jump,
+  Run,
+  Crouch,
+  Use,
+  LookUp,
+  LookDown,
+  LookLeft,
+  LookRight,
+  Grab,
+  Freeze,
+  NextTool,
+  PreviousTool,
+  ToggleCameraView,
+  ToggleFly,
+  ShowUi,
+  ToggleWorldVisualizer,
+  RotateToolgun,
+  LockRotation,
+  ToggleTerminal,
+  ToggleControls,
+  SpawnMenuPrevious,
+  SpawnMenuNext,
+  SpawnMenuSelect,
+  Thruster1,
+  Thruster2,
+  Thruster3,
+  Thruster4,
+}
+
+impl Action {
+  pub const ALL: &'static [Action] = &[
+    Action::Forward,
+    Action::Backward,
+    Action::Left,
+    Action::Right,
+    Action::Jump,
+    Action::Run,
+    Action::Crouch,
+    Action::Use,
+    Action::LookUp,
+    Action::LookDown,
+    Action::LookLeft,
+    Action::LookRight,
+    Action::Grab,
+    Action::Freeze,
+    Action::NextTool,
+    Action::PreviousTool,
+    Action::ToggleCameraView,
+    Action::ToggleFly,
+    Action::ShowUi,
+    Action::ToggleWorldVisualizer,
+    Action::RotateToolgun,
+    Action::LockRotation,
+    Action::ToggleTerminal,
+    Action::ToggleControls,
+    Action::SpawnMenuPrevious,
+    Action::SpawnMenuNext,
+    Action::SpawnMenuSelect,
+    Action::Thruster1,
+    Action::Thruster2,
+    Action::Thruster3,
+    Action::Thruster4,
+  ];
 
+  /// Actions that fire thrusters, one for each group of thrusters that `/thruster` can attach.
+  pub const THRUSTERS: &'static [Action] = &[
+    Action::Thruster1,
+    Action::Thruster2,
+    Action::Thruster3,
+    Action::Thruster4,
+  ];
+
+  pub fn label(&self) -> &'static str {
+    match self {
+      Action::Forward => "Move forward",
+      Action::Backward => "Move backward",
+      Action::Left => "Move left",
+      Action::Right => "Move right",
+      Action::Jump => "Jump",
+      Action::Run => "Run",
+      Action::Crouch => "Crouch",
+      Action::Use => "Use",
+      Action::LookUp => "Look up",
+      Action::LookDown => "Look down",
+      Action::LookLeft => "Look left",
+      Action::LookRight => "Look right",
+      Action::Grab => "Grab with physgun",
+      Action::Freeze => "Freeze held object",
+      Action::NextTool => "Next tool",
+      Action::PreviousTool => "Previous tool",
+      Action::ToggleCameraView => "Toggle third person",
+      Action::ToggleFly => "Toggle fly",
+      Action::ShowUi => "Spawn menu",
+      Action::ToggleWorldVisualizer => "Debugger",
+      Action::RotateToolgun => "Rotate held object",
+      Action::LockRotation => "Snap rotation",
+      Action::ToggleTerminal => "Terminal",
+      Action::ToggleControls => "Controls",
+      Action::SpawnMenuPrevious => "Spawn menu: previous",
+      Action::SpawnMenuNext => "Spawn menu: next",
+      Action::SpawnMenuSelect => "Spawn menu: spawn",
+      Action::Thruster1 => "Thrusters 1",
+      Action::Thruster2 => "Thrusters 2",
+      Action::Thruster3 => "Thrusters 3",
+      Action::Thruster4 => "Thrusters 4",
+    }
+  }
+
+  /// Whether both actions can be triggered at once, in which case they can't share a binding.
+  /// Snapping only applies while rotating a held object, when the player can't run anyway.
+  pub fn conflicts_with(&self, other: Action) -> bool {
+    let rotating = |action: Action| action == Action::LockRotation;
+    *self != other && rotating(*self) == rotating(other)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AxisDirection {
+  Positive,
+  Negative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+  Key(KeyCode),
+  Mouse(MouseButton),
+  Gamepad(GamepadButtonType),
+  /// One direction of a stick, e.g. pushing the left stick up.
+  GamepadAxis(GamepadAxisType, AxisDirection),
+}
+
+impl fmt::Display for Binding {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Binding::Key(key) => write!(f, "{:?}", key),
+      Binding::Mouse(MouseButton::Other(button)) => write!(f, "Mouse {}", button),
+      Binding::Mouse(button) => write!(f, "Mouse {:?}", button),
+      Binding::Gamepad(button) => write!(f, "Pad {:?}", button),
+      Binding::GamepadAxis(axis, AxisDirection::Positive) => write!(f, "Pad {:?}+", axis),
+      Binding::GamepadAxis(axis, AxisDirection::Negative) => write!(f, "Pad {:?}-", axis),
+    }
+  }
+}
+
+/// The bindings of every action, persisted to disk as JSON.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InputMap {
+  pub bindings: BTreeMap<Action, [Option<Binding>; BINDING_SLOTS]>,
   pub invert_y: bool,
+  #[serde(skip)]
+  path: PathBuf,
 }
 
 impl Default for InputMap {
   fn default() -> Self {
-    Self {
-      key_forward: KeyCode::W,
-      key_backward: KeyCode::S,
-      key_left: KeyCode::A,
-      key_right: KeyCode::D,
-      key_jump: KeyCode::Back,
-      key_run: KeyCode::LShift,
-      key_crouch: KeyCode::LControl,
-      key_toggle_camera_view: KeyCode::V,
-      key_toggle_fly: KeyCode::F,
-      key_show_ui: KeyCode::Tab,
-      key_toggle_world_visualizer: KeyCode::LAlt,
-      key_rotate_toolgun: KeyCode::E,
-      key_lock_rotation: KeyCode::LShift,
-      key_toggle_terminal: KeyCode::Grave,
+    use AxisDirection::{Negative, Positive};
+    use Binding::{Gamepad, GamepadAxis, Key, Mouse};
+    use GamepadAxisType::{LeftStickX, LeftStickY, RightStickX, RightStickY};
+    let bindings = vec![
+      (
+        Action::Forward,
+        [
+          Some(Key(KeyCode::W)),
+          Some(GamepadAxis(LeftStickY, Positive)),
+        ],
+      ),
+      (
+        Action::Backward,
+        [
+          Some(Key(KeyCode::S)),
+          Some(GamepadAxis(LeftStickY, Negative)),
+        ],
+      ),
+      (
+        Action::Left,
+        [
+          Some(Key(KeyCode::A)),
+          Some(GamepadAxis(LeftStickX, Negative)),
+        ],
+      ),
+      (
+        Action::Right,
+        [
+          Some(Key(KeyCode::D)),
+          Some(GamepadAxis(LeftStickX, Positive)),
+        ],
+      ),
+      (
+        Action::Jump,
+        [
+          Some(Key(KeyCode::Space)),
+          Some(Gamepad(GamepadButtonType::South)),
+        ],
+      ),
+      (
+        Action::Run,
+        [
+          Some(Key(KeyCode::LShift)),
+          Some(Gamepad(GamepadButtonType::LeftThumb)),
+        ],
+      ),
+      (
+        Action::Crouch,
+        [
+          Some(Key(KeyCode::LControl)),
+          Some(Gamepad(GamepadButtonType::East)),
+        ],
+      ),
+      (
+        Action::LookUp,
+        [None, Some(GamepadAxis(RightStickY, Positive))],
+      ),
+      (
+        Action::LookDown,
+        [None, Some(GamepadAxis(RightStickY, Negative))],
+      ),
+      (
+        Action::LookLeft,
+        [None, Some(GamepadAxis(RightStickX, Negative))],
+      ),
+      (
+        Action::LookRight,
+        [None, Some(GamepadAxis(RightStickX, Positive))],
+      ),
+      (
+        Action::Grab,
+        [
+          Some(Mouse(MouseButton::Left)),
+          Some(Gamepad(GamepadButtonType::RightTrigger2)),
+        ],
+      ),
+      (
+        Action::Freeze,
+        [
+          Some(Mouse(MouseButton::Right)),
+          Some(Gamepad(GamepadButtonType::LeftTrigger2)),
+        ],
+      ),
+      (
+        Action::NextTool,
+        [
+          Some(Key(KeyCode::Q)),
+          Some(Gamepad(GamepadButtonType::RightTrigger)),
+        ],
+      ),
+      (
+        Action::PreviousTool,
+        [None, Some(Gamepad(GamepadButtonType::LeftTrigger))],
+      ),
+      (
+        Action::ToggleCameraView,
+        [
+          Some(Key(KeyCode::V)),
+          Some(Gamepad(GamepadButtonType::RightThumb)),
+        ],
+      ),
+      (Action::ToggleFly, [Some(Key(KeyCode::F)), None]),
+      (
+        Action::Use,
+        [
+          Some(Key(KeyCode::R)),
+          Some(Gamepad(GamepadButtonType::West)),
+        ],
+      ),
+      (
+        Action::ShowUi,
+        [
+          Some(Key(KeyCode::Tab)),
+          Some(Gamepad(GamepadButtonType::DPadUp)),
+        ],
+      ),
+      (
+        Action::ToggleWorldVisualizer,
+        [Some(Key(KeyCode::LAlt)), None],
+      ),
+      (
+        Action::RotateToolgun,
+        [Some(Key(KeyCode::E)), Some(Mouse(MouseButton::Middle))],
+      ),
+      (Action::LockRotation, [Some(Key(KeyCode::LShift)), None]),
+      (Action::ToggleTerminal, [Some(Key(KeyCode::Grave)), None]),
+      (Action::ToggleControls, [Some(Key(KeyCode::F1)), None]),
+      (
+        Action::SpawnMenuPrevious,
+        [
+          Some(Key(KeyCode::Left)),
+          Some(Gamepad(GamepadButtonType::DPadLeft)),
+        ],
+      ),
+      (
+        Action::SpawnMenuNext,
+        [
+          Some(Key(KeyCode::Right)),
+          Some(Gamepad(GamepadButtonType::DPadRight)),
+        ],
+      ),
+      // On a gamepad the d-pad is busy holding ShowUi, so selecting takes a face button
+      (
+        Action::SpawnMenuSelect,
+        [
+          Some(Key(KeyCode::Return)),
+          Some(Gamepad(GamepadButtonType::North)),
+        ],
+      ),
+      (
+        Action::Thruster1,
+        [
+          Some(Key(KeyCode::Numpad8)),
+          Some(Gamepad(GamepadButtonType::DPadDown)),
+        ],
+      ),
+      (Action::Thruster2, [Some(Key(KeyCode::Numpad2)), None]),
+      (Action::Thruster3, [Some(Key(KeyCode::Numpad4)), None]),
+      (Action::Thruster4, [Some(Key(KeyCode::Numpad6)), None]),
+    ];
+
+    InputMap {
+      bindings: bindings.into_iter().collect(),
       invert_y: false,
+      path: PathBuf::from(INPUT_MAP_PATH),
+    }
+  }
+}
+
+impl InputMap {
+  /// Reads the input map at `path`, falling back to the defaults if there isn't one.
+  pub fn load(path: &Path) -> Self {
+    #[cfg(not(target_arch = "wasm32"))]
+    let loaded =
+      std::fs::read_to_string(path).ok().and_then(|contents| {
+        match serde_json::from_str::<InputMap>(&contents) {
+          Ok(input_map) => Some(input_map),
+          Err(e) => {
+            warn!("ignoring {:?}: {}", path, e);
+            None
+          }
+        }
+      });
+
+    #[cfg(target_arch = "wasm32")]
+    let loaded: Option<InputMap> = None;
+
+    let mut input_map = loaded.unwrap_or_default();
+    // Actions added since the file was saved get their default bindings
+    for (action, bindings) in InputMap::default().bindings {
+      input_map.bindings.entry(action).or_insert(bindings);
     }
+    input_map.path = path.to_owned();
+    input_map
   }
+
+  pub fn save(&self) -> anyhow::Result<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      if let Some(dir) = self.path.parent() {
+        std::fs::create_dir_all(dir)?;
+      }
+      std::fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+    }
+    Ok(())
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn reset(&mut self) {
+    let path = std::mem::take(&mut self.path);
+    *self = InputMap {
+      path,
+      ..InputMap::default()
+    };
+  }
+
+  pub fn bindings(&self, action: Action) -> impl Iterator<Item = Binding> + '_ {
+    self
+      .bindings
+      .get(&action)
+      .into_iter()
+      .flat_map(|bindings| bindings.iter().filter_map(|binding| *binding))
+  }
+
+  /// Binds `binding` to a slot of `action`, returning the other actions that now share it.
+  pub fn bind(&mut self, action: Action, slot: usize, binding: Option<Binding>) -> Vec<Action> {
+    self.bindings.entry(action).or_default()[slot] = binding;
+    binding
+      .map(|binding| self.conflicts(action, binding))
+      .unwrap_or_default()
+  }
+
+  /// Actions other than `action` that `binding` would also trigger at the same time.
+  pub fn conflicts(&self, action: Action, binding: Binding) -> Vec<Action> {
+    Action::ALL
+      .iter()
+      .copied()
+      .filter(|other| action.conflicts_with(*other))
+      .filter(|other| self.bindings(*other).any(|b| b == binding))
+      .collect()
+  }
+}
+
+/// Which actions are held this frame, combined from every input device.
+#[derive(Default)]
+pub struct ActionState {
+  /// How far each action is held, from 0 to 1. Buttons are either 0 or 1 while sticks can be in
+  /// between.
+  values: HashMap<Action, f32>,
+  pressed: HashSet<Action>,
+  just_pressed: HashSet<Action>,
+  just_released: HashSet<Action>,
+}
+
+impl ActionState {
+  pub fn value(&self, action: Action) -> f32 {
+    self.values.get(&action).copied().unwrap_or(0.)
+  }
+
+  /// Combines two opposite actions into a value from -1 to 1, e.g. `Right` and `Left`.
+  pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+    self.value(positive) - self.value(negative)
+  }
+
+  pub fn pressed(&self, action: Action) -> bool {
+    self.pressed.contains(&action)
+  }
+
+  pub fn just_pressed(&self, action: Action) -> bool {
+    self.just_pressed.contains(&action)
+  }
+
+  pub fn just_released(&self, action: Action) -> bool {
+    self.just_released.contains(&action)
+  }
+}
+
+/// Applies the deadzone and response curve to how far a stick is pushed in one direction.
+fn shape_axis(value: f32, deadzone: f32, curve: f32) -> f32 {
+  if value <= deadzone {
+    0.
+  } else {
+    ((value - deadzone) / (1. - deadzone)).min(1.).powf(curve)
+  }
+}
+
+pub fn update_action_state(
+  input_map: Res<InputMap>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mouse_input: Res<Input<MouseButton>>,
+  gamepad_input: Res<Input<GamepadButton>>,
+  gamepad_axes: Res<Axis<GamepadAxis>>,
+  gamepads: Res<Gamepads>,
+  cvars: Res<Cvars>,
+  mut state: ResMut<ActionState>,
+) {
+  let (deadzone, curve) = (cvars.float(GAMEPAD_DEADZONE), cvars.float(GAMEPAD_CURVE));
+  let is_pressed = |pressed: bool| if pressed { 1. } else { 0. };
+  let value = |binding: Binding| match binding {
+    Binding::Key(key) => is_pressed(keyboard_input.pressed(key)),
+    Binding::Mouse(button) => is_pressed(mouse_input.pressed(button)),
+    Binding::Gamepad(button) => is_pressed(
+      gamepads
+        .iter()
+        .any(|gamepad| gamepad_input.pressed(GamepadButton(*gamepad, button))),
+    ),
+    Binding::GamepadAxis(axis, direction) => gamepads
+      .iter()
+      .filter_map(|gamepad| gamepad_axes.get(GamepadAxis(*gamepad, axis)))
+      .map(|value| match direction {
+        AxisDirection::Positive => value,
+        AxisDirection::Negative => -value,
+      })
+      .map(|value| shape_axis(value, deadzone, curve))
+      .fold(0., f32::max),
+  };
+
+  state.values = Action::ALL
+    .iter()
+    .map(|action| {
+      let value = input_map
+        .bindings(*action)
+        .map(|binding| value(binding))
+        .fold(0., f32::max);
+      (*action, value)
+    })
+    .filter(|(_, value)| *value > 0.)
+    .collect();
+
+  let previous = std::mem::take(&mut state.pressed);
+  state.pressed = state
+    .values
+    .iter()
+    .filter(|(_, value)| **value >= PRESS_THRESHOLD)
+    .map(|(action, _)| *action)
+    .collect();
+  state.just_pressed = state.pressed.difference(&previous).copied().collect();
+  state.just_released = previous.difference(&state.pressed).copied().collect();
 }
@@ -0,0 +1,152 @@
+use super::{
+  controller::{BodyTag, CharacterController},
+  movement::{cast, hit_normal, MovementMode, SKIN},
+};
+use crate::prelude::*;
+use bevy_rapier3d::{
+  na::Isometry3,
+  prelude::*,
+  rapier::{
+    dynamics::{BodyStatus, IntegrationParameters},
+    pipeline::QueryPipeline,
+  },
+};
+
+/// Most surfaces that a single move slides along before giving up.
+const MAX_SLIDES: usize = 4;
+
+/// Moves from `start` by `motion`, sliding along whatever is in the way. `free_distance` tells
+/// how far the body can go in a direction and the normal of what stops it. Returns where the body
+/// ended up and the normals of the surfaces it hit.
+fn collide_and_slide(
+  free_distance: &impl Fn(Vec3, Vec3, f32) -> Option<(f32, Vec3)>,
+  start: Vec3,
+  motion: Vec3,
+) -> (Vec3, Vec<Vec3>) {
+  let mut position = start;
+  let mut remaining = motion;
+  let mut normals = vec![];
+  for _ in 0..MAX_SLIDES {
+    let distance = remaining.length();
+    if distance < 1E-5 {
+      break;
+    }
+    let direction = remaining / distance;
+    match free_distance(position, direction, distance) {
+      None => {
+        position += remaining;
+        break;
+      }
+      Some((travel, normal)) => {
+        position += direction * travel;
+        remaining -= direction * travel;
+        // Keep the part of the motion that runs along the surface
+        remaining -= normal * remaining.dot(normal);
+        normals.push(normal);
+      }
+    }
+  }
+  (position, normals)
+}
+
+/// Moves the body as a kinematic body when `CharacterController::kinematic` is set, instead of
+/// pushing a dynamic body around with impulses. Steps by the physics timestep rather than the
+/// frame time, so it speeds up and slows down with `/timescale` like everything else.
+pub fn kinematic_move(
+  integration_parameters: Res<IntegrationParameters>,
+  rapier_config: Res<RapierConfiguration>,
+  mut controller: ResMut<CharacterController>,
+  pipeline: Res<QueryPipeline>,
+  collider_query: QueryPipelineColliderComponentsQuery,
+  collider_position_query: Query<&ColliderPosition>,
+  mut body_query: Query<(&mut RigidBodyPosition, &mut RigidBodyType), With<BodyTag>>,
+) {
+  if controller.fly {
+    return;
+  }
+
+  let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+  // Slightly smaller than the body, so it doesn't start out touching what it stands on
+  let half_extents = controller.half_extents() - Vec3::splat(SKIN * 0.5);
+  let shape = ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z);
+  let free_distance = |from: Vec3, direction: Vec3, max_distance: f32| {
+    cast(&pipeline, &colliders, &shape, from, direction, max_distance).map(|hit| {
+      let normal = hit_normal(&collider_position_query, &hit);
+      ((hit.1.toi - SKIN * 0.5).max(0.), normal)
+    })
+  };
+
+  for (mut position, mut body_type) in body_query.iter_mut() {
+    if !controller.kinematic {
+      if *body_type == BodyStatus::KinematicPositionBased {
+        *body_type = BodyStatus::Dynamic;
+      }
+      continue;
+    }
+    if *body_type != BodyStatus::KinematicPositionBased {
+      *body_type = BodyStatus::KinematicPositionBased;
+    }
+
+    let dt = integration_parameters.dt;
+    let walking = controller.mode == MovementMode::Walk;
+    let grounded = walking && controller.grounded && controller.velocity.y <= 0.;
+    if walking {
+      controller.velocity.y = if grounded {
+        0.
+      } else {
+        rapier_config.gravity.y.mul_add(dt, controller.velocity.y)
+      };
+    }
+
+    let mut motion = controller.velocity * dt;
+    if grounded {
+      // Ride along with platforms going up or down
+      motion.y += controller.ground_velocity.y * dt;
+    }
+    let horizontal = motion * Vec3::new(1., 0., 1.);
+    let vertical = motion - horizontal;
+
+    let start = position.position.translation.vector.to_glam_vec3();
+    let (mut end, normals) = collide_and_slide(&free_distance, start, horizontal);
+
+    // Walked into something too steep - try stepping up onto it
+    let blocked = normals
+      .iter()
+      .any(|normal| !controller.is_walkable(*normal));
+    if blocked && grounded && controller.step_height > 0. {
+      let rise = free_distance(start, Vec3::Y, controller.step_height)
+        .map_or(controller.step_height, |(distance, _)| distance);
+      let (stepped, _) = collide_and_slide(&free_distance, start + Vec3::Y * rise, horizontal);
+      let landing = free_distance(stepped, -Vec3::Y, rise)
+        .filter(|(_, normal)| controller.is_walkable(*normal));
+      if let Some((drop, _)) = landing {
+        let stepped = stepped - Vec3::Y * drop;
+        let travelled = |to: Vec3| ((to - start) * Vec3::new(1., 0., 1.)).length();
+        if travelled(stepped) > travelled(end) {
+          end = stepped;
+        }
+      }
+    }
+
+    let (fallen, normals) = collide_and_slide(&free_distance, end, vertical);
+    end = fallen;
+    for normal in normals {
+      // Landing or bumping the head stops vertical movement
+      if normal.y.abs() > 0.5 && controller.velocity.dot(normal) < 0. {
+        controller.velocity.y = 0.;
+      }
+    }
+
+    // Stick to the ground when walking down slopes and stairs instead of flying off
+    if grounded {
+      if let Some((drop, normal)) = free_distance(end, -Vec3::Y, controller.step_height) {
+        if controller.is_walkable(normal) {
+          end -= Vec3::Y * drop;
+        }
+      }
+    }
+
+    position.next_position =
+      Isometry3::from_parts(end.to_na_translation(), position.position.rotation);
+  }
+}
@@ -1,10 +1,11 @@
+use crate::cvars::{Cvar, Cvars};
 use crate::prelude::*;
 use crate::ui::UiWindowManager;
 
 // system that converts delta axis events into pitch and yaw
 use super::{
-  controller::CharacterController,
   events::{LookDeltaEvent, LookEvent, PitchEvent, YawEvent},
+  input_map::{Action, ActionState, InputMap},
 };
 
 use bevy::input::mouse::MouseMotion;
@@ -55,10 +56,39 @@ impl Default for MouseSettings {
   }
 }
 
-const PITCH_BOUND: f32 = std::f32::consts::FRAC_PI_2 - 1E-3;
+pub const SENSITIVITY: &str = "mouse.sensitivity";
+pub const GAMEPAD_LOOK_SPEED: &str = "gamepad.look_speed";
+
+pub fn cvars() -> Vec<Cvar> {
+  vec![
+    Cvar::float(
+      SENSITIVITY,
+      "Radians turned per pixel of mouse movement",
+      MouseSettings::default().sensitivity,
+    )
+    .range(0., 0.1),
+    Cvar::float(
+      GAMEPAD_LOOK_SPEED,
+      "Radians per second turned with the look stick fully pushed",
+      3.,
+    )
+    .range(0., 20.),
+  ]
+}
+
+pub fn apply_cvars(cvars: Res<Cvars>, mut settings: ResMut<MouseSettings>) {
+  if cvars.is_changed() {
+    settings.sensitivity = cvars.float(SENSITIVITY);
+  }
+}
+
+pub const PITCH_BOUND: f32 = std::f32::consts::FRAC_PI_2 - 1E-3;
 
 pub fn input_to_look(
-  keyboard_input: Res<Input<KeyCode>>,
+  time: Res<Time>,
+  cvars: Res<Cvars>,
+  actions: Res<ActionState>,
+  input_map: Res<InputMap>,
   mut settings: ResMut<MouseSettings>,
   mut mouse_motion: EventReader<MouseMotion>,
   mut pitch_events: EventWriter<PitchEvent>,
@@ -66,14 +96,13 @@ pub fn input_to_look(
   mut look_events: EventWriter<LookEvent>,
   mut look_delta_events: EventWriter<LookDeltaEvent>,
   ui_window_manager: Res<UiWindowManager>,
-  controller: Res<CharacterController>,
 ) {
   if ui_window_manager.is_showing() {
     return;
   }
 
   // TODO: make this modular
-  if keyboard_input.pressed(controller.input_map.key_rotate_toolgun) {
+  if actions.pressed(Action::RotateToolgun) {
     return;
   }
 
@@ -82,9 +111,20 @@ pub fn input_to_look(
     // NOTE: -= to invert
     delta -= motion.delta;
   }
+  delta *= settings.sensitivity;
+
+  // Sticks turn at a rate rather than by a distance like the mouse
+  let stick = Vec2::new(
+    -actions.axis(Action::LookRight, Action::LookLeft),
+    actions.axis(Action::LookUp, Action::LookDown),
+  );
+  delta += stick * cvars.float(GAMEPAD_LOOK_SPEED) * time.delta_seconds();
+
+  if input_map.invert_y {
+    delta.y = -delta.y;
+  }
 
-  if delta.length_squared() > 1E-6 {
-    delta *= settings.sensitivity;
+  if delta.length_squared() > 1E-12 {
     settings.yaw_pitch_roll += delta.extend(0.0);
     if settings.yaw_pitch_roll.y > PITCH_BOUND {
       settings.yaw_pitch_roll.y = PITCH_BOUND;
@@ -1,10 +1,15 @@
 // Adapted from https://github.com/superdump/bevy_prototype_character_controller/
-use bevy::prelude::*;
+use crate::{console::AppConsoleExt, cvars::AppCvarsExt};
+use bevy::{input::InputSystem, prelude::*};
+use std::path::Path;
 
+pub mod camera;
 pub mod controller;
 pub mod events;
 pub mod input_map;
+pub mod kinematic;
 pub mod look;
+pub mod movement;
 pub mod physics;
 pub mod raycast;
 pub mod spawn;
@@ -13,6 +18,11 @@ const PROCESS_INPUT_EVENTS: &str = "process_input_events";
 const APPLY_INPUT: &str = "apply_input";
 const UPDATE_VELOCITY: &str = "update_velocity";
 
+// Systems in APPLY_INPUT that move the body, in the order they run
+const STEP_UP: &str = "step_up";
+const KINEMATIC_MOVE: &str = "kinematic_move";
+const APPLY_NOCLIP: &str = "apply_noclip";
+
 pub struct PlayerControllerPlugin;
 impl Plugin for PlayerControllerPlugin {
   fn build(&self, app: &mut App) {
@@ -30,8 +40,19 @@ impl Plugin for PlayerControllerPlugin {
       .add_event::<events::ForceEvent>()
       .init_resource::<look::MouseSettings>()
       .init_resource::<controller::CharacterController>()
+      .init_resource::<camera::CameraMode>()
+      .insert_resource(input_map::InputMap::load(Path::new(
+        input_map::INPUT_MAP_PATH,
+      )))
+      .init_resource::<input_map::ActionState>()
+      .add_system_to_stage(
+        CoreStage::PreUpdate,
+        input_map::update_action_state.system().after(InputSystem),
+      )
       .init_resource::<raycast::ViewInfo>()
       .add_system(raycast::compute_view_info.system())
+      .add_system(controller::apply_cvars.system())
+      .add_system(look::apply_cvars.system())
       .add_stage_after(
         CoreStage::PreUpdate,
         PROCESS_INPUT_EVENTS,
@@ -44,6 +65,10 @@ impl Plugin for PlayerControllerPlugin {
       .add_system_to_stage(PROCESS_INPUT_EVENTS, controller::input_to_events.system())
       .add_system_to_stage(CoreStage::Update, controller::controller_to_yaw.system())
       .add_system_to_stage(CoreStage::Update, controller::controller_to_pitch.system())
+      .add_system_to_stage(PROCESS_INPUT_EVENTS, camera::spectator_move.system())
+      .add_system(camera::attach_camera.system())
+      .add_system(camera::orbit_camera.system())
+      .add_system(camera::third_person_collision.system())
       //
       // Apply forces through physics engine
       .add_stage_before(
@@ -52,12 +77,43 @@ impl Plugin for PlayerControllerPlugin {
         SystemStage::parallel(),
       )
       .add_system_to_stage(UPDATE_VELOCITY, physics::body_to_velocity.system())
+      .add_system_to_stage(UPDATE_VELOCITY, movement::detect_ground.system())
+      .add_system_to_stage(UPDATE_VELOCITY, movement::detect_volumes.system())
       .add_stage_after(PROCESS_INPUT_EVENTS, APPLY_INPUT, SystemStage::parallel())
       .add_system_to_stage(
         APPLY_INPUT,
         physics::controller_to_rapier_dynamic_impulse.system(),
       )
-      .add_system_to_stage(APPLY_INPUT, physics::controller_to_fly.system());
+      .add_system_to_stage(APPLY_INPUT, physics::controller_to_fly.system())
+      .add_system_to_stage(APPLY_INPUT, movement::step_up.system().label(STEP_UP))
+      .add_system_to_stage(
+        APPLY_INPUT,
+        kinematic::kinematic_move
+          .system()
+          .label(KINEMATIC_MOVE)
+          .after(STEP_UP),
+      )
+      .add_system_to_stage(
+        APPLY_INPUT,
+        physics::apply_noclip
+          .system()
+          .label(APPLY_NOCLIP)
+          .after(KINEMATIC_MOVE),
+      )
+      .add_system_to_stage(APPLY_INPUT, movement::crouch.system().after(APPLY_NOCLIP))
+      .add_console_command(controller::noclip_command())
+      .add_console_command(movement::volume_command())
+      .add_console_command(camera::spectate_command())
+      .add_console_command(camera::orbit_command());
+
+    for cvar in controller::cvars()
+      .into_iter()
+      .chain(look::cvars())
+      .chain(input_map::cvars())
+      .chain(camera::cvars())
+    {
+      app.add_cvar(cvar);
+    }
 
     #[cfg(not(target = "wasm32"))]
     app.add_startup_system(spawn::init_hud.system());
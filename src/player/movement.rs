@@ -0,0 +1,382 @@
+use super::{
+  controller::{BodyModelTag, BodyTag, CharacterController, HeadTag},
+  raycast::ViewInfo,
+  spawn::{self, RAPIER_PLAYER_GROUP},
+};
+use crate::{
+  console::{ArgKind, ConsoleCommand},
+  prelude::*,
+};
+use bevy_rapier3d::{
+  na::{Isometry3, Translation3, UnitQuaternion, Vector3},
+  prelude::*,
+  rapier::{
+    geometry::{ColliderHandle, InteractionGroups},
+    parry::query::{TOIStatus, TOI},
+    pipeline::QueryPipeline,
+  },
+};
+
+pub const RAPIER_VOLUME_GROUP: u32 = 2;
+
+/// Gap kept between the body and the ground when casting, so resting contacts are still found.
+pub const SKIN: f32 = 0.05;
+/// How quickly the head moves to its crouched or standing height.
+const CROUCH_TRANSITION_SPEED: f32 = 10.;
+/// Seconds before a ladder can be grabbed again after jumping off it.
+const LADDER_DETACH_TIME: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementMode {
+  Walk,
+  Swim,
+  Ladder,
+}
+
+impl Default for MovementMode {
+  fn default() -> Self {
+    MovementMode::Walk
+  }
+}
+
+/// A sensor that switches the player into another movement mode while they are inside it.
+pub struct MovementVolume(pub MovementMode);
+
+/// Colliders the player stands on and bumps into. Queries count as part of the player, so
+/// colliders that don't collide with the player, like a carried object, are skipped too.
+pub fn solid_groups() -> InteractionGroups {
+  InteractionGroups::new(
+    RAPIER_PLAYER_GROUP,
+    u32::MAX ^ RAPIER_PLAYER_GROUP ^ RAPIER_VOLUME_GROUP,
+  )
+}
+
+pub fn cast(
+  pipeline: &QueryPipeline,
+  colliders: &QueryPipelineColliderComponentsSet,
+  shape: &ColliderShape,
+  from: Vec3,
+  direction: Vec3,
+  max_toi: f32,
+) -> Option<(ColliderHandle, TOI)> {
+  pipeline.cast_shape(
+    colliders,
+    &Isometry3::translation(from.x, from.y, from.z),
+    &direction.to_na_vector3(),
+    &**shape,
+    max_toi,
+    solid_groups(),
+    None,
+  )
+}
+
+/// World space normal of the surface that was hit.
+pub fn hit_normal(
+  collider_position_query: &Query<&ColliderPosition>,
+  hit: &(ColliderHandle, TOI),
+) -> Vec3 {
+  let (handle, toi) = hit;
+  match (toi.status, collider_position_query.get(handle.entity())) {
+    (TOIStatus::Penetrating, _) | (_, Err(_)) => Vec3::Y,
+    (_, Ok(position)) => (position.0.rotation * toi.normal1.into_inner()).to_glam_vec3(),
+  }
+}
+
+/// Shape casts the body downwards to find out whether it is standing on walkable ground, and
+/// how fast that ground is moving.
+pub fn detect_ground(
+  mut controller: ResMut<CharacterController>,
+  pipeline: Res<QueryPipeline>,
+  collider_query: QueryPipelineColliderComponentsQuery,
+  collider_position_query: Query<&ColliderPosition>,
+  collider_parent_query: Query<&ColliderParent>,
+  ground_body_query: Query<(&RigidBodyVelocity, &RigidBodyMassProps)>,
+  body_query: Query<&RigidBodyPosition, With<BodyTag>>,
+) {
+  let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+  for position in body_query.iter() {
+    // Slightly narrower than the body, so walls beside it don't count as ground
+    let half_extents = controller.half_extents();
+    let shape = ColliderShape::cuboid(half_extents.x * 0.9, half_extents.y, half_extents.z * 0.9);
+    let from = position.position.translation.vector.to_glam_vec3() + Vec3::Y * SKIN;
+    let hit = cast(&pipeline, &colliders, &shape, from, -Vec3::Y, SKIN * 2.);
+
+    controller.ground_normal = hit
+      .map(|hit| hit_normal(&collider_position_query, &hit))
+      .unwrap_or(Vec3::ZERO);
+    controller.grounded = controller.is_walkable(controller.ground_normal);
+
+    // Velocity of the ground at the body, including its spin
+    let ground_body = hit.and_then(|(handle, _)| collider_parent_query.get(handle.entity()).ok());
+    controller.ground_velocity =
+      match ground_body.and_then(|parent| ground_body_query.get(parent.handle.entity()).ok()) {
+        Some((velocity, mass_props)) if controller.grounded => {
+          let arm = position.position.translation.vector - mass_props.world_com.coords;
+          (velocity.linvel + velocity.angvel.cross(&arm)).to_glam_vec3()
+        }
+        _ => Vec3::ZERO,
+      };
+    if controller.grounded {
+      controller.jumping = false;
+    }
+  }
+}
+
+/// Switches the movement mode when the body enters or leaves a `MovementVolume`.
+pub fn detect_volumes(
+  time: Res<Time>,
+  mut controller: ResMut<CharacterController>,
+  pipeline: Res<QueryPipeline>,
+  collider_query: QueryPipelineColliderComponentsQuery,
+  volume_query: Query<&MovementVolume>,
+  mut body_query: Query<(&RigidBodyPosition, &mut RigidBodyForces), With<BodyTag>>,
+) {
+  let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+  let groups = InteractionGroups::all().with_filter(RAPIER_VOLUME_GROUP);
+  controller.ladder_detach = (controller.ladder_detach - time.delta_seconds()).max(0.);
+
+  for (position, mut forces) in body_query.iter_mut() {
+    let volume_mode = |handle: ColliderHandle| {
+      volume_query
+        .get(handle.entity())
+        .map(|volume| volume.0)
+        .unwrap_or_default()
+    };
+
+    let mut mode = MovementMode::Walk;
+    // Swimming starts once the middle of the body is under water
+    let center = position.position.translation.vector.into();
+    pipeline.intersections_with_point(&colliders, &center, groups, None, |handle| {
+      if volume_mode(handle) == MovementMode::Swim {
+        mode = MovementMode::Swim;
+      }
+      true
+    });
+    // Ladders are grabbed as soon as the body touches them
+    if controller.ladder_detach <= 0. {
+      let half_extents = controller.half_extents();
+      let shape = ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z);
+      pipeline.intersections_with_shape(
+        &colliders,
+        &position.position,
+        &*shape,
+        groups,
+        None,
+        |handle| {
+          if volume_mode(handle) == MovementMode::Ladder {
+            mode = MovementMode::Ladder;
+          }
+          true
+        },
+      );
+    }
+
+    if controller.mode != mode {
+      controller.mode = mode;
+      forces.gravity_scale = if mode == MovementMode::Walk { 1. } else { 0. };
+    }
+  }
+}
+
+/// Called when jumping off a ladder, so the player doesn't immediately grab it again.
+pub fn detach_from_ladder(controller: &mut CharacterController) {
+  controller.ladder_detach = LADDER_DETACH_TIME;
+}
+
+/// Lifts the body onto ledges lower than the step height that it is walking into.
+pub fn step_up(
+  controller: Res<CharacterController>,
+  pipeline: Res<QueryPipeline>,
+  collider_query: QueryPipelineColliderComponentsQuery,
+  collider_position_query: Query<&ColliderPosition>,
+  mut body_query: Query<(&mut RigidBodyPosition, &mut RigidBodyVelocity), With<BodyTag>>,
+) {
+  if controller.fly
+    || controller.kinematic
+    || controller.mode != MovementMode::Walk
+    || !controller.grounded
+  {
+    return;
+  }
+  let horizontal = controller.velocity * Vec3::new(1., 0., 1.);
+  if horizontal.length_squared() < 1E-6 || controller.step_height <= 0. {
+    return;
+  }
+  let direction = horizontal.normalize();
+  let distance = horizontal.length() * controller.dt + SKIN * 2.;
+
+  let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+  // Slightly narrower than the body, so casts don't start out touching the wall being climbed
+  let half_extents = controller.half_extents();
+  let shape = ColliderShape::cuboid(half_extents.x - SKIN, half_extents.y, half_extents.z - SKIN);
+
+  for (mut position, mut velocity) in body_query.iter_mut() {
+    let feet = position.position.translation.vector.to_glam_vec3() + Vec3::Y * SKIN;
+
+    // Only obstacles that are too steep to walk up need a step
+    match cast(&pipeline, &colliders, &shape, feet, direction, distance) {
+      Some(hit) if !controller.is_walkable(hit_normal(&collider_position_query, &hit)) => {}
+      _ => continue,
+    }
+
+    // There has to be room above the obstacle
+    let raised = feet + Vec3::Y * controller.step_height;
+    if cast(
+      &pipeline,
+      &colliders,
+      &shape,
+      feet,
+      Vec3::Y,
+      controller.step_height,
+    )
+    .is_some()
+      || cast(&pipeline, &colliders, &shape, raised, direction, distance).is_some()
+    {
+      continue;
+    }
+
+    // Find the top of the step
+    let ahead = raised + direction * distance;
+    let hit = match cast(
+      &pipeline,
+      &colliders,
+      &shape,
+      ahead,
+      -Vec3::Y,
+      controller.step_height,
+    ) {
+      Some(hit) if controller.is_walkable(hit_normal(&collider_position_query, &hit)) => hit,
+      _ => continue,
+    };
+    let rise = controller.step_height - hit.1.toi;
+    if rise > SKIN {
+      position.position.translation.vector.y += rise;
+      position.next_position = position.position;
+      velocity.linvel.y = velocity.linvel.y.max(0.);
+    }
+  }
+}
+
+/// Shrinks the body while the crouch input is held, and moves the head down with it.
+pub fn crouch(
+  mut commands: Commands,
+  time: Res<Time>,
+  mut controller: ResMut<CharacterController>,
+  pipeline: Res<QueryPipeline>,
+  collider_query: QueryPipelineColliderComponentsQuery,
+  mut body_query: Query<(Entity, &mut RigidBodyPosition), With<BodyTag>>,
+  mut model_query: Query<&mut Transform, (With<BodyModelTag>, Without<HeadTag>)>,
+  mut head_query: Query<&mut Transform, (With<HeadTag>, Without<BodyModelTag>)>,
+) {
+  let colliders = QueryPipelineColliderComponentsSet(&collider_query);
+  let wants_crouch =
+    controller.wants_crouch && controller.mode == MovementMode::Walk && !controller.fly;
+
+  if wants_crouch != controller.crouched {
+    for (entity, mut position) in body_query.iter_mut() {
+      // On the ground the feet stay put, in the air the legs are pulled up instead
+      let shift = if controller.grounded {
+        (controller.height - controller.crouch_height) / 2.
+      } else {
+        0.
+      };
+
+      if !wants_crouch {
+        // Only stand up if there is room to
+        let standing = ColliderShape::cuboid(
+          controller.half_width,
+          controller.height / 2. - SKIN,
+          controller.half_width,
+        );
+        let mut standing_position = position.position;
+        standing_position.translation.vector.y += shift;
+        if pipeline
+          .intersection_with_shape(
+            &colliders,
+            &standing_position,
+            &*standing,
+            solid_groups(),
+            None,
+          )
+          .is_some()
+        {
+          continue;
+        }
+      }
+
+      position.position.translation.vector.y += if wants_crouch { -shift } else { shift };
+      position.next_position = position.position;
+      controller.crouched = wants_crouch;
+
+      let half_extents = controller.half_extents();
+      commands.entity(entity).insert(ColliderShape::cuboid(
+        half_extents.x,
+        half_extents.y,
+        half_extents.z,
+      ));
+    }
+  }
+
+  let half_extents = controller.half_extents();
+  for mut transform in model_query.iter_mut() {
+    transform.scale = half_extents;
+  }
+  let head_offset = spawn::head_offset(&controller);
+  let t = (CROUCH_TRANSITION_SPEED * time.delta_seconds()).min(1.);
+  for mut transform in head_query.iter_mut() {
+    transform.translation.y += (head_offset - transform.translation.y) * t;
+  }
+}
+
+/// Collider for a `MovementVolume`, which only the player's movement queries see.
+pub fn volume_collider(position: Isometry3<f32>, half_extents: Vec3) -> ColliderBundle {
+  ColliderBundle {
+    collider_type: ColliderType::Sensor,
+    shape: ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
+    position: position.into(),
+    flags: ColliderFlags {
+      collision_groups: InteractionGroups::all().with_memberships(RAPIER_VOLUME_GROUP),
+      ..Default::default()
+    },
+    ..Default::default()
+  }
+}
+
+fn volume_modes(_world: &mut World) -> Vec<String> {
+  vec!["swim".to_owned(), "ladder".to_owned()]
+}
+
+pub fn volume_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "volume",
+    "Places a swim or ladder volume where you are looking",
+    |world, args| {
+      let mode = match args.text(0).unwrap() {
+        "swim" => MovementMode::Swim,
+        "ladder" => MovementMode::Ladder,
+        other => anyhow::bail!("unknown volume {}, expected swim or ladder", other),
+      };
+      let size = args.float(1).unwrap_or(4.).max(0.1);
+      let view_info = world.get_resource::<ViewInfo>().unwrap();
+      let center = view_info
+        .hit_point()
+        .unwrap_or_else(|| view_info.ray.point_at(size))
+        + Vector3::new(0., size / 2., 0.);
+      let position = Isometry3::from_parts(
+        Translation3::from(center.coords),
+        UnitQuaternion::identity(),
+      );
+
+      world
+        .spawn()
+        .insert_bundle(volume_collider(position, Vec3::splat(size / 2.)))
+        .insert_bundle((
+          MovementVolume(mode),
+          Name::new(format!("{:?} volume", mode).to_lowercase()),
+        ));
+      Ok(Some(format!("placed {:?} volume", mode).to_lowercase()))
+    },
+  )
+  .arg("kind", ArgKind::Choice(volume_modes))
+  .optional_arg("size", ArgKind::Float)
+}
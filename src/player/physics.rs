@@ -1,17 +1,25 @@
-use super::{controller::*, events::*};
+use super::{
+  controller::*,
+  events::*,
+  spawn::{self, Player},
+};
 use crate::prelude::*;
 
 use bevy_rapier3d::{
   na::Vector3,
   physics::RigidBodyHandleComponent,
   prelude::*,
-  rapier::{dynamics::BodyStatus, math::Vector},
+  rapier::{dynamics::BodyStatus, geometry::InteractionGroups, math::Vector},
 };
 
 pub fn body_to_velocity(
   mut query: Query<&RigidBodyVelocity, With<BodyTag>>,
   mut controller: ResMut<CharacterController>,
 ) {
+  // The kinematic controller keeps track of its own velocity
+  if controller.kinematic {
+    return;
+  }
   for velocity in query.iter_mut() {
     controller.velocity = velocity.linvel.into();
   }
@@ -37,7 +45,7 @@ pub fn controller_to_rapier_dynamic_impulse(
 
   if impulse.length_squared() > 1E-6 {
     for (mass_props, mut velocity, mut body_type, mut activation) in query.iter_mut() {
-      if !controller.fly {
+      if !controller.fly && !controller.kinematic {
         *body_type = BodyStatus::Dynamic;
         velocity.apply_impulse(mass_props, impulse.into());
         activation.wake_up(true);
@@ -69,3 +77,25 @@ pub fn controller_to_fly(
     }
   }
 }
+
+/// Turns collisions with the player's body off while noclip is on.
+pub fn apply_noclip(
+  controller: Res<CharacterController>,
+  player: Res<Player>,
+  mut flags_query: Query<&mut ColliderFlags>,
+) {
+  if !controller.is_changed() {
+    return;
+  }
+  if let Ok(mut flags) = flags_query.get_mut(player.body) {
+    let (collision_groups, solver_groups) = if controller.noclip {
+      (InteractionGroups::none(), InteractionGroups::none())
+    } else {
+      (spawn::player_collision_groups(), InteractionGroups::all())
+    };
+    if flags.collision_groups != collision_groups {
+      flags.collision_groups = collision_groups;
+      flags.solver_groups = solver_groups;
+    }
+  }
+}
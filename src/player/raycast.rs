@@ -1,5 +1,6 @@
 use super::{
   look::LookDirection,
+  movement::RAPIER_VOLUME_GROUP,
   spawn::{Player, RAPIER_PLAYER_GROUP},
 };
 use crate::prelude::*;
@@ -64,7 +65,7 @@ pub fn compute_view_info(
       &view_info.ray,
       f32::MAX,
       true,
-      InteractionGroups::all().with_filter(u32::MAX ^ RAPIER_PLAYER_GROUP),
+      InteractionGroups::all().with_filter(u32::MAX ^ RAPIER_PLAYER_GROUP ^ RAPIER_VOLUME_GROUP),
       None,
     )
     .map(|(collider_handle, intersection)| {
@@ -1,5 +1,8 @@
 use super::{controller, look};
-use crate::prelude::*;
+use crate::{
+  health::{Health, PLAYER_MAX_HEALTH},
+  prelude::*,
+};
 use bevy::render::camera::PerspectiveProjection;
 use bevy_rapier3d::{prelude::*, rapier::geometry::InteractionGroups};
 
@@ -9,15 +12,38 @@ pub struct Player {
   pub camera: Entity,
 }
 
+/// Where the player respawns. With several, the player takes turns between them.
+pub struct SpawnPoint;
+
 pub const RAPIER_PLAYER_GROUP: u32 = 1;
 
-pub fn spawn_character(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
-  let height = 3.0;
-  let head_scale = 0.3;
+const HEAD_SCALE: f32 = 0.3;
+
+pub fn player_collision_groups() -> InteractionGroups {
+  InteractionGroups::all().with_memberships(RAPIER_PLAYER_GROUP)
+}
+
+/// Height of the head above the middle of the body, which drops while crouched.
+pub fn head_offset(controller: &controller::CharacterController) -> f32 {
+  let standing = 0.5 * HEAD_SCALE + controller.height - 1.695;
+  standing - (controller.height / 2. - controller.half_extents().y)
+}
+
+/// Where the body starts out, and respawns if there are no spawn points.
+pub fn start_position(controller: &controller::CharacterController) -> Vec3 {
+  Vec3::new(0., controller.height * 3., 5.5)
+}
+
+pub fn spawn_character(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  controller: Res<controller::CharacterController>,
+) {
+  let half_extents = controller.half_extents();
 
   let rigid_body = RigidBodyBundle {
     body_type: BodyStatus::Dynamic,
-    position: vector![0., height * 3., 5.5].into(),
+    position: start_position(&controller).to_na_vector3().into(),
     mass_properties: RigidBodyMassProps {
       flags: RigidBodyMassPropsFlags::ROTATION_LOCKED,
       ..Default::default()
@@ -26,10 +52,16 @@ pub fn spawn_character(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>)
   };
 
   let collider = ColliderBundle {
-    shape: ColliderShape::cuboid(1.0, height / 2., 1.0),
+    shape: ColliderShape::cuboid(half_extents.x, half_extents.y, half_extents.z),
     mass_properties: ColliderMassProps::Density(1.0),
+    // The controller handles slowing down, and friction would let the body cling to walls
+    material: ColliderMaterial {
+      friction: 0.0,
+      friction_combine_rule: CoefficientCombineRule::Min,
+      ..Default::default()
+    },
     flags: ColliderFlags {
-      collision_groups: InteractionGroups::all().with_memberships(RAPIER_PLAYER_GROUP),
+      collision_groups: player_collision_groups(),
       ..Default::default()
     },
     ..Default::default()
@@ -38,6 +70,7 @@ pub fn spawn_character(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>)
   let mut body = commands
     .spawn_bundle((
       controller::BodyTag,
+      Health::new(PLAYER_MAX_HEALTH),
       Transform::identity(),
       GlobalTransform::identity(),
       Name::new("player body"),
@@ -53,13 +86,13 @@ pub fn spawn_character(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>)
     .spawn_bundle(PbrBundle {
       mesh: cube,
       transform: Transform::from_matrix(Mat4::from_scale_rotation_translation(
-        Vec3::new(1.0, height / 2., 1.0),
+        half_extents,
         Quat::IDENTITY,
         Vec3::ZERO,
       )),
       ..Default::default()
     })
-    .insert(Name::new("player body model"))
+    .insert_bundle((controller::BodyModelTag, Name::new("player body model")))
     .id();
 
   let yaw = commands
@@ -78,7 +111,7 @@ pub fn spawn_character(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>)
       Transform::from_matrix(Mat4::from_scale_rotation_translation(
         Vec3::ONE,
         Quat::from_rotation_y(0.),
-        Vec3::new(0.0, 0.5 * head_scale + height - 1.695, 0.0),
+        Vec3::new(0.0, head_offset(&controller), 0.0),
       )),
       Name::new("player head"),
     ))
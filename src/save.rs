@@ -0,0 +1,258 @@
+use crate::{
+  console::{AppConsoleExt, ArgKind, ConsoleCommand},
+  models::{self, ModelInfo, ModelInstance, ModelParams},
+  prelude::*,
+  scripts::{
+    pymod::{ScriptOutputEvent, ScriptOutputLevel},
+    script::{SaveScriptStateEvent, Script},
+  },
+  tools::Frozen,
+};
+use anyhow::bail;
+use bevy_rapier3d::{
+  na::Isometry3,
+  prelude::{RigidBodyPosition, RigidBodyType},
+  rapier::dynamics::BodyStatus,
+};
+use serde::{Deserialize, Serialize};
+
+const SAVES_DIR: &str = "saves";
+const DEFAULT_SAVE: &str = "quicksave";
+
+/// A spawned model as stored in a save file.
+#[derive(Serialize, Deserialize)]
+struct SavedModel {
+  /// Name of the model, see `ModelInfo::name`.
+  model: String,
+  position: Isometry3<f32>,
+  body_status: BodyStatus,
+  #[serde(default)]
+  script: Option<Script>,
+}
+
+/// The props placed by the player, i.e. all models except the static ones the map spawned.
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+  models: Vec<SavedModel>,
+}
+
+pub struct SaveWorldEvent {
+  pub name: String,
+}
+
+pub struct LoadWorldEvent {
+  pub name: String,
+}
+
+fn save_path(name: &str) -> anyhow::Result<String> {
+  if name.is_empty() || name.contains(|c| c == '/' || c == '\\') || name.starts_with('.') {
+    bail!("'{}' is not a valid save name", name);
+  }
+  Ok(format!("{}/{}.json", SAVES_DIR, name))
+}
+
+fn write_save(name: &str, save: &SaveFile) -> anyhow::Result<()> {
+  let path = save_path(name)?;
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    std::fs::create_dir_all(SAVES_DIR)?;
+    std::fs::write(path, serde_json::to_string_pretty(save)?)?;
+    return Ok(());
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  bail!("saving isn't supported on the web");
+}
+
+fn read_save(name: &str) -> anyhow::Result<SaveFile> {
+  let path = save_path(name)?;
+  #[cfg(not(target_arch = "wasm32"))]
+  return Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?);
+
+  #[cfg(target_arch = "wasm32")]
+  bail!("loading isn't supported on the web");
+}
+
+/// Names of the existing saves.
+fn save_names(_world: &mut World) -> Vec<String> {
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    let mut names = std::fs::read_dir(SAVES_DIR)
+      .map(|entries| {
+        entries
+          .filter_map(|entry| entry.ok())
+          .filter_map(|entry| entry.file_name().into_string().ok())
+          .filter_map(|name| name.strip_suffix(".json").map(|name| name.to_owned()))
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_default();
+    names.sort();
+    names
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  vec![]
+}
+
+pub(crate) fn print(output_events: &mut Events<ScriptOutputEvent>, result: anyhow::Result<String>) {
+  output_events.send(match result {
+    Ok(output) => ScriptOutputEvent {
+      output: output + "\n",
+      level: ScriptOutputLevel::Output,
+    },
+    Err(e) => ScriptOutputEvent {
+      output: format!("{}\n", e),
+      level: ScriptOutputLevel::Error,
+    },
+  });
+}
+
+/// Runs after `Update` so the scripts have already snapshotted their state.
+fn save_world(
+  mut events: EventReader<SaveWorldEvent>,
+  mut output_events: ResMut<Events<ScriptOutputEvent>>,
+  query: Query<(
+    &ModelInstance,
+    &RigidBodyPosition,
+    &RigidBodyType,
+    Option<&Frozen>,
+    Option<&Script>,
+  )>,
+  model_query: Query<&ModelInfo>,
+) {
+  for SaveWorldEvent { name } in events.iter() {
+    let models = query
+      .iter()
+      .filter(|(_, _, body_status, frozen, _)| {
+        **body_status == BodyStatus::Dynamic || frozen.is_some()
+      })
+      .filter_map(|(instance, position, body_status, _, script)| {
+        Some(SavedModel {
+          model: model_query.get(instance.0).ok()?.name.clone(),
+          position: position.position,
+          body_status: *body_status,
+          script: script.cloned(),
+        })
+      })
+      .collect::<Vec<_>>();
+    let count = models.len();
+    let result =
+      write_save(name, &SaveFile { models }).map(|_| format!("saved {} models to {}", count, name));
+    print(&mut output_events, result);
+  }
+}
+
+fn load_world(
+  mut commands: Commands,
+  mut events: EventReader<LoadWorldEvent>,
+  mut output_events: ResMut<Events<ScriptOutputEvent>>,
+  instance_query: Query<(Entity, &RigidBodyType, Option<&Frozen>), With<ModelInstance>>,
+  model_query: Query<(Entity, &ModelInfo, &ModelParams, &Handle<Scene>)>,
+) {
+  for LoadWorldEvent { name } in events.iter() {
+    let save = match read_save(name) {
+      Ok(save) => save,
+      Err(e) => {
+        print(&mut output_events, Err(e));
+        continue;
+      }
+    };
+
+    for (entity, body_status, frozen) in instance_query.iter() {
+      if *body_status == BodyStatus::Dynamic || frozen.is_some() {
+        commands.entity(entity).despawn_recursive();
+      }
+    }
+
+    let mut missing = vec![];
+    let count = save.models.len();
+    for saved in save.models {
+      let model = model_query
+        .iter()
+        .find(|(_, model_info, _, _)| model_info.name == saved.model);
+      let (model, model_info, params, scene_handle) = match model {
+        Some(model) => model,
+        None => {
+          missing.push(saved.model);
+          continue;
+        }
+      };
+      let entity = models::spawn_model(
+        &mut commands,
+        model,
+        (model_info, params, scene_handle),
+        &saved.position,
+        saved.body_status,
+      );
+      if saved.body_status != BodyStatus::Dynamic {
+        commands.entity(entity).insert(Frozen);
+      }
+      if let Some(script) = saved.script {
+        commands.entity(entity).insert(script);
+      }
+    }
+
+    let result = if missing.is_empty() {
+      Ok(format!("loaded {} models from {}", count, name))
+    } else {
+      Err(anyhow::anyhow!(
+        "loaded {} models from {}, these models aren't loaded: {}",
+        count - missing.len(),
+        name,
+        missing.join(", ")
+      ))
+    };
+    print(&mut output_events, result);
+  }
+}
+
+fn save_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "save",
+    "Saves the props you placed and their scripts' state",
+    |world, args| {
+      let name = args.text(0).unwrap_or(DEFAULT_SAVE).to_owned();
+      save_path(&name)?;
+      world
+        .get_resource_mut::<Events<SaveScriptStateEvent>>()
+        .unwrap()
+        .send(SaveScriptStateEvent);
+      world
+        .get_resource_mut::<Events<SaveWorldEvent>>()
+        .unwrap()
+        .send(SaveWorldEvent { name });
+      Ok(None)
+    },
+  )
+  .optional_arg("name", ArgKind::Choice(save_names))
+}
+
+fn load_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "load",
+    "Replaces the props you placed with those of a save",
+    |world, args| {
+      let name = args.text(0).unwrap_or(DEFAULT_SAVE).to_owned();
+      save_path(&name)?;
+      world
+        .get_resource_mut::<Events<LoadWorldEvent>>()
+        .unwrap()
+        .send(LoadWorldEvent { name });
+      Ok(None)
+    },
+  )
+  .optional_arg("name", ArgKind::Choice(save_names))
+}
+
+pub struct SavePlugin;
+impl Plugin for SavePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_event::<SaveWorldEvent>()
+      .add_event::<LoadWorldEvent>()
+      .add_system(load_world.system())
+      .add_system_to_stage(CoreStage::PostUpdate, save_world.system())
+      .add_console_command(save_command())
+      .add_console_command(load_command());
+  }
+}
@@ -0,0 +1,188 @@
+use super::{
+  sandbox::{self, ScriptLimits, ScriptTrust},
+  world_guard::WorldGuard,
+  PyInterpreter,
+};
+use crate::prelude::*;
+use bevy::app::ManualEventReader;
+use rustpython_vm::{
+  builtins::PyStrRef, compile::Mode, scope::Scope, ItemProtocol, PyResult, TryFromObject,
+  VirtualMachine,
+};
+
+/// Asks for `code` to be compiled (but not run) so syntax errors can be shown while editing.
+pub struct CheckScriptEvent {
+  pub code: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ScriptDiagnostic {
+  /// Zero-based line of the error.
+  pub row: usize,
+  /// Zero-based character offset of the error within its line.
+  pub column: usize,
+  pub message: String,
+}
+
+/// Diagnostics for the code of the most recent `CheckScriptEvent`.
+#[derive(Default)]
+pub struct ScriptDiagnostics(pub Vec<ScriptDiagnostic>);
+
+/// Where the most recently reported exception was raised.
+#[derive(Clone, Debug)]
+pub struct ScriptError {
+  /// The filename the code was compiled with, e.g. an asset path like `scripts/thruster.py`.
+  pub filename: String,
+  /// Zero-based line that raised the exception.
+  pub row: usize,
+  pub message: String,
+}
+
+#[derive(Default)]
+pub struct LastScriptError(pub Option<ScriptError>);
+
+/// Finds the innermost frame of a formatted traceback, i.e. the last `File "...", line N` entry.
+pub(super) fn parse_traceback(traceback: &str) -> Option<ScriptError> {
+  let (filename, line) = traceback.lines().rev().find_map(|line| {
+    let rest = line.trim_start().strip_prefix("File \"")?;
+    let (filename, rest) = rest.split_at(rest.find('"')?);
+    let line = rest.strip_prefix("\", line ")?;
+    let digits = line
+      .chars()
+      .take_while(|c| c.is_ascii_digit())
+      .collect::<String>();
+    Some((filename.to_owned(), digits.parse::<usize>().ok()?))
+  })?;
+  let message = traceback
+    .lines()
+    .rev()
+    .find(|line| !line.trim().is_empty())
+    .unwrap_or("")
+    .to_owned();
+  Some(ScriptError {
+    filename,
+    row: line.saturating_sub(1),
+    message,
+  })
+}
+
+/// Asks for the names available on `path`, a dotted name like `world.player`. An empty path
+/// means the names available in the global scope.
+pub struct CompletionRequestEvent {
+  pub path: String,
+}
+
+/// Completions for the most recent `CompletionRequestEvent`.
+#[derive(Default)]
+pub struct ScriptCompletions {
+  pub path: Option<String>,
+  pub names: Vec<String>,
+}
+
+#[derive(Default)]
+pub(super) struct IntrospectionEventReaders {
+  check: ManualEventReader<CheckScriptEvent>,
+  completion: ManualEventReader<CompletionRequestEvent>,
+}
+
+const COMPLETION_HELPERS: &'static str = r#"
+import builtins, crateton
+
+def completions(scope, path):
+  try:
+    if not path:
+      names = set(scope.keys()) | set(dir(builtins)) | set(dir(crateton))
+    else:
+      parts = path.split(".")
+      obj = scope[parts[0]] if parts[0] in scope else getattr(builtins, parts[0])
+      for part in parts[1:]:
+        obj = getattr(obj, part)
+      names = set(dir(obj))
+  except Exception:
+    return ""
+  return ",".join(sorted(name for name in names if not name.startswith("_")))
+"#;
+
+pub(super) fn create_completion_helpers(vm: &VirtualMachine) -> PyResult<Scope> {
+  let scope = vm.new_scope_with_builtins();
+  let code_obj = vm
+    .compile(COMPLETION_HELPERS, Mode::Exec, "<completions>".to_owned())
+    .map_err(|err| vm.new_syntax_error(&err))?;
+  vm.run_code_obj(code_obj, scope.clone())?;
+  Ok(scope)
+}
+
+fn completions(vm: &VirtualMachine, py: &PyInterpreter, path: &str) -> PyResult<Vec<String>> {
+  let completions = py.completion_helpers.globals.get_item("completions", vm)?;
+  let names = vm.invoke(&completions, (py.scope.globals.clone(), path.to_owned()))?;
+  Ok(
+    PyStrRef::try_from_object(vm, names)?
+      .as_ref()
+      .split(',')
+      .filter(|name| !name.is_empty())
+      .map(String::from)
+      .collect(),
+  )
+}
+
+pub(super) fn introspect_scripts(world: &mut World) {
+  let (code, path) = world.resource_scope(|world, mut readers: Mut<IntrospectionEventReaders>| {
+    let code = readers
+      .check
+      .iter(world.get_resource::<Events<CheckScriptEvent>>().unwrap())
+      .last()
+      .map(|event| event.code.clone());
+    let path = readers
+      .completion
+      .iter(
+        world
+          .get_resource::<Events<CompletionRequestEvent>>()
+          .unwrap(),
+      )
+      .last()
+      .map(|event| event.path.clone());
+    (code, path)
+  });
+  if code.is_none() && path.is_none() {
+    return;
+  }
+
+  let limits = world.get_resource::<ScriptLimits>().unwrap().clone();
+  let py = world.remove_non_send::<PyInterpreter>().unwrap();
+  let (diagnostics, names) = py.interpreter.enter(|vm| {
+    let diagnostics = code.map(
+      |code| match vm.compile(&code, Mode::Exec, "<editor>".to_owned()) {
+        Ok(_) => vec![],
+        Err(err) => vec![ScriptDiagnostic {
+          row: err.location.row().saturating_sub(1),
+          column: err.location.column().saturating_sub(1),
+          message: err.error.to_string(),
+        }],
+      },
+    );
+
+    // Looking up attributes can run user-defined properties, so they need the world and a budget
+    let names = path.as_ref().map(|path| {
+      WorldGuard::scope(world, || {
+        sandbox::run_sandboxed(vm, &py.sandbox, ScriptTrust::Trusted, &limits, || {
+          completions(vm, &py, path)
+        })
+        .and_then(|names| names)
+        .unwrap_or_default()
+      })
+    });
+
+    (diagnostics, names)
+  });
+  world.insert_non_send(py);
+
+  if let Some(diagnostics) = diagnostics {
+    world.insert_resource(ScriptDiagnostics(diagnostics));
+  }
+  if let (Some(path), Some(names)) = (path, names) {
+    world.insert_resource(ScriptCompletions {
+      path: Some(path),
+      names,
+    });
+  }
+}
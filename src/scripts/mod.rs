@@ -8,11 +8,18 @@ use rustpython_vm::{
 use vm::{builtins::PyNone, scope::Scope, Interpreter};
 
 use pymod::{
-  crateton_pymod::{CStdout, CWorld},
-  ScriptOutputEvent,
+  crateton_pymod::{COutput, CWorld},
+  ScriptOutputEvent, ScriptOutputLevel,
 };
+use sandbox::{ScriptLimits, ScriptTrust};
+use script::{PythonSource, PythonSourceLoader, SaveScriptStateEvent, ScriptContexts};
+use world_guard::WorldGuard;
 
+pub mod introspection;
 pub mod pymod;
+pub mod sandbox;
+pub mod script;
+pub mod world_guard;
 
 // const SCRIPT: &'static str = r#"
 // # print(world.entity_with_name("player body").transform().position().to_list())
@@ -21,48 +28,108 @@ pub mod pymod;
 struct PyInterpreter {
   interpreter: Interpreter,
   scope: Scope,
+  sandbox: sandbox::Sandbox,
+  state_helpers: Scope,
+  completion_helpers: Scope,
+  contexts: ScriptContexts,
 }
 
 pub struct RunScriptEvent {
   pub code: String,
+  /// `Mode::Single` echoes the `repr` of expression results like the Python REPL does.
+  pub mode: Mode,
+  pub trust: ScriptTrust,
+  /// Name shown in tracebacks, e.g. the asset path of the file being run.
+  pub filename: String,
 }
 
 #[derive(Default)]
 struct RunScriptEventReader(ManualEventReader<RunScriptEvent>);
 
+fn format_exception(vm: &vm::VirtualMachine, exc: vm::builtins::PyBaseExceptionRef) -> String {
+  let mut error_text = String::new();
+  vm::exceptions::write_exception(&mut error_text, vm, &exc).unwrap();
+  error_text
+}
+
+/// Sends a Python exception's traceback to the terminal, falling back to the log if the world
+/// isn't available.
+fn report_exception(vm: &vm::VirtualMachine, exc: vm::builtins::PyBaseExceptionRef) {
+  let output = format_exception(vm, exc);
+  let sent = WorldGuard::with(vm, |world| {
+    if let Some(error) = introspection::parse_traceback(&output) {
+      world.insert_resource(introspection::LastScriptError(Some(error)));
+    }
+
+    let mut events = world
+      .get_resource_mut::<Events<ScriptOutputEvent>>()
+      .unwrap();
+    events.send(ScriptOutputEvent {
+      output: output.clone(),
+      level: ScriptOutputLevel::Error,
+    });
+  });
+  if sent.is_err() {
+    warn!("Python error: {}", output);
+  }
+}
+
 fn run_scripts(world: &mut World) {
-  let (py, mut event_reader, events) = unsafe {
-    (
-      world
-        .get_non_send_resource_unchecked_mut::<PyInterpreter>()
-        .unwrap(),
-      world
-        .get_resource_unchecked_mut::<RunScriptEventReader>()
-        .unwrap(),
-      world.get_resource::<Events<RunScriptEvent>>().unwrap(),
-    )
-  };
+  let scripts = world.resource_scope(|world, mut event_reader: Mut<RunScriptEventReader>| {
+    let events = world.get_resource::<Events<RunScriptEvent>>().unwrap();
+    event_reader
+      .0
+      .iter(events)
+      .map(|event| {
+        (
+          event.code.clone(),
+          event.mode,
+          event.trust,
+          event.filename.clone(),
+        )
+      })
+      .collect::<Vec<_>>()
+  });
+  if scripts.is_empty() {
+    return;
+  }
+
+  // Rerunning a file clears its error, which is reported again if it still happens
+  let mut last_error = world
+    .get_resource_mut::<introspection::LastScriptError>()
+    .unwrap();
+  let rerun = last_error.0.as_ref().map_or(false, |error| {
+    scripts
+      .iter()
+      .any(|(_, _, _, filename)| *filename == error.filename)
+  });
+  if rerun {
+    last_error.0 = None;
+  }
+
+  let limits = world.get_resource::<ScriptLimits>().unwrap().clone();
 
+  // Take the interpreter out of the world so the world can be lent to Python without aliasing it
+  let py = world.remove_non_send::<PyInterpreter>().unwrap();
   py.interpreter.enter(|vm| {
-    let run_code = |code: &str| -> anyhow::Result<()> {
-      let code_obj = vm.compile(code, Mode::Exec, "<embedded>".to_owned())?;
-      let output = vm.run_code_obj(code_obj, py.scope.clone());
-      match output {
-        Ok(_) => Ok(()),
-        Err(exc) => {
-          let mut error_text = String::new();
-          vm::exceptions::write_exception(&mut error_text, vm, &exc).unwrap();
-          Err(anyhow::Error::msg(error_text))
+    let run_code =
+      |code: &str, mode: Mode, trust: ScriptTrust, filename: &str| -> vm::PyResult<()> {
+        let code_obj = py.sandbox.compile(vm, code, mode, filename, trust)?;
+        sandbox::run_sandboxed(vm, &py.sandbox, trust, &limits, || {
+          vm.run_code_obj(code_obj, py.scope.clone())
+        })?
+        .map(|_| ())
+      };
+
+    WorldGuard::scope(world, || {
+      for (code, mode, trust, filename) in scripts.iter() {
+        if let Err(exc) = run_code(code, *mode, *trust, filename) {
+          report_exception(vm, exc);
         }
       }
-    };
-
-    for event in event_reader.0.iter(&events) {
-      if let Err(e) = run_code(&event.code) {
-        warn!("Python error: {}", e);
-      }
-    }
+    });
   });
+  world.insert_non_send(py);
 }
 
 fn create_interpreter(world: &mut World) {
@@ -76,39 +143,68 @@ fn create_interpreter(world: &mut World) {
     InitParameter::Internal
   });
 
-  let scope = interpreter.enter(|vm| {
-    // Make sure crateton is imported so constructors are initialized, ie cworld.into_ref doesn't panic
-    vm.import(module_name, None, 0).unwrap();
-
-    let stdout = (CStdout {}).into_ref(vm);
-    vm.set_attr(&vm.sys_module, "stdout", stdout).unwrap();
-
-    let cworld = CWorld::new(world);
-    let cworld = cworld.into_ref(vm);
-
-    let scope = vm.new_scope_with_builtins();
-    scope
-      .globals
-      .set_item("world", cworld.clone().into(), vm)
-      .unwrap();
-
-    // Reset SIGINT handler to default so Ctrl-C exits application instead of getting caught by Python
-    const RESET_SIGINT: &'static str = r#"
+  let limits = world.get_resource::<ScriptLimits>().unwrap().clone();
+
+  // Lend the world during setup too, so that startup errors reach the terminal
+  let (scope, sandbox, state_helpers, completion_helpers) = WorldGuard::scope(world, || {
+    interpreter.enter(|vm| {
+      // Make sure crateton is imported so constructors are initialized, ie cworld.into_ref doesn't panic
+      let module = vm.import(module_name, None, 0).unwrap();
+
+      let stdout = (COutput {
+        level: ScriptOutputLevel::Output,
+      })
+      .into_ref(vm);
+      vm.set_attr(&vm.sys_module, "stdout", stdout).unwrap();
+      let stderr = (COutput {
+        level: ScriptOutputLevel::Error,
+      })
+      .into_ref(vm);
+      vm.set_attr(&vm.sys_module, "stderr", stderr).unwrap();
+
+      let cworld = (CWorld {}).into_ref(vm);
+
+      let scope = vm.new_scope_with_builtins();
+      scope
+        .globals
+        .set_item("world", cworld.clone().into(), vm)
+        .unwrap();
+      scope
+        .globals
+        .set_item("log", vm.get_attribute(module, "log").unwrap(), vm)
+        .unwrap();
+
+      // Reset SIGINT handler to default so Ctrl-C exits application instead of getting caught by Python.
+      // This runs in its own scope so that `signal` isn't left behind in the globals scripts see.
+      const RESET_SIGINT: &'static str = r#"
 import signal
 signal.signal(signal.SIGINT, signal.SIG_DFL)
-    "#;
-    if let Err(exc) = vm.run_code_obj(
-      vm.compile(RESET_SIGINT, Mode::Exec, "<embedded>".to_owned())
-        .unwrap(),
-      scope.clone(),
-    ) {
-      vm::exceptions::print_exception(vm, exc);
-    }
+      "#;
+      if let Err(exc) = vm.run_code_obj(
+        vm.compile(RESET_SIGINT, Mode::Exec, "<embedded>".to_owned())
+          .unwrap(),
+        vm.new_scope_with_builtins(),
+      ) {
+        report_exception(vm, exc);
+      }
 
-    scope
+      (
+        scope,
+        sandbox::create_sandbox(vm, &limits).unwrap(),
+        script::create_state_helpers(vm).unwrap(),
+        introspection::create_completion_helpers(vm).unwrap(),
+      )
+    })
   });
 
-  world.insert_non_send(PyInterpreter { interpreter, scope });
+  world.insert_non_send(PyInterpreter {
+    interpreter,
+    scope,
+    sandbox,
+    state_helpers,
+    completion_helpers,
+    contexts: ScriptContexts::default(),
+  });
 }
 
 pub struct ScriptsPlugin;
@@ -117,8 +213,24 @@ impl Plugin for ScriptsPlugin {
     app
       .add_startup_system(create_interpreter.exclusive_system())
       .add_system(run_scripts.exclusive_system())
+      .add_system(script::run_entity_scripts.exclusive_system())
+      .add_system(introspection::introspect_scripts.exclusive_system())
+      .add_asset::<PythonSource>()
+      .init_asset_loader::<PythonSourceLoader>()
       .init_resource::<RunScriptEventReader>()
+      .init_resource::<script::SaveScriptStateEventReader>()
+      .init_resource::<script::UseEventReader>()
+      .init_resource::<script::DamageEventReader>()
+      .init_resource::<script::BreakEventReader>()
+      .init_resource::<ScriptLimits>()
+      .init_resource::<introspection::IntrospectionEventReaders>()
+      .init_resource::<introspection::ScriptDiagnostics>()
+      .init_resource::<introspection::ScriptCompletions>()
+      .init_resource::<introspection::LastScriptError>()
       .add_event::<RunScriptEvent>()
+      .add_event::<SaveScriptStateEvent>()
+      .add_event::<introspection::CheckScriptEvent>()
+      .add_event::<introspection::CompletionRequestEvent>()
       .add_event::<ScriptOutputEvent>();
   }
 }
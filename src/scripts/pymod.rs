@@ -1,19 +1,120 @@
 use rustpython_vm::pymodule;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptOutputLevel {
+  /// Text written to `sys.stdout`, including REPL results.
+  Output,
+  Debug,
+  Info,
+  Warning,
+  /// Text written to `sys.stderr`, and tracebacks.
+  Error,
+}
+
+impl ScriptOutputLevel {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_lowercase().as_str() {
+      "debug" => Some(ScriptOutputLevel::Debug),
+      "info" => Some(ScriptOutputLevel::Info),
+      "warn" | "warning" => Some(ScriptOutputLevel::Warning),
+      "error" => Some(ScriptOutputLevel::Error),
+      _ => None,
+    }
+  }
+}
+
+/// Signatures of the functions and methods in the `crateton` module, shown as hints in the editor.
+pub const METHOD_SIGNATURES: &[(&str, &str)] = &[
+  (
+    "log",
+    "log(level: str, msg: str)  # level is debug, info, warning or error",
+  ),
+  ("to_list", "CVec3.to_list() -> [float, float, float]"),
+  ("position", "position() -> CVec3"),
+  (
+    "entity_with_name",
+    "CWorld.entity_with_name(name: str) -> CEntity",
+  ),
+  ("transform", "CEntity.transform() -> CTransform"),
+  (
+    "apply_impulse",
+    "CEntity.apply_impulse(x: float, y: float, z: float)",
+  ),
+  ("attach_script", "CEntity.attach_script(path: str)"),
+  (
+    "set_usable",
+    "CEntity.set_usable(prompt: str)  # calls on_use(user) in the entity's script when used",
+  ),
+  ("clear_usable", "CEntity.clear_usable()"),
+  (
+    "set_health",
+    "CEntity.set_health(max: float)  # breaks the entity when it runs out, calling on_break()",
+  ),
+  ("health", "CEntity.health() -> float"),
+  ("damage", "CEntity.damage(amount: float)"),
+  (
+    "set_sun_color",
+    "CEnvironment.set_sun_color(r: float, g: float, b: float)",
+  ),
+  (
+    "set_ambient_color",
+    "CEnvironment.set_ambient_color(r: float, g: float, b: float)",
+  ),
+  (
+    "set_sky_color",
+    "CEnvironment.set_sky_color(r: float, g: float, b: float)",
+  ),
+  (
+    "set_horizon_color",
+    "CEnvironment.set_horizon_color(r: float, g: float, b: float)  # makes the sky a gradient",
+  ),
+  ("clear_horizon_color", "CEnvironment.clear_horizon_color()"),
+  ("cvar", "cvar(name: str) -> int | float | bool"),
+  (
+    "set_cvar",
+    "set_cvar(name: str, value: int | float | bool | str)",
+  ),
+  ("teleport", "CPlayer.teleport(x: float, y: float, z: float)"),
+  ("look_direction", "CPlayer.look_direction() -> CVec3"),
+  ("look", "CPlayer.look(yaw: float, pitch: float)  # radians"),
+  (
+    "press",
+    "CPlayer.press(input: str)  # forward, backward, left, right, run, jump or crouch",
+  ),
+];
+
 pub struct ScriptOutputEvent {
   pub output: String,
+  pub level: ScriptOutputLevel,
 }
 
 #[pymodule]
 pub mod crateton_pymod {
-  use super::ScriptOutputEvent;
-  use crate::prelude::*;
+  use super::{ScriptOutputEvent, ScriptOutputLevel};
+  use crate::{
+    cvars::{CvarValue, Cvars},
+    environment::Environment,
+    health::{DamageEvent, Health},
+    interaction::Usable,
+    player::{
+      controller::{self, CharacterController, Perspective},
+      events::{PitchEvent, YawEvent},
+      look::{LookDirection, MouseSettings, PITCH_BOUND},
+      spawn::Player,
+    },
+    prelude::*,
+    scripts::{
+      sandbox::{self, ScriptTrust},
+      script::Script,
+      world_guard::WorldGuard,
+    },
+  };
+  use bevy_rapier3d::{na::Translation3, prelude::*};
   use rustpython_vm::{
     builtins::{PyFloat, PyList, PyStrRef, PyTypeRef},
-    pyclass, pyimpl, ItemProtocol, PyRef, PyResult, PyValue, StaticType, TryIntoRef,
-    VirtualMachine,
+    pyclass, pyimpl, PyObjectRef, PyResult, PyValue, StaticType, VirtualMachine,
   };
-  use std::{fmt, ptr::NonNull};
+  use std::fmt;
 
   macro_rules! pyvalue_impl {
     ($id:ident) => {
@@ -79,8 +180,8 @@ pub mod crateton_pymod {
   #[pyattr]
   #[pyclass(name, module = "crateton")]
   #[derive(Debug)]
-  struct CEntity {
-    entity: Entity,
+  pub struct CEntity {
+    pub entity: Entity,
   }
   pyvalue_impl!(CEntity);
 
@@ -88,77 +189,550 @@ pub mod crateton_pymod {
   impl CEntity {
     #[pymethod]
     fn transform(&self, vm: &VirtualMachine) -> PyResult<CTransform> {
-      CWorld::fetch(vm)
-        .world()
-        .get::<Transform>(self.entity)
-        .map(|transform| CTransform {
-          transform: *transform,
+      WorldGuard::with(vm, |world| {
+        world
+          .get::<Transform>(self.entity)
+          .map(|transform| CTransform {
+            transform: *transform,
+          })
+      })?
+      .ok_or_else(|| {
+        vm.new_lookup_error(format!("Entity {:?} does not have Transform", self.entity))
+      })
+    }
+
+    #[pymethod]
+    fn apply_impulse(&self, x: f64, y: f64, z: f64, vm: &VirtualMachine) -> PyResult<()> {
+      WorldGuard::with(vm, |world| {
+        let mut entity = world.get_entity_mut(self.entity)?;
+        let mass_props = entity.get::<RigidBodyMassProps>()?.clone();
+        let impulse = Vec3::new(x as f32, y as f32, z as f32);
+        entity
+          .get_mut::<RigidBodyVelocity>()?
+          .apply_impulse(&mass_props, impulse.into());
+        entity.get_mut::<RigidBodyActivation>()?.wake_up(true);
+        Some(())
+      })?
+      .ok_or_else(|| vm.new_lookup_error(format!("Entity {:?} is not a rigid body", self.entity)))
+    }
+
+    #[pymethod]
+    fn attach_script(&self, path: PyStrRef, vm: &VirtualMachine) -> PyResult<()> {
+      // A script can only attach scripts as trusted as itself
+      let script = Script::new(path.as_ref(), sandbox::current_trust());
+      WorldGuard::with(vm, |world| {
+        world.get_entity_mut(self.entity).map(|mut entity| {
+          entity.insert(script);
         })
-        .ok_or_else(|| {
-          vm.new_lookup_error(format!("Entity {:?} does not have Transform", self.entity))
+      })?
+      .ok_or_else(|| vm.new_lookup_error(format!("Entity {:?} does not exist", self.entity)))
+    }
+
+    /// Shows `prompt` under the crosshair when the player looks at the entity.
+    #[pymethod]
+    fn set_usable(&self, prompt: PyStrRef, vm: &VirtualMachine) -> PyResult<()> {
+      let usable = Usable::new(prompt.as_ref());
+      WorldGuard::with(vm, |world| {
+        world.get_entity_mut(self.entity).map(|mut entity| {
+          entity.insert(usable);
         })
+      })?
+      .ok_or_else(|| vm.new_lookup_error(format!("Entity {:?} does not exist", self.entity)))
+    }
+
+    #[pymethod]
+    fn clear_usable(&self, vm: &VirtualMachine) -> PyResult<()> {
+      WorldGuard::with(vm, |world| {
+        world.get_entity_mut(self.entity).map(|mut entity| {
+          entity.remove::<Usable>();
+        })
+      })?
+      .ok_or_else(|| vm.new_lookup_error(format!("Entity {:?} does not exist", self.entity)))
+    }
+
+    /// Gives the entity full health out of `max`.
+    #[pymethod]
+    fn set_health(&self, max: f64, vm: &VirtualMachine) -> PyResult<()> {
+      WorldGuard::with(vm, |world| {
+        world.get_entity_mut(self.entity).map(|mut entity| {
+          entity.insert(Health::new(max as f32));
+        })
+      })?
+      .ok_or_else(|| vm.new_lookup_error(format!("Entity {:?} does not exist", self.entity)))
+    }
+
+    #[pymethod]
+    fn health(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      WorldGuard::with(vm, |world| {
+        world
+          .get::<Health>(self.entity)
+          .map(|health| health.current as f64)
+      })?
+      .ok_or_else(|| vm.new_lookup_error(format!("Entity {:?} does not have Health", self.entity)))
+    }
+
+    #[pymethod]
+    fn damage(&self, amount: f64, vm: &VirtualMachine) -> PyResult<()> {
+      WorldGuard::with(vm, |world| {
+        world
+          .get_resource_mut::<Events<DamageEvent>>()
+          .unwrap()
+          .send(DamageEvent {
+            entity: self.entity,
+            amount: amount as f32,
+          });
+      })
     }
   }
 
   #[pyattr]
   #[pyclass(name, module = "crateton")]
-  pub struct CWorld(NonNull<World>);
+  pub struct CWorld {}
   pyvalue_impl!(CWorld);
   debug_impl!(CWorld);
 
   #[pyimpl]
   impl CWorld {
-    pub fn fetch(vm: &VirtualMachine) -> PyRef<Self> {
-      vm.current_globals()
-        .get_item("world", vm)
-        .unwrap()
-        .try_into_ref(vm)
-        .unwrap()
+    #[pyproperty]
+    fn player(&self) -> CPlayer {
+      CPlayer {}
     }
 
-    pub fn new(world: &mut World) -> Self {
-      CWorld(NonNull::new(world).unwrap())
+    #[pyproperty]
+    fn environment(&self) -> CEnvironment {
+      CEnvironment {}
     }
 
-    fn world(&self) -> &World {
-      unsafe { self.0.as_ref() }
+    #[pymethod]
+    fn entity_with_name(&self, name: PyStrRef, vm: &VirtualMachine) -> PyResult<CEntity> {
+      let name = name.as_ref();
+      WorldGuard::with(vm, |world| {
+        world
+          .query::<(Entity, &Name)>()
+          .iter(world)
+          .find(|(_, name_component)| name == name_component.as_str())
+          .map(|(entity, _)| CEntity { entity })
+      })?
+      .ok_or_else(|| vm.new_lookup_error(format!("Name {} does not exist", name)))
     }
+  }
 
-    fn world_mut(&self) -> &mut World {
-      unsafe { &mut *self.0.as_ptr() }
+  #[pyattr]
+  #[pyclass(name, module = "crateton")]
+  pub struct CEnvironment {}
+  pyvalue_impl!(CEnvironment);
+  debug_impl!(CEnvironment);
+
+  impl CEnvironment {
+    fn with_environment<T>(
+      vm: &VirtualMachine,
+      f: impl FnOnce(&mut Environment) -> T,
+    ) -> PyResult<T> {
+      WorldGuard::with(vm, |world| {
+        f(&mut world.get_resource_mut::<Environment>().unwrap())
+      })
+    }
+
+    fn color(r: f64, g: f64, b: f64) -> [f32; 3] {
+      [r as f32, g as f32, b as f32]
+    }
+  }
+
+  /// Angles are in degrees, and fog_distance and day_length turn off at 0.
+  #[pyimpl]
+  impl CEnvironment {
+    #[pyproperty]
+    fn sun_elevation(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      Self::with_environment(vm, |environment| environment.sun_elevation as f64)
+    }
+
+    #[pyproperty(setter)]
+    fn set_sun_elevation(&self, degrees: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| environment.sun_elevation = degrees as f32)
+    }
+
+    #[pyproperty]
+    fn sun_azimuth(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      Self::with_environment(vm, |environment| environment.sun_azimuth as f64)
+    }
+
+    #[pyproperty(setter)]
+    fn set_sun_azimuth(&self, degrees: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| environment.sun_azimuth = degrees as f32)
+    }
+
+    #[pyproperty]
+    fn sun_intensity(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      Self::with_environment(vm, |environment| environment.sun_intensity as f64)
+    }
+
+    #[pyproperty(setter)]
+    fn set_sun_intensity(&self, lux: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| environment.sun_intensity = lux as f32)
+    }
+
+    #[pyproperty]
+    fn ambient_brightness(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      Self::with_environment(vm, |environment| environment.ambient_brightness as f64)
+    }
+
+    #[pyproperty(setter)]
+    fn set_ambient_brightness(&self, brightness: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| {
+        environment.ambient_brightness = brightness as f32
+      })
+    }
+
+    #[pyproperty]
+    fn fog_distance(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      Self::with_environment(vm, |environment| environment.fog_distance as f64)
+    }
+
+    #[pyproperty(setter)]
+    fn set_fog_distance(&self, distance: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| {
+        environment.fog_distance = distance.max(0.) as f32
+      })
+    }
+
+    #[pyproperty]
+    fn day_length(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      Self::with_environment(vm, |environment| environment.day_length as f64)
+    }
+
+    #[pyproperty(setter)]
+    fn set_day_length(&self, seconds: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| {
+        environment.day_length = seconds.max(0.) as f32
+      })
     }
 
     #[pymethod]
-    fn entity_with_name(&self, name: PyStrRef, vm: &VirtualMachine) -> PyResult<CEntity> {
-      let name = name.as_ref();
-      self
-        .world_mut()
-        .query::<(Entity, &Name)>()
-        .iter(self.world())
-        .find(|(_, name_component)| name == name_component.as_str())
-        .map(|(entity, _)| CEntity { entity })
-        .ok_or_else(|| vm.new_lookup_error(format!("Name {} does not exist", name)))
+    fn set_sun_color(&self, r: f64, g: f64, b: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| {
+        environment.sun_color = Self::color(r, g, b)
+      })
+    }
+
+    #[pymethod]
+    fn set_ambient_color(&self, r: f64, g: f64, b: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| {
+        environment.ambient_color = Self::color(r, g, b)
+      })
+    }
+
+    #[pymethod]
+    fn set_sky_color(&self, r: f64, g: f64, b: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| {
+        environment.sky_color = Self::color(r, g, b)
+      })
+    }
+
+    #[pymethod]
+    fn set_horizon_color(&self, r: f64, g: f64, b: f64, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| {
+        environment.horizon_color = Some(Self::color(r, g, b))
+      })
+    }
+
+    #[pymethod]
+    fn clear_horizon_color(&self, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_environment(vm, |environment| environment.horizon_color = None)
     }
   }
 
   #[pyattr]
   #[pyclass(name, module = "crateton")]
-  pub struct CStdout {}
-  pyvalue_impl!(CStdout);
-  debug_impl!(CStdout);
+  pub struct CPlayer {}
+  pyvalue_impl!(CPlayer);
+  debug_impl!(CPlayer);
+
+  impl CPlayer {
+    fn player(world: &World) -> (Entity, Entity) {
+      let player = world.get_resource::<Player>().unwrap();
+      (player.body, player.camera)
+    }
+
+    fn with_controller<T>(
+      vm: &VirtualMachine,
+      f: impl FnOnce(&mut CharacterController) -> T,
+    ) -> PyResult<T> {
+      WorldGuard::with(vm, |world| {
+        f(&mut world.get_resource_mut::<CharacterController>().unwrap())
+      })
+    }
+  }
 
   #[pyimpl]
-  impl CStdout {
+  impl CPlayer {
+    #[pymethod]
+    fn position(&self, vm: &VirtualMachine) -> PyResult<CVec3> {
+      WorldGuard::with(vm, |world| {
+        let (body, _) = Self::player(world);
+        let position = world.get::<RigidBodyPosition>(body).unwrap();
+        CVec3 {
+          vec: position.position.translation.vector.to_glam_vec3(),
+        }
+      })
+    }
+
+    #[pymethod]
+    fn teleport(&self, x: f64, y: f64, z: f64, vm: &VirtualMachine) -> PyResult<()> {
+      WorldGuard::with(vm, |world| {
+        let (body, _) = Self::player(world);
+        let translation = Translation3::new(x as f32, y as f32, z as f32);
+        let mut position = world.get_mut::<RigidBodyPosition>(body).unwrap();
+        position.position.translation = translation;
+        position.next_position.translation = translation;
+        let mut velocity = world.get_mut::<RigidBodyVelocity>(body).unwrap();
+        velocity.linvel = Vec3::ZERO.into();
+        velocity.angvel = Vec3::ZERO.into();
+      })
+    }
+
+    /// The direction the camera is facing.
     #[pymethod]
-    fn write(&self, data: PyStrRef, vm: &VirtualMachine) {
-      let world = CWorld::fetch(vm);
+    fn look_direction(&self, vm: &VirtualMachine) -> PyResult<CVec3> {
+      WorldGuard::with(vm, |world| {
+        let (_, camera) = Self::player(world);
+        CVec3 {
+          vec: world.get::<LookDirection>(camera).unwrap().forward,
+        }
+      })
+    }
+
+    /// Points the camera at the given yaw and pitch, in radians.
+    #[pymethod]
+    fn look(&self, yaw: f64, pitch: f64, vm: &VirtualMachine) -> PyResult<()> {
+      WorldGuard::with(vm, |world| {
+        let mut settings = world.get_resource_mut::<MouseSettings>().unwrap();
+        settings.yaw_pitch_roll.x = yaw as f32;
+        settings.yaw_pitch_roll.y = (pitch as f32).clamp(-PITCH_BOUND, PITCH_BOUND);
+        let yaw_pitch_roll = settings.yaw_pitch_roll;
+
+        // The yaw and head transforms only follow events, so send the same ones mouse input does
+        world
+          .get_resource_mut::<Events<YawEvent>>()
+          .unwrap()
+          .send(YawEvent(yaw_pitch_roll.x));
+        world
+          .get_resource_mut::<Events<PitchEvent>>()
+          .unwrap()
+          .send(PitchEvent(yaw_pitch_roll.y));
+      })
+    }
+
+    #[pyproperty]
+    fn fly(&self, vm: &VirtualMachine) -> PyResult<bool> {
+      Self::with_controller(vm, |controller| controller.fly)
+    }
+
+    #[pyproperty(setter)]
+    fn set_fly(&self, fly: bool, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_controller(vm, |controller| {
+        controller.fly = fly;
+        controller.noclip = false;
+      })
+    }
+
+    /// Flying through walls without colliding with anything.
+    #[pyproperty]
+    fn noclip(&self, vm: &VirtualMachine) -> PyResult<bool> {
+      Self::with_controller(vm, |controller| controller.noclip)
+    }
+
+    #[pyproperty(setter)]
+    fn set_noclip(&self, noclip: bool, vm: &VirtualMachine) -> PyResult<()> {
+      Self::with_controller(vm, |controller| {
+        controller.noclip = noclip;
+        controller.fly = noclip;
+      })
+    }
+
+    #[pyproperty]
+    fn walk_speed(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      WorldGuard::with(vm, |world| {
+        world
+          .get_resource::<Cvars>()
+          .unwrap()
+          .float(controller::WALK_SPEED) as f64
+      })
+    }
+
+    #[pyproperty(setter)]
+    fn set_walk_speed(&self, speed: f64, vm: &VirtualMachine) -> PyResult<()> {
+      set_cvar_value(vm, controller::WALK_SPEED, CvarValue::Float(speed as f32))
+    }
+
+    #[pyproperty]
+    fn run_speed(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      WorldGuard::with(vm, |world| {
+        world
+          .get_resource::<Cvars>()
+          .unwrap()
+          .float(controller::RUN_SPEED) as f64
+      })
+    }
+
+    #[pyproperty(setter)]
+    fn set_run_speed(&self, speed: f64, vm: &VirtualMachine) -> PyResult<()> {
+      set_cvar_value(vm, controller::RUN_SPEED, CvarValue::Float(speed as f32))
+    }
+
+    #[pyproperty]
+    fn jump_speed(&self, vm: &VirtualMachine) -> PyResult<f64> {
+      WorldGuard::with(vm, |world| {
+        world
+          .get_resource::<Cvars>()
+          .unwrap()
+          .float(controller::JUMP_SPEED) as f64
+      })
+    }
+
+    #[pyproperty(setter)]
+    fn set_jump_speed(&self, speed: f64, vm: &VirtualMachine) -> PyResult<()> {
+      set_cvar_value(vm, controller::JUMP_SPEED, CvarValue::Float(speed as f32))
+    }
+
+    /// Either "first" or "third".
+    #[pyproperty]
+    fn perspective(&self, vm: &VirtualMachine) -> PyResult<String> {
+      WorldGuard::with(vm, |world| {
+        let (_, camera) = Self::player(world);
+        match world.get::<Perspective>(camera).unwrap() {
+          Perspective::FirstPerson => "first".to_owned(),
+          Perspective::ThirdPerson => "third".to_owned(),
+        }
+      })
+    }
+
+    #[pyproperty(setter)]
+    fn set_perspective(&self, perspective: PyStrRef, vm: &VirtualMachine) -> PyResult<()> {
+      let perspective = match perspective.as_ref() {
+        "first" => Perspective::FirstPerson,
+        "third" => Perspective::ThirdPerson,
+        other => {
+          return Err(vm.new_value_error(format!(
+            "Unknown perspective {}, expected first or third",
+            other
+          )));
+        }
+      };
+      WorldGuard::with(vm, |world| {
+        let (_, camera) = Self::player(world);
+        *world.get_mut::<Transform>(camera).unwrap() = perspective.to_transform();
+        *world.get_mut::<Perspective>(camera).unwrap() = perspective;
+      })
+    }
+
+    /// Holds down a movement input for the next controller step. Call it every frame, e.g. from
+    /// `update(dt)`, to keep moving.
+    #[pymethod]
+    fn press(&self, input: PyStrRef, vm: &VirtualMachine) -> PyResult<()> {
+      let input = input.as_ref().to_owned();
+      Self::with_controller(vm, |controller| {
+        let state = &mut controller.input_state;
+        match input.as_str() {
+          "forward" => state.forward = true,
+          "backward" => state.backward = true,
+          "left" => state.left = true,
+          "right" => state.right = true,
+          "run" => state.run = true,
+          "jump" => state.jump = true,
+          "crouch" => state.crouch = true,
+          _ => {
+            return false;
+          }
+        };
+        true
+      })?
+      .then(|| ())
+      .ok_or_else(|| {
+        vm.new_value_error(format!(
+          "Unknown input {}, expected one of forward, backward, left, right, run, jump, crouch",
+          input
+        ))
+      })
+    }
+  }
+
+  fn send_output(vm: &VirtualMachine, output: String, level: ScriptOutputLevel) -> PyResult<()> {
+    WorldGuard::with(vm, |world| {
       let mut events = world
-        .world_mut()
         .get_resource_mut::<Events<ScriptOutputEvent>>()
         .unwrap();
-      events.send(ScriptOutputEvent {
-        output: data.as_ref().to_owned(),
-      });
+      events.send(ScriptOutputEvent { output, level });
+    })
+  }
+
+  #[pyfunction]
+  fn log(level: PyStrRef, msg: PyStrRef, vm: &VirtualMachine) -> PyResult<()> {
+    let level = ScriptOutputLevel::from_name(level.as_ref()).ok_or_else(|| {
+      vm.new_value_error(format!(
+        "Unknown log level {}, expected one of debug, info, warning, error",
+        level.as_ref()
+      ))
+    })?;
+    send_output(vm, format!("{}\n", msg.as_ref()), level)
+  }
+
+  /// Cvars are saved to the user's config, so scripts from elsewhere can't change them.
+  fn set_cvar_value(vm: &VirtualMachine, name: &str, value: CvarValue) -> PyResult<()> {
+    if sandbox::current_trust() != ScriptTrust::Trusted {
+      return Err(vm.new_exception_msg(
+        vm.ctx.exceptions.permission_error.clone(),
+        format!("Untrusted scripts can't set {}", name),
+      ));
+    }
+    WorldGuard::with(vm, |world| {
+      world.get_resource_mut::<Cvars>().unwrap().set(name, value)
+    })?
+    .map_err(|e| vm.new_value_error(e.to_string()))
+  }
+
+  #[pyfunction]
+  fn cvar(name: PyStrRef, vm: &VirtualMachine) -> PyResult<PyObjectRef> {
+    let value = WorldGuard::with(vm, |world| {
+      let cvars = world.get_resource::<Cvars>().unwrap();
+      cvars.get(name.as_ref()).map(|cvar| cvar.value().clone())
+    })?
+    .ok_or_else(|| vm.new_lookup_error(format!("No cvar named {}", name.as_ref())))?;
+    Ok(match value {
+      CvarValue::Int(value) => vm.ctx.new_int(value),
+      CvarValue::Float(value) => vm.ctx.new_float(value as f64),
+      CvarValue::Bool(value) => vm.ctx.new_bool(value),
+    })
+  }
+
+  #[pyfunction]
+  fn set_cvar(name: PyStrRef, value: PyObjectRef, vm: &VirtualMachine) -> PyResult<()> {
+    // Parsing the text form accepts the same values as the terminal, e.g. True, 1 or "on"
+    let text = vm.to_str(&value)?;
+    let value = WorldGuard::with(vm, |world| {
+      let cvars = world.get_resource::<Cvars>().unwrap();
+      let cvar = cvars
+        .get(name.as_ref())
+        .ok_or_else(|| format!("No cvar named {}", name.as_ref()))?;
+      cvar.parse(text.as_ref()).map_err(|e| e.to_string())
+    })?
+    .map_err(|e| vm.new_value_error(e))?;
+    set_cvar_value(vm, name.as_ref(), value)
+  }
+
+  /// Replacement for `sys.stdout` and `sys.stderr` that forwards writes to the terminal.
+  #[pyattr]
+  #[pyclass(name, module = "crateton")]
+  pub struct COutput {
+    pub level: ScriptOutputLevel,
+  }
+  pyvalue_impl!(COutput);
+  debug_impl!(COutput);
+
+  #[pyimpl]
+  impl COutput {
+    #[pymethod]
+    fn write(&self, data: PyStrRef, vm: &VirtualMachine) -> PyResult<()> {
+      send_output(vm, data.as_ref().to_owned(), self.level)
     }
 
     #[pymethod]
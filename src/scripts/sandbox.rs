@@ -0,0 +1,508 @@
+use crate::prelude::*;
+use bevy::utils::{Duration, Instant};
+use rustpython_vm::{
+  builtins::{code::PyCodeRef, PyBaseExceptionRef, PyStrRef},
+  compile::Mode,
+  function::FuncArgs,
+  scope::Scope,
+  ItemProtocol, PyObjectRef, PyResult, TryFromObject, VirtualMachine,
+};
+use std::cell::Cell;
+
+/// Whether a script came from the local user or from somewhere else (e.g. a shared save file).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScriptTrust {
+  Trusted,
+  Untrusted,
+}
+
+thread_local! {
+  static CURRENT_TRUST: Cell<ScriptTrust> = Cell::new(ScriptTrust::Trusted);
+  /// When the invocation that is currently running runs out of time.
+  static DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// The trust level of the invocation that is currently running, so that anything a script
+/// creates (e.g. attached scripts) can't be more trusted than the script itself.
+pub fn current_trust() -> ScriptTrust {
+  CURRENT_TRUST.with(|current| current.get())
+}
+
+/// Modules that untrusted scripts can never import, even if they are added to the allowlist.
+const DENIED_MODULES: &[&str] = &[
+  "signal",
+  "_signal",
+  "os",
+  "posix",
+  "nt",
+  "io",
+  "_io",
+  "pathlib",
+  "shutil",
+  "subprocess",
+  "sys",
+];
+
+#[derive(Clone)]
+pub struct ScriptLimits {
+  /// How long a single invocation may run before a `TimeoutError` is raised inside the VM.
+  /// Zero disables the budget.
+  pub time_budget: Duration,
+  /// The most items a `range` or repeated sequence made by an untrusted script may have. Native
+  /// code can't be interrupted by the time budget, so this is what stops e.g. `'a' * 10**10`.
+  pub max_items: usize,
+  /// Top-level modules that untrusted scripts are allowed to import. They are imported when the
+  /// interpreter starts, so changes only apply after a restart.
+  pub allowed_modules: Vec<String>,
+}
+
+impl Default for ScriptLimits {
+  fn default() -> Self {
+    ScriptLimits {
+      time_budget: Duration::from_secs(2),
+      max_items: 1_000_000,
+      // Most of the pure Python stdlib keeps `os` and `sys` in its module globals, and modules
+      // like `itertools` and `time` can block in native code, so only these are allowed by default
+      allowed_modules: vec!["crateton", "math"]
+        .into_iter()
+        .map(String::from)
+        .collect(),
+    }
+  }
+}
+
+impl ScriptLimits {
+  fn allowed_modules(&self) -> Vec<String> {
+    self
+      .allowed_modules
+      .iter()
+      .filter(|module| !DENIED_MODULES.contains(&module.as_str()))
+      .cloned()
+      .collect()
+  }
+}
+
+/// Attributes that untrusted scripts can't get at, since they lead to the internals of other
+/// modules, e.g. `().__class__.__base__.__subclasses__()` or `gen.gi_frame.f_back.f_globals`.
+const FRAME_ATTRIBUTES: &[&str] = &[
+  "gi_frame",
+  "cr_frame",
+  "ag_frame",
+  "tb_frame",
+  "f_back",
+  "f_globals",
+  "f_builtins",
+  "f_locals",
+];
+
+fn is_hidden_attribute(name: &str) -> bool {
+  (name.starts_with("__") && name.ends_with("__")) || FRAME_ATTRIBUTES.contains(&name)
+}
+
+// The VM only checks the time in its call hook, so every loop and comprehension is rewritten to
+// call a function on each iteration. That's enough to break out of a `while True: pass`, and
+// doesn't depend on the platform having signals. Loops in native code (e.g. `sum(range(n))`) can't
+// be interrupted, so untrusted code also gets `*`, `**`, `range` and `pow` checked against
+// `ScriptLimits::max_items`. Other native functions that take a size, like `str.ljust`, aren't.
+const INSTRUMENT: &'static str = r#"
+import ast
+
+# Bound now, since the builtins are swapped out while untrusted scripts compile code
+real_compile = compile
+
+# Builtins that restricted code calls instead of `a * b` and `a ** b`, and in `a *= b` and `a **= b`
+SIZE_CHECKS = {
+  ast.Mult: ("__sandbox_mul__", "__sandbox_check_mul__"),
+  ast.Pow: ("__sandbox_pow__", "__sandbox_check_pow__"),
+}
+
+def budget_check(node):
+  check = real_compile("(lambda: True)()", "<sandbox>", "eval", ast.PyCF_ONLY_AST).body
+  for child in ast.walk(check):
+    ast.copy_location(child, node)
+  return check
+
+def call(name, args, node):
+  func = ast.Name(id=name, ctx=ast.Load())
+  return ast.copy_location(ast.Call(func=func, args=args, keywords=[]), node)
+
+class BudgetChecks(ast.NodeTransformer):
+  def visit_loop(self, node):
+    self.generic_visit(node)
+    node.body.insert(0, ast.copy_location(ast.Expr(budget_check(node)), node))
+    return node
+
+  visit_For = visit_AsyncFor = visit_While = visit_loop
+
+  def visit_comprehension(self, node):
+    self.generic_visit(node)
+    node.ifs.append(budget_check(node.iter))
+    return node
+
+class Restrictions(ast.NodeTransformer):
+  def __init__(self, filename):
+    self.filename = filename
+
+  def reject(self, node, message):
+    line = getattr(node, "lineno", 1)
+    column = getattr(node, "col_offset", 0) + 1
+    raise SyntaxError(message, (self.filename, line, column, None))
+
+  def generic_visit(self, node):
+    # Scripts can't rebind the size checks, not even as arguments or locals
+    names = [getattr(node, field, None) for field in ("id", "name", "arg", "asname")]
+    names.extend(getattr(node, "names", ()))
+    for name in names:
+      if isinstance(name, str) and name.startswith("__sandbox"):
+        self.reject(node, "'%s' is reserved for the sandbox" % name)
+    return ast.NodeTransformer.generic_visit(self, node)
+
+  def visit_Attribute(self, node):
+    if is_hidden(node.attr):
+      self.reject(node, "untrusted scripts can't access '%s'" % node.attr)
+    return self.generic_visit(node)
+
+  def visit_BinOp(self, node):
+    self.generic_visit(node)
+    if type(node.op) not in SIZE_CHECKS:
+      return node
+    return call(SIZE_CHECKS[type(node.op)][0], [node.left, node.right], node)
+
+  def visit_AugAssign(self, node):
+    self.generic_visit(node)
+    # Only plain names can be read again without running anything twice
+    if type(node.op) in SIZE_CHECKS and isinstance(node.target, ast.Name):
+      target = ast.copy_location(ast.Name(id=node.target.id, ctx=ast.Load()), node.target)
+      node.value = call(SIZE_CHECKS[type(node.op)][1], [target, node.value], node.value)
+    return node
+
+def instrument(source, filename, mode, restricted=False):
+  if not isinstance(source, ast.AST):
+    source = real_compile(source, filename, mode, ast.PyCF_ONLY_AST)
+  if restricted:
+    source = Restrictions(filename).visit(source)
+  tree = ast.fix_missing_locations(BudgetChecks().visit(source))
+  return real_compile(tree, filename, mode)
+"#;
+
+// Kept apart from the instrumentation so nothing untrusted scripts call has `real_compile` in its
+// globals. Only builtin types are checked, so no script code runs in here either.
+const SIZE_CHECKS: &'static str = r#"
+real_range = range
+real_pow = pow
+
+SEQUENCES = (str, bytes, bytearray, list, tuple)
+INTEGERS = (int, bool)
+
+def check_size(items):
+  if items > MAX_ITEMS:
+    raise MemoryError("untrusted scripts can't make more than %d items at once" % MAX_ITEMS)
+
+def check_mul(a, b):
+  for sequence, count in ((a, b), (b, a)):
+    if type(sequence) in SEQUENCES and type(count) in INTEGERS:
+      check_size(len(sequence) * count)
+  return b
+
+def check_pow(a, b):
+  # Counted in 64 bit digits, which cost about as much as an item does
+  if type(a) in INTEGERS and type(b) in INTEGERS and b > 0:
+    check_size(a.bit_length() * b // 64)
+  return b
+
+def mul(a, b):
+  return a * check_mul(a, b)
+
+def power(a, b, mod=None):
+  if mod is None:
+    return a ** check_pow(a, b)
+  return real_pow(a, b, mod)
+
+def checked_range(*args):
+  result = real_range(*args)
+  check_size(len(result))
+  return result
+"#;
+
+fn mode_name(mode: Mode) -> &'static str {
+  match mode {
+    Mode::Exec => "exec",
+    Mode::Eval => "eval",
+    Mode::Single => "single",
+  }
+}
+
+fn denied(vm: &VirtualMachine, message: String) -> PyBaseExceptionRef {
+  vm.new_exception_msg(vm.ctx.exceptions.permission_error.clone(), message)
+}
+
+/// Raises a `TimeoutError` if the invocation that is currently running is out of time.
+fn check_budget(vm: &VirtualMachine) -> PyResult<()> {
+  match DEADLINE.with(|deadline| deadline.get()) {
+    Some(deadline) if Instant::now() >= deadline => Err(vm.new_exception_msg(
+      vm.ctx.exceptions.timeout_error.clone(),
+      "script exceeded its time budget".to_owned(),
+    )),
+    _ => Ok(()),
+  }
+}
+
+fn run_helpers(vm: &VirtualMachine, source: &str, filename: &str) -> PyResult<Scope> {
+  let scope = vm.new_scope_with_builtins();
+  let code_obj = vm
+    .compile(source, Mode::Exec, filename.to_owned())
+    .map_err(|err| vm.new_syntax_error(&err))?;
+  vm.run_code_obj(code_obj, scope.clone())?;
+  Ok(scope)
+}
+
+/// Replacements for builtins that untrusted scripts get while they run. The guards are native
+/// functions so nothing reachable from Python holds on to the real builtins.
+pub struct Sandbox {
+  instrument: PyObjectRef,
+  budget_hook: PyObjectRef,
+  untrusted_builtins: Vec<(&'static str, PyObjectRef)>,
+}
+
+pub fn create_sandbox(vm: &VirtualMachine, limits: &ScriptLimits) -> PyResult<Sandbox> {
+  let instrument_helpers = run_helpers(vm, INSTRUMENT, "<sandbox>")?;
+  let is_hidden = vm.ctx.new_function("is_hidden", |name: PyStrRef| {
+    is_hidden_attribute(name.as_ref())
+  });
+  instrument_helpers
+    .globals
+    .set_item("is_hidden", is_hidden, vm)?;
+  let instrument = instrument_helpers.globals.get_item("instrument", vm)?;
+
+  let size_checks = run_helpers(vm, SIZE_CHECKS, "<sandbox limits>")?;
+  size_checks
+    .globals
+    .set_item("MAX_ITEMS", vm.ctx.new_int(limits.max_items), vm)?;
+  // Untrusted code calls these in place of `*` and `**` even when a trusted script calls into it
+  for (name, helper) in [
+    ("__sandbox_mul__", "mul"),
+    ("__sandbox_check_mul__", "check_mul"),
+    ("__sandbox_pow__", "power"),
+    ("__sandbox_check_pow__", "check_pow"),
+  ]
+  .iter()
+  {
+    vm.set_attr(
+      &vm.builtins,
+      *name,
+      size_checks.globals.get_item(*helper, vm)?,
+    )?;
+  }
+
+  // Import allowed modules up front so their own imports aren't blocked by the guard
+  let allowed = limits.allowed_modules();
+  for module in allowed.iter() {
+    if vm.import(module.as_str(), None, 0).is_err() {
+      warn!(
+        "Module {} allowed for untrusted scripts can't be imported",
+        module
+      );
+    }
+  }
+
+  let builtin = |name: &str| vm.get_attribute(vm.builtins.clone(), name);
+  let real_import = builtin("__import__")?;
+  let guarded_import = vm.ctx.new_function(
+    "__import__",
+    move |args: FuncArgs, vm: &VirtualMachine| -> PyResult {
+      let name =
+        PyStrRef::try_from_object(vm, args.args.first().cloned().unwrap_or(vm.ctx.none()))?;
+      let top = name.as_ref().split('.').next().unwrap_or_default();
+      if !allowed.iter().any(|module| module == top) {
+        return Err(vm.new_exception_msg(
+          vm.ctx.exceptions.import_error.clone(),
+          format!("module '{}' is not available to untrusted scripts", top),
+        ));
+      }
+      vm.invoke(&real_import, args)
+    },
+  );
+  let deny = |name: &'static str, message: &'static str| {
+    vm.ctx.new_function(
+      name,
+      move |_args: FuncArgs, vm: &VirtualMachine| -> PyResult {
+        Err(denied(vm, message.to_owned()))
+      },
+    )
+  };
+  // `getattr(x, "__class__")` and friends get the same checks as `x.__class__` does when compiling
+  let guarded_attribute = |name: &'static str| -> PyResult {
+    let real = builtin(name)?;
+    Ok(vm.ctx.new_function(
+      name,
+      move |args: FuncArgs, vm: &VirtualMachine| -> PyResult {
+        let attribute = args
+          .args
+          .get(1)
+          .and_then(|attribute| PyStrRef::try_from_object(vm, attribute.clone()).ok());
+        if let Some(attribute) = attribute {
+          if is_hidden_attribute(attribute.as_ref()) {
+            return Err(denied(
+              vm,
+              format!("untrusted scripts can't access '{}'", attribute),
+            ));
+          }
+        }
+        vm.invoke(&real, args)
+      },
+    ))
+  };
+
+  // Code compiled at runtime gets the same checks as scripts do
+  let guarded_compile = {
+    let instrument = instrument.clone();
+    vm.ctx.new_function(
+      "compile",
+      move |mut args: FuncArgs, vm: &VirtualMachine| -> PyResult {
+        let source = args.args.first().cloned().unwrap_or(vm.ctx.none());
+        if args.args.len() != 3
+          || !args.kwargs.is_empty()
+          || PyStrRef::try_from_object(vm, source).is_err()
+        {
+          return Err(denied(
+            vm,
+            "untrusted scripts can only call compile(source, filename, mode) with a string"
+              .to_owned(),
+          ));
+        }
+        args.args.push(vm.ctx.new_bool(true));
+        vm.invoke(&instrument, args)
+      },
+    )
+  };
+  let guarded_eval = |name: &'static str, mode: Mode| -> PyResult {
+    let real = builtin(name)?;
+    let instrument = instrument.clone();
+    Ok(vm.ctx.new_function(
+      name,
+      move |mut args: FuncArgs, vm: &VirtualMachine| -> PyResult {
+        if let Some(source) = args.args.first().cloned() {
+          if let Ok(source) = PyStrRef::try_from_object(vm, source) {
+            args.args[0] = vm.invoke(
+              &instrument,
+              (
+                source,
+                "<string>".to_owned(),
+                mode_name(mode).to_owned(),
+                true,
+              ),
+            )?;
+          }
+        }
+        vm.invoke(&real, args)
+      },
+    ))
+  };
+  let untrusted_builtins = vec![
+    ("__import__", guarded_import),
+    (
+      "open",
+      deny(
+        "open",
+        "filesystem access is not available to untrusted scripts",
+      ),
+    ),
+    (
+      "vars",
+      deny("vars", "vars() is not available to untrusted scripts"),
+    ),
+    ("getattr", guarded_attribute("getattr")?),
+    ("setattr", guarded_attribute("setattr")?),
+    ("delattr", guarded_attribute("delattr")?),
+    ("hasattr", guarded_attribute("hasattr")?),
+    ("compile", guarded_compile),
+    ("exec", guarded_eval("exec", Mode::Exec)?),
+    ("eval", guarded_eval("eval", Mode::Eval)?),
+    ("range", size_checks.globals.get_item("checked_range", vm)?),
+    ("pow", size_checks.globals.get_item("power", vm)?),
+  ];
+
+  let budget_hook = vm
+    .ctx
+    .new_function("budget_hook", |_args: FuncArgs, vm: &VirtualMachine| {
+      check_budget(vm)
+    });
+
+  Ok(Sandbox {
+    instrument,
+    budget_hook,
+    untrusted_builtins,
+  })
+}
+
+impl Sandbox {
+  /// Compiles a script with a budget check in every loop, so it can be stopped when it runs out
+  /// of time. Untrusted scripts also can't reach hidden attributes, and
+  /// get their `*`, `**`, `range` and `pow` checked against `ScriptLimits::max_items`.
+  pub fn compile(
+    &self,
+    vm: &VirtualMachine,
+    source: &str,
+    mode: Mode,
+    filename: &str,
+    trust: ScriptTrust,
+  ) -> PyResult<PyCodeRef> {
+    let code = vm.invoke(
+      &self.instrument,
+      (
+        source.to_owned(),
+        filename.to_owned(),
+        mode_name(mode).to_owned(),
+        trust == ScriptTrust::Untrusted,
+      ),
+    )?;
+    PyCodeRef::try_from_object(vm, code)
+  }
+}
+
+/// Runs `f` with the sandbox restrictions for `trust` applied, lifting them again afterwards
+/// even if `f` raised.
+pub fn run_sandboxed<T>(
+  vm: &VirtualMachine,
+  sandbox: &Sandbox,
+  trust: ScriptTrust,
+  limits: &ScriptLimits,
+  f: impl FnOnce() -> T,
+) -> PyResult<T> {
+  let mut replaced = vec![];
+  if trust == ScriptTrust::Untrusted {
+    for (name, replacement) in sandbox.untrusted_builtins.iter() {
+      let previous = vm.get_attribute(vm.builtins.clone(), *name)?;
+      vm.set_attr(&vm.builtins, *name, replacement.clone())?;
+      replaced.push((*name, previous));
+    }
+  }
+
+  // The call hook runs on every Python call, including the ones added to loops when compiling.
+  // A nested invocation can't outlast the one it runs in.
+  let budget = (limits.time_budget > Duration::ZERO).then(|| {
+    let mut deadline = Instant::now() + limits.time_budget;
+    let previous_deadline = DEADLINE.with(|current| current.get());
+    if let Some(previous_deadline) = previous_deadline {
+      deadline = deadline.min(previous_deadline);
+    }
+    DEADLINE.with(|current| current.set(Some(deadline)));
+    let previous_hook = vm.trace_func.replace(sandbox.budget_hook.clone());
+    let previous_tracing = vm.use_tracing.replace(true);
+    (previous_deadline, previous_hook, previous_tracing)
+  });
+
+  let previous = CURRENT_TRUST.with(|current| current.replace(trust));
+  let result = f();
+  CURRENT_TRUST.with(|current| current.set(previous));
+
+  if let Some((previous_deadline, previous_hook, previous_tracing)) = budget {
+    DEADLINE.with(|current| current.set(previous_deadline));
+    vm.trace_func.replace(previous_hook);
+    vm.use_tracing.set(previous_tracing);
+  }
+  for (name, previous) in replaced.into_iter().rev() {
+    vm.set_attr(&vm.builtins, name, previous)?;
+  }
+  Ok(result)
+}
@@ -0,0 +1,401 @@
+use super::{
+  pymod::crateton_pymod::CEntity,
+  report_exception,
+  sandbox::{self, ScriptLimits, ScriptTrust},
+  world_guard::WorldGuard,
+  PyInterpreter,
+};
+use crate::{
+  health::{BreakEvent, DamageEvent},
+  interaction::UseEvent,
+  prelude::*,
+};
+use bevy::{
+  app::ManualEventReader,
+  asset::{AssetLoader, LoadContext, LoadedAsset},
+  reflect::{self as bevy_reflect, TypeUuid},
+  utils::BoxedFuture,
+};
+use rustpython_vm::{
+  builtins::PyStrRef, compile::Mode, scope::Scope, ItemProtocol, PyObjectRef, PyResult, PyValue,
+  TryFromObject, VirtualMachine,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(TypeUuid)]
+#[uuid = "0b8f4f6e-3c2a-4d51-9a7e-5d0c1f6b2e93"]
+pub struct PythonSource(pub String);
+
+#[derive(Default)]
+pub struct PythonSourceLoader;
+impl AssetLoader for PythonSourceLoader {
+  fn load<'a>(
+    &'a self,
+    bytes: &'a [u8],
+    load_context: &'a mut LoadContext,
+  ) -> BoxedFuture<'a, anyhow::Result<()>> {
+    Box::pin(async move {
+      let source = String::from_utf8(bytes.to_vec())?;
+      load_context.set_default_asset(LoadedAsset::new(PythonSource(source)));
+      Ok(())
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["py"]
+  }
+}
+
+fn untrusted() -> ScriptTrust {
+  ScriptTrust::Untrusted
+}
+
+/// A Python behaviour attached to an entity. Each script runs in its own scope, with `self`
+/// bound to the entity, and may define `update(dt)` to be called every frame, `on_use(user)`
+/// to be called when the player uses the entity, and `on_damage(amount)` and `on_break()` to be
+/// called when an entity with health gets hurt or runs out of it.
+///
+/// Scripts can customize what gets saved by defining `save()` returning a JSON-compatible
+/// value and `load(state)` to restore it. Otherwise all JSON-compatible globals are saved.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Script {
+  pub path: String,
+  #[serde(default)]
+  pub state: Option<String>,
+  /// Scripts deserialized from a save file are never trusted.
+  #[serde(skip, default = "untrusted")]
+  pub trust: ScriptTrust,
+  #[serde(skip)]
+  handle: Option<Handle<PythonSource>>,
+}
+
+impl Script {
+  pub fn new(path: impl Into<String>, trust: ScriptTrust) -> Self {
+    Script {
+      path: path.into(),
+      state: None,
+      trust,
+      handle: None,
+    }
+  }
+}
+
+/// Snapshots the state of every script context into its `Script` component, e.g. before the
+/// world is saved.
+pub struct SaveScriptStateEvent;
+
+#[derive(Default)]
+pub(super) struct SaveScriptStateEventReader(ManualEventReader<SaveScriptStateEvent>);
+
+#[derive(Default)]
+pub(super) struct UseEventReader(ManualEventReader<UseEvent>);
+
+#[derive(Default)]
+pub(super) struct DamageEventReader(ManualEventReader<DamageEvent>);
+
+#[derive(Default)]
+pub(super) struct BreakEventReader(ManualEventReader<BreakEvent>);
+
+pub(super) struct ScriptContext {
+  scope: Scope,
+  /// Path and trust of the script the context was started from, to restart it when the entity's
+  /// script is replaced.
+  path: String,
+  trust: ScriptTrust,
+}
+
+pub(super) type ScriptContexts = HashMap<Entity, ScriptContext>;
+
+const STATE_HELPERS: &'static str = r#"
+import json
+
+def save_state(scope):
+  if "save" in scope:
+    return json.dumps(scope["save"]())
+  state = {}
+  for name, value in scope.items():
+    if name.startswith("_") or name in ("self", "world"):
+      continue
+    try:
+      json.dumps(value)
+    except (TypeError, ValueError):
+      continue
+    state[name] = value
+  return json.dumps(state)
+
+def load_state(scope, data):
+  state = json.loads(data)
+  if "load" in scope:
+    scope["load"](state)
+  else:
+    scope.update(state)
+"#;
+
+pub(super) fn create_state_helpers(vm: &VirtualMachine) -> PyResult<Scope> {
+  let scope = vm.new_scope_with_builtins();
+  let code_obj = vm
+    .compile(STATE_HELPERS, Mode::Exec, "<script state>".to_owned())
+    .map_err(|err| vm.new_syntax_error(&err))?;
+  vm.run_code_obj(code_obj, scope.clone())?;
+  Ok(scope)
+}
+
+struct PendingScript {
+  entity: Entity,
+  path: String,
+  source: String,
+  state: Option<String>,
+  trust: ScriptTrust,
+}
+
+fn create_context(
+  vm: &VirtualMachine,
+  py: &PyInterpreter,
+  limits: &ScriptLimits,
+  pending: &PendingScript,
+) -> PyResult<Scope> {
+  let scope = vm.new_scope_with_builtins();
+  let this = (CEntity {
+    entity: pending.entity,
+  })
+  .into_ref(vm);
+  scope.globals.set_item("self", this.into(), vm)?;
+  scope
+    .globals
+    .set_item("world", py.scope.globals.get_item("world", vm)?, vm)?;
+
+  let code_obj = py.sandbox.compile(
+    vm,
+    &pending.source,
+    Mode::Exec,
+    &pending.path,
+    pending.trust,
+  )?;
+  sandbox::run_sandboxed(vm, &py.sandbox, pending.trust, limits, || {
+    vm.run_code_obj(code_obj, scope.clone())?;
+    if let Some(state) = &pending.state {
+      let load_state = py.state_helpers.globals.get_item("load_state", vm)?;
+      vm.invoke(&load_state, (scope.globals.clone(), state.clone()))?;
+    }
+    Ok(())
+  })??;
+
+  Ok(scope)
+}
+
+fn save_context(
+  vm: &VirtualMachine,
+  py: &PyInterpreter,
+  limits: &ScriptLimits,
+  context: &ScriptContext,
+) -> PyResult<String> {
+  sandbox::run_sandboxed(vm, &py.sandbox, context.trust, limits, || {
+    let save_state = py.state_helpers.globals.get_item("save_state", vm)?;
+    let state = vm.invoke(&save_state, (context.scope.globals.clone(),))?;
+    Ok(PyStrRef::try_from_object(vm, state)?.as_ref().to_owned())
+  })?
+}
+
+fn update_context(
+  vm: &VirtualMachine,
+  py: &PyInterpreter,
+  limits: &ScriptLimits,
+  context: &ScriptContext,
+  dt: f64,
+) -> PyResult<()> {
+  let update = match context.scope.globals.get_item_option("update", vm)? {
+    Some(update) => update,
+    None => {
+      return Ok(());
+    }
+  };
+  sandbox::run_sandboxed(vm, &py.sandbox, context.trust, limits, || {
+    vm.invoke(&update, (dt,)).map(|_| ())
+  })?
+}
+
+/// Calls the function `name` defined by the script, if there is one.
+fn call_hook(
+  vm: &VirtualMachine,
+  py: &PyInterpreter,
+  limits: &ScriptLimits,
+  context: &ScriptContext,
+  name: &str,
+  args: Vec<PyObjectRef>,
+) -> PyResult<()> {
+  let hook = match context.scope.globals.get_item_option(name, vm)? {
+    Some(hook) => hook,
+    None => {
+      return Ok(());
+    }
+  };
+  sandbox::run_sandboxed(vm, &py.sandbox, context.trust, limits, || {
+    vm.invoke(&hook, args).map(|_| ())
+  })?
+}
+
+pub(super) fn run_entity_scripts(world: &mut World) {
+  let asset_server = world.get_resource::<AssetServer>().unwrap().clone();
+  for mut script in world.query::<&mut Script>().iter_mut(world) {
+    if script.handle.is_none() {
+      script.handle = Some(asset_server.load(script.path.as_str()));
+    }
+  }
+
+  let save_requested =
+    world.resource_scope(|world, mut event_reader: Mut<SaveScriptStateEventReader>| {
+      let events = world
+        .get_resource::<Events<SaveScriptStateEvent>>()
+        .unwrap();
+      event_reader.0.iter(events).count() > 0
+    });
+  let uses = world.resource_scope(|world, mut event_reader: Mut<UseEventReader>| {
+    let events = world.get_resource::<Events<UseEvent>>().unwrap();
+    event_reader
+      .0
+      .iter(events)
+      .map(|event| (event.user, event.target))
+      .collect::<Vec<_>>()
+  });
+  let damages = world.resource_scope(|world, mut event_reader: Mut<DamageEventReader>| {
+    let events = world.get_resource::<Events<DamageEvent>>().unwrap();
+    event_reader
+      .0
+      .iter(events)
+      .map(|event| (event.entity, event.amount))
+      .collect::<Vec<_>>()
+  });
+  let breaks = world.resource_scope(|world, mut event_reader: Mut<BreakEventReader>| {
+    let events = world.get_resource::<Events<BreakEvent>>().unwrap();
+    event_reader
+      .0
+      .iter(events)
+      .map(|event| event.entity)
+      .collect::<Vec<_>>()
+  });
+
+  let mut py = world.remove_non_send::<PyInterpreter>().unwrap();
+  let mut contexts = std::mem::take(&mut py.contexts);
+
+  // Broken entities are already despawned, but still get to run `on_break` one last time
+  let broken = breaks
+    .iter()
+    .filter_map(|entity| Some((*entity, contexts.remove(entity)?)))
+    .collect::<Vec<_>>();
+
+  // Drop contexts whose entity was despawned or had its script removed or replaced
+  contexts.retain(|entity, context| {
+    world.get::<Script>(*entity).map_or(false, |script| {
+      script.path == context.path && script.trust == context.trust
+    })
+  });
+
+  let unstarted = world
+    .query::<(Entity, &Script)>()
+    .iter(world)
+    .filter(|(entity, _)| !contexts.contains_key(entity))
+    .filter_map(|(entity, script)| Some((entity, script.handle.clone()?)))
+    .collect::<Vec<_>>();
+  let sources = world.get_resource::<Assets<PythonSource>>().unwrap();
+  let pending = unstarted
+    .into_iter()
+    .filter_map(|(entity, handle)| {
+      let source = sources.get(handle)?;
+      let script = world.get::<Script>(entity).unwrap();
+      Some(PendingScript {
+        entity,
+        path: script.path.clone(),
+        source: source.0.clone(),
+        state: script.state.clone(),
+        trust: script.trust,
+      })
+    })
+    .collect::<Vec<_>>();
+
+  let dt = world.get_resource::<Time>().unwrap().delta_seconds_f64();
+  let limits = world.get_resource::<ScriptLimits>().unwrap().clone();
+
+  let saved_states = py.interpreter.enter(|vm| {
+    WorldGuard::scope(world, || {
+      let mut created = Vec::new();
+      for pending in pending.iter() {
+        match create_context(vm, &py, &limits, pending) {
+          Ok(scope) => created.push((pending.entity, scope, pending)),
+          Err(exc) => report_exception(vm, exc),
+        }
+      }
+      for (entity, scope, pending) in created {
+        contexts.insert(
+          entity,
+          ScriptContext {
+            scope,
+            path: pending.path.clone(),
+            trust: pending.trust,
+          },
+        );
+      }
+
+      for context in contexts.values() {
+        if let Err(exc) = update_context(vm, &py, &limits, context, dt) {
+          report_exception(vm, exc);
+        }
+      }
+
+      for (user, target) in uses.iter() {
+        if let Some(context) = contexts.get(target) {
+          let user = (CEntity { entity: *user }).into_ref(vm).into();
+          if let Err(exc) = call_hook(vm, &py, &limits, context, "on_use", vec![user]) {
+            report_exception(vm, exc);
+          }
+        }
+      }
+
+      for (entity, amount) in damages.iter() {
+        let context = contexts.get(entity).or_else(|| {
+          broken
+            .iter()
+            .find(|(broken, _)| broken == entity)
+            .map(|(_, context)| context)
+        });
+        if let Some(context) = context {
+          let amount = vm.ctx.new_float(*amount as f64);
+          if let Err(exc) = call_hook(vm, &py, &limits, context, "on_damage", vec![amount]) {
+            report_exception(vm, exc);
+          }
+        }
+      }
+
+      for (_, context) in broken.iter() {
+        if let Err(exc) = call_hook(vm, &py, &limits, context, "on_break", vec![]) {
+          report_exception(vm, exc);
+        }
+      }
+
+      if !save_requested {
+        return vec![];
+      }
+
+      contexts
+        .iter()
+        .filter_map(
+          |(entity, context)| match save_context(vm, &py, &limits, context) {
+            Ok(state) => Some((*entity, state)),
+            Err(exc) => {
+              report_exception(vm, exc);
+              None
+            }
+          },
+        )
+        .collect::<Vec<_>>()
+    })
+  });
+  py.contexts = contexts;
+  world.insert_non_send(py);
+
+  for (entity, state) in saved_states {
+    if let Some(mut script) = world.get_mut::<Script>(entity) {
+      script.state = Some(state);
+    }
+  }
+}
@@ -0,0 +1,45 @@
+use crate::prelude::*;
+use rustpython_vm::{PyResult, VirtualMachine};
+use std::{cell::Cell, ptr::NonNull};
+
+thread_local! {
+  static CURRENT_WORLD: Cell<Option<NonNull<World>>> = Cell::new(None);
+}
+
+/// Restores the previous contents of `CURRENT_WORLD` when dropped, so the slot is reset even if
+/// a script callback panics.
+struct Restore(Option<NonNull<World>>);
+
+impl Drop for Restore {
+  fn drop(&mut self) {
+    CURRENT_WORLD.with(|current| current.set(self.0));
+  }
+}
+
+/// Gives Python callbacks access to the `World`, but only while a script invocation is running.
+///
+/// The world is lent out with [`WorldGuard::scope`] around a call into the interpreter. Python
+/// bindings then borrow it with [`WorldGuard::with`]. While borrowed, the slot is emptied, so a
+/// nested access raises a Python error instead of creating an aliasing `&mut World`.
+pub struct WorldGuard;
+
+impl WorldGuard {
+  pub fn scope<T>(world: &mut World, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_WORLD.with(|current| current.replace(Some(NonNull::from(world))));
+    let _restore = Restore(previous);
+    f()
+  }
+
+  pub fn with<T>(vm: &VirtualMachine, f: impl FnOnce(&mut World) -> T) -> PyResult<T> {
+    let mut world = CURRENT_WORLD
+      .with(|current| current.take())
+      .ok_or_else(|| {
+        vm.new_runtime_error("the world is only accessible while a script is running".to_owned())
+      })?;
+    let _restore = Restore(Some(world));
+
+    // SAFETY: the pointer was created from a `&mut World` that `scope` holds for the duration of
+    // the invocation, and taking it out of the slot guarantees this is the only live borrow.
+    Ok(f(unsafe { world.as_mut() }))
+  }
+}
@@ -44,6 +44,12 @@ impl SerdeFormat for RmpFormat {
 #[uuid = "e37c93d2-e55f-42ba-8ba4-ee063768b4f8"]
 pub struct RawData(Vec<u8>);
 
+impl RawData {
+  pub fn bytes(&self) -> &[u8] {
+    &self.0
+  }
+}
+
 #[derive(Default)]
 struct SerdeAssetLoader<F>(PhantomData<F>);
 impl<F: SerdeFormat> AssetLoader for SerdeAssetLoader<F> {
@@ -63,7 +69,8 @@ impl<F: SerdeFormat> AssetLoader for SerdeAssetLoader<F> {
   }
 }
 
-type Deserializer = Box<dyn Fn(&mut Commands, Entity, &RawData) -> () + Send + Sync>;
+type Deserializer =
+  Box<dyn Fn(&mut Commands, Entity, &RawData) -> anyhow::Result<()> + Send + Sync>;
 
 struct SingleDataLoader<F> {
   handle: Handle<RawData>,
@@ -75,11 +82,10 @@ impl<F: SerdeFormat> SingleDataLoader<F> {
   fn new<T: DeserializeOwned + Send + Sync + 'static>(handle: Handle<RawData>) -> Self {
     let convert = Box::new(
       |commands: &mut Commands, entity: Entity, serialized_data: &RawData| {
-        let data: T = F::deserialize(&serialized_data.0).unwrap();
-        commands
-          .entity(entity)
-          .insert(data)
-          .remove::<LoadingSerializedDataTag<T>>();
+        let mut entity_commands = commands.entity(entity);
+        entity_commands.remove::<LoadingSerializedDataTag<T>>();
+        entity_commands.insert(F::deserialize::<T>(&serialized_data.0)?);
+        Ok(())
       },
     ) as Deserializer;
     SingleDataLoader {
@@ -110,23 +116,32 @@ pub struct LoadingSerializedDataTag<T>(PhantomData<T>);
 
 fn load_data<F: SerdeFormat>(
   mut commands: Commands,
+  asset_server: Res<AssetServer>,
   assets: Res<Assets<RawData>>,
   mut loader: ResMut<SerdeLoader<F>>,
+  entity_query: Query<Entity>,
 ) {
   for (entity, loaders) in loader.0.iter_mut() {
-    let mut to_delete = vec![];
-    for (i, loader) in loaders.iter().enumerate() {
-      if let Some(data) = assets.get(loader.handle.clone()) {
-        (loader.convert)(&mut commands, *entity, data);
-        to_delete.push(i);
+    // The entity may have been despawned while its data was loading
+    let exists = entity_query.get(*entity).is_ok();
+    loaders.retain(|loader| {
+      let data = match assets.get(loader.handle.clone()) {
+        Some(data) => data,
+        None => return true,
+      };
+      if exists {
+        if let Err(e) = (loader.convert)(&mut commands, *entity, data) {
+          let path = asset_server
+            .get_handle_path(&loader.handle)
+            .map(|path| path.path().display().to_string())
+            .unwrap_or_default();
+          warn!("Failed to load {}: {}", path, e);
+        }
       }
-    }
-
-    to_delete.reverse();
-    for i in to_delete {
-      loaders.remove(i);
-    }
+      false
+    });
   }
+  loader.0.retain(|_, loaders| !loaders.is_empty());
 }
 
 fn register<F: SerdeFormat>(app: &mut App) {
@@ -0,0 +1,333 @@
+use crate::{
+  console::{AppConsoleExt, ArgKind, ConsoleCommand},
+  map::MapEntity,
+  physics::{NORMAL_ATTRIBUTE, POSITION_ATTRIBUTE},
+  prelude::*,
+};
+use bevy::{
+  ecs::system::CommandQueue,
+  render::{mesh::Indices, pipeline::PrimitiveTopology},
+};
+use bevy_rapier3d::{
+  na::{DMatrix, Isometry3, UnitQuaternion, Vector3},
+  prelude::*,
+  rapier::dynamics::BodyStatus,
+};
+use serde::{Deserialize, Serialize};
+
+const MAX_RESOLUTION: usize = 512;
+const MAX_OCTAVES: u32 = 16;
+
+fn size_default() -> f32 {
+  200.
+}
+
+fn resolution_default() -> usize {
+  128
+}
+
+fn octaves_default() -> u32 {
+  4
+}
+
+fn amplitude_default() -> f32 {
+  10.
+}
+
+fn scale_default() -> f32 {
+  50.
+}
+
+fn color_default() -> [f32; 3] {
+  [0.45, 0.6, 0.3]
+}
+
+/// Ground generated from fractal noise, with a heightfield collider. Changing it regenerates the
+/// mesh and collider.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Terrain {
+  /// Center of the terrain, with heights measured from it.
+  #[serde(default)]
+  pub position: Vec3,
+  /// Width and depth in meters.
+  #[serde(default = "size_default")]
+  pub size: f32,
+  /// Number of cells along each side.
+  #[serde(default = "resolution_default")]
+  pub resolution: usize,
+  #[serde(default)]
+  pub seed: u32,
+  /// Layers of noise, each with half the size and height of the one before.
+  #[serde(default = "octaves_default")]
+  pub octaves: u32,
+  /// Highest the terrain can reach above or below its position.
+  #[serde(default = "amplitude_default")]
+  pub amplitude: f32,
+  /// Size in meters of the largest hills.
+  #[serde(default = "scale_default")]
+  pub scale: f32,
+  #[serde(default = "color_default")]
+  pub color: [f32; 3],
+}
+
+impl Default for Terrain {
+  fn default() -> Self {
+    Terrain {
+      position: Vec3::ZERO,
+      size: size_default(),
+      resolution: resolution_default(),
+      seed: 0,
+      octaves: octaves_default(),
+      amplitude: amplitude_default(),
+      scale: scale_default(),
+      color: color_default(),
+    }
+  }
+}
+
+/// Random value in [-1, 1] for a point of the noise lattice.
+fn lattice_value(seed: u32, x: i32, z: i32) -> f32 {
+  let mut hash = seed.wrapping_mul(0x9E37_79B1)
+    ^ (x as u32).wrapping_mul(0x85EB_CA6B)
+    ^ (z as u32).wrapping_mul(0xC2B2_AE35);
+  hash ^= hash >> 16;
+  hash = hash.wrapping_mul(0x7FEB_352D);
+  hash ^= hash >> 15;
+  hash = hash.wrapping_mul(0x846C_A68B);
+  hash ^= hash >> 16;
+  hash as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+/// Value noise, smoothly interpolated between random values at integer coordinates.
+fn value_noise(seed: u32, x: f32, z: f32) -> f32 {
+  let (x0, z0) = (x.floor(), z.floor());
+  let smooth = |t: f32| t * t * (3. - 2. * t);
+  let (tx, tz) = (smooth(x - x0), smooth(z - z0));
+  let (x0, z0) = (x0 as i32, z0 as i32);
+  let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+  lerp(
+    lerp(
+      lattice_value(seed, x0, z0),
+      lattice_value(seed, x0 + 1, z0),
+      tx,
+    ),
+    lerp(
+      lattice_value(seed, x0, z0 + 1),
+      lattice_value(seed, x0 + 1, z0 + 1),
+      tx,
+    ),
+    tz,
+  )
+}
+
+impl Terrain {
+  /// Checks the parameters that would otherwise give a broken mesh, e.g. from a hand-written map.
+  pub fn validate(&self) -> anyhow::Result<()> {
+    if !(self.size > 0. && self.size.is_finite()) {
+      anyhow::bail!("the terrain size must be positive");
+    }
+    if !(self.scale > 0. && self.scale.is_finite()) {
+      anyhow::bail!("the terrain scale must be positive");
+    }
+    if !self.amplitude.is_finite() {
+      anyhow::bail!("the terrain amplitude must be a number");
+    }
+    Ok(())
+  }
+
+  fn row_len(&self) -> usize {
+    self.resolution.clamp(1, MAX_RESOLUTION) + 1
+  }
+
+  fn cell_size(&self) -> f32 {
+    self.size / (self.row_len() - 1) as f32
+  }
+
+  /// Height at a point relative to the center of the terrain.
+  pub fn height(&self, x: f32, z: f32) -> f32 {
+    let (mut total, mut weight, mut frequency, mut amplitude) = (0., 0., 1. / self.scale, 1.);
+    for octave in 0..self.octaves.clamp(1, MAX_OCTAVES) {
+      let seed = self.seed.wrapping_add(octave);
+      total += value_noise(seed, x * frequency, z * frequency) * amplitude;
+      weight += amplitude;
+      frequency *= 2.;
+      amplitude *= 0.5;
+    }
+    total / weight * self.amplitude
+  }
+
+  /// Heights of the grid, a row along X for each step along Z.
+  fn heights(&self) -> Vec<f32> {
+    let (n, cell) = (self.row_len(), self.cell_size());
+    let start = -self.size / 2.;
+    (0..n * n)
+      .map(|index| {
+        let (i, j) = (index / n, index % n);
+        self.height(start + j as f32 * cell, start + i as f32 * cell)
+      })
+      .collect()
+  }
+
+  fn mesh(&self, heights: &[f32]) -> Mesh {
+    let (n, cell) = (self.row_len(), self.cell_size());
+    let start = -self.size / 2.;
+    let height = |i: usize, j: usize| heights[i.min(n - 1) * n + j.min(n - 1)];
+
+    let mut positions = Vec::with_capacity(n * n);
+    let mut normals = Vec::with_capacity(n * n);
+    let mut uvs = Vec::with_capacity(n * n);
+    for i in 0..n {
+      for j in 0..n {
+        positions.push([
+          start + j as f32 * cell,
+          height(i, j),
+          start + i as f32 * cell,
+        ]);
+        // Central differences, one-sided at the edges
+        let dx = (height(i, j + 1) - height(i, j.saturating_sub(1))) / cell;
+        let dz = (height(i + 1, j) - height(i.saturating_sub(1), j)) / cell;
+        let normal = Vec3::new(-dx, 2., -dz).normalize();
+        normals.push([normal.x, normal.y, normal.z]);
+        uvs.push([j as f32 / (n - 1) as f32, i as f32 / (n - 1) as f32]);
+      }
+    }
+
+    let mut indices = Vec::with_capacity((n - 1) * (n - 1) * 6);
+    for i in 0..n - 1 {
+      for j in 0..n - 1 {
+        let a = (i * n + j) as u32;
+        let (b, c, d) = (a + 1, a + n as u32, a + n as u32 + 1);
+        indices.extend_from_slice(&[a, c, b, b, c, d]);
+      }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(POSITION_ATTRIBUTE, positions);
+    mesh.set_attribute(NORMAL_ATTRIBUTE, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+  }
+
+  fn collider_shape(&self, heights: &[f32]) -> ColliderShape {
+    let n = self.row_len();
+    // Rows of the heightfield go along Z and columns along X, spanning the unit square before
+    // scaling
+    let heights = DMatrix::from_fn(n, n, |i, j| heights[i * n + j]);
+    ColliderShape::heightfield(heights, Vector3::new(self.size, 1., self.size))
+  }
+}
+
+pub fn spawn_terrain(commands: &mut Commands, terrain: &Terrain) -> Entity {
+  commands
+    .spawn_bundle(PbrBundle {
+      transform: Transform::from_translation(terrain.position),
+      ..Default::default()
+    })
+    .insert_bundle((terrain.clone(), MapEntity, Name::new("terrain")))
+    .id()
+}
+
+/// Generates the mesh and collider of new or changed terrain.
+fn build_terrain(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+  query: Query<(Entity, &Terrain, Option<&ColliderShape>), Changed<Terrain>>,
+) {
+  for (entity, terrain, collider) in query.iter() {
+    info!("Generating terrain with seed {}", terrain.seed);
+    let heights = terrain.heights();
+    let shape = terrain.collider_shape(&heights);
+    let [r, g, b] = terrain.color;
+
+    let mut entity_commands = commands.entity(entity);
+    entity_commands.insert_bundle((
+      meshes.add(terrain.mesh(&heights)),
+      materials.add(Color::rgb(r, g, b).into()),
+    ));
+    if collider.is_some() {
+      entity_commands.insert(shape);
+    } else {
+      let position = Isometry3::from_parts(
+        terrain.position.to_na_translation(),
+        UnitQuaternion::identity(),
+      );
+      entity_commands
+        .insert_bundle(RigidBodyBundle {
+          body_type: BodyStatus::Static,
+          position: position.into(),
+          ..Default::default()
+        })
+        .insert_bundle(ColliderBundle {
+          shape,
+          ..Default::default()
+        })
+        .insert(RigidBodyPositionSync::Discrete);
+    }
+  }
+}
+
+fn terrain_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "terrain",
+    "Regenerates the terrain of the map with new noise parameters, adding terrain if there is none",
+    |world, args| {
+      let entity = world
+        .query_filtered::<Entity, With<Terrain>>()
+        .iter(world)
+        .next();
+      let mut terrain = entity
+        .map(|entity| world.get::<Terrain>(entity).unwrap().clone())
+        .unwrap_or_default();
+      if let Some(seed) = args.int(0) {
+        terrain.seed = seed as u32;
+      }
+      if let Some(octaves) = args.int(1) {
+        if octaves < 1 || octaves > MAX_OCTAVES as i64 {
+          anyhow::bail!(
+            "the number of octaves must be between 1 and {}",
+            MAX_OCTAVES
+          );
+        }
+        terrain.octaves = octaves as u32;
+      }
+      if let Some(amplitude) = args.float(2) {
+        terrain.amplitude = amplitude;
+      }
+      if let Some(scale) = args.float(3) {
+        terrain.scale = scale;
+      }
+      terrain.validate()?;
+
+      let message = format!(
+        "terrain {} {} {} {}",
+        terrain.seed, terrain.octaves, terrain.amplitude, terrain.scale
+      );
+      match entity {
+        Some(entity) => {
+          world.entity_mut(entity).insert(terrain);
+        }
+        None => {
+          let mut queue = CommandQueue::default();
+          spawn_terrain(&mut Commands::new(&mut queue, world), &terrain);
+          queue.apply(world);
+        }
+      }
+      Ok(Some(message))
+    },
+  )
+  .optional_arg("seed", ArgKind::Int)
+  .optional_arg("octaves", ArgKind::Int)
+  .optional_arg("amplitude", ArgKind::Float)
+  .optional_arg("scale", ArgKind::Float)
+}
+
+pub struct TerrainPlugin;
+impl Plugin for TerrainPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_system(build_terrain.system())
+      .add_console_command(terrain_command());
+  }
+}
@@ -1,9 +1,16 @@
 // Adapted from https://github.com/Laumania/Unity3d-PhysicsGun
 
 use crate::{
-  player::{controller::CharacterController, raycast::ViewInfo, spawn::Player},
+  cvars::{AppCvarsExt, Cvar, Cvars},
+  interaction::CarryState,
+  player::{
+    input_map::{Action, ActionState},
+    raycast::ViewInfo,
+    spawn::Player,
+  },
   prelude::*,
   shaders::{AttachShaderEvent, DetachShaderEvent},
+  ui::map_editor::MapEditor,
 };
 use bevy::{
   input::mouse::{MouseMotion, MouseWheel},
@@ -33,11 +40,50 @@ struct ToolStateInner {
 #[derive(Default)]
 struct ToolState(Option<ToolStateInner>);
 
-struct Frozen;
+pub struct Frozen;
+
+/// Tools the player can switch between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+  Physgun,
+  /// No tool, so clicking doesn't grab anything.
+  Hands,
+}
+
+impl Tool {
+  pub const ALL: &'static [Tool] = &[Tool::Physgun, Tool::Hands];
+}
+
+pub struct ActiveTool(pub Tool);
+
+impl Default for ActiveTool {
+  fn default() -> Self {
+    ActiveTool(Tool::Physgun)
+  }
+}
+
+fn switch_tool_system(actions: Res<ActionState>, mut active_tool: ResMut<ActiveTool>) {
+  let step = if actions.just_pressed(Action::NextTool) {
+    1
+  } else if actions.just_pressed(Action::PreviousTool) {
+    Tool::ALL.len() - 1
+  } else {
+    return;
+  };
+  let index = Tool::ALL
+    .iter()
+    .position(|tool| *tool == active_tool.0)
+    .unwrap();
+  active_tool.0 = Tool::ALL[(index + step) % Tool::ALL.len()];
+  info!("switched to {:?}", active_tool.0);
+}
 
 fn tool_system(
   mut commands: Commands,
-  mouse_input: Res<Input<MouseButton>>,
+  actions: Res<ActionState>,
+  active_tool: Res<ActiveTool>,
+  carry_state: Res<CarryState>,
+  map_editor: Res<MapEditor>,
   player: Res<Player>,
   mut tool_state: ResMut<ToolState>,
   transform_query: Query<&GlobalTransform>,
@@ -48,13 +94,15 @@ fn tool_system(
   view_info: ResMut<ViewInfo>,
   mut body_query: Query<(&mut RigidBodyType, &RigidBodyPosition)>,
 ) {
+  // Clicking selects things in the map editor instead
+  let physgun = active_tool.0 == Tool::Physgun && !map_editor.enabled;
   match tool_state.0.as_ref() {
     Some(inner) => {
       let entity = inner.held_body;
       let (mut body_type, _) = body_query.get_mut(entity).unwrap();
-      let reset = if !mouse_input.pressed(MouseButton::Left) {
+      let reset = if !physgun || !actions.pressed(Action::Grab) {
         true
-      } else if mouse_input.just_pressed(MouseButton::Right) {
+      } else if actions.just_pressed(Action::Freeze) {
         *body_type = BodyStatus::Static;
         commands.entity(entity).insert(Frozen);
         true
@@ -73,7 +121,9 @@ fn tool_system(
     }
 
     None => {
-      if !mouse_input.just_pressed(MouseButton::Left) {
+      // Clicking while carrying something throws it instead
+      let carrying = carry_state.is_carrying() || actions.pressed(Action::Use);
+      if !physgun || carrying || !actions.just_pressed(Action::Grab) {
         return;
       }
 
@@ -107,13 +157,13 @@ fn tool_system(
   }
 }
 
-const FORCE_MULTIPLIER: f32 = 0.1;
-const MOUSE_WHEEL_MULTIPLIER: f32 = 3.;
-const DISTANCE_MIN: f32 = 3.;
+pub const FORCE_MULTIPLIER: &str = "tools.force_multiplier";
+pub const SCROLL_DISTANCE: &str = "tools.scroll_distance";
+pub const MIN_DISTANCE: &str = "tools.min_distance";
 
 fn move_system(
   time: Res<Time>,
-  keyboard_input: Res<Input<KeyCode>>,
+  actions: Res<ActionState>,
   mut mouse_wheel_reader: EventReader<MouseWheel>,
   mut mouse_motion_reader: EventReader<MouseMotion>,
   mut tool_state: ResMut<ToolState>,
@@ -125,13 +175,14 @@ fn move_system(
   player: Res<Player>,
   transform_query: Query<&GlobalTransform>,
   view_info: ResMut<ViewInfo>,
-  controller: Res<CharacterController>,
+  cvars: Res<Cvars>,
 ) {
+  let force_multiplier = cvars.float(FORCE_MULTIPLIER);
   if let Some(inner) = tool_state.0.as_mut() {
     // Change distance from player based on mouse wheel
     for event in mouse_wheel_reader.iter() {
-      inner.distance =
-        (inner.distance + event.y.signum() * MOUSE_WHEEL_MULTIPLIER * -1.).max(DISTANCE_MIN);
+      inner.distance = (inner.distance + event.y.signum() * cvars.float(SCROLL_DISTANCE) * -1.)
+        .max(cvars.float(MIN_DISTANCE));
     }
 
     let (position, mass_props, mut velocity) = body_query.get_mut(inner.held_body).unwrap();
@@ -139,15 +190,15 @@ fn move_system(
     let target_pos = view_info.ray.point_at(inner.distance).coords + inner.hit_offset;
     let current_pos = position.position.translation.vector;
     let force =
-      (target_pos - current_pos) / time.delta_seconds() * mass_props.mass() * FORCE_MULTIPLIER;
+      (target_pos - current_pos) / time.delta_seconds() * mass_props.mass() * force_multiplier;
 
     let player_transform = transform_query.get(player.camera).unwrap();
     let player_rotation = player_transform.rotation.to_na_unit_quat();
 
-    if keyboard_input.pressed(controller.input_map.key_rotate_toolgun) {
+    if actions.pressed(Action::RotateToolgun) {
       for event in mouse_motion_reader.iter() {
         let delta = event.delta;
-        let snap_mode = keyboard_input.pressed(controller.input_map.key_lock_rotation);
+        let snap_mode = actions.pressed(Action::LockRotation);
 
         // After testing, if snap rotation accumulates as normal rotation, then feels too fast
         let multiplier = if snap_mode { 0.005 } else { 0.01 };
@@ -188,7 +239,7 @@ fn move_system(
     let current_rotation = position.position.rotation;
     let rotation_delta = current_rotation.rotation_to(&desired_rotation);
     let torque =
-      rotation_delta.scaled_axis() / time.delta_seconds() * mass_props.mass() * FORCE_MULTIPLIER;
+      rotation_delta.scaled_axis() / time.delta_seconds() * mass_props.mass() * force_multiplier;
 
     velocity.linvel = Vector3::zeros();
     velocity.angvel = Vector3::zeros();
@@ -231,6 +282,26 @@ impl Plugin for ToolPlugin {
     app
       .init_resource::<OutlineShader>()
       .init_resource::<ToolState>()
+      .init_resource::<ActiveTool>()
+      .add_cvar(
+        Cvar::float(
+          FORCE_MULTIPLIER,
+          "How strongly held objects are pulled towards the tool",
+          0.1,
+        )
+        .range(0., 1.),
+      )
+      .add_cvar(Cvar::float(
+        SCROLL_DISTANCE,
+        "Distance a held object moves per mouse wheel step",
+        3.,
+      ))
+      .add_cvar(Cvar::float(
+        MIN_DISTANCE,
+        "Closest a held object can be brought",
+        3.,
+      ))
+      .add_system(switch_tool_system.system())
       .add_system(tool_system.system())
       .add_system(move_system.system())
       .add_startup_system(init_outline_shader.system());
@@ -0,0 +1,229 @@
+use super::{UiLock, UiWindowManager};
+use crate::{
+  player::input_map::{Action, ActionState, AxisDirection, Binding, InputMap, BINDING_SLOTS},
+  prelude::*,
+};
+use bevy_egui::{egui, EguiContext};
+use egui::{Color32, Grid};
+
+const REBIND_HINT: &str =
+  "Click, then press a key or button or push a stick. Escape cancels and Delete unbinds.";
+
+#[derive(Default)]
+struct ControlsState {
+  /// The action and slot waiting for an input to bind.
+  rebinding: Option<(Action, usize)>,
+  /// Result of the last change, e.g. a conflict or a failed save.
+  status: Option<(String, bool)>,
+}
+
+impl ControlsState {
+  fn set_error(&mut self, message: String) {
+    self.status = Some((message, true));
+  }
+
+  fn set_status(&mut self, message: String) {
+    self.status = Some((message, false));
+  }
+
+  fn save(&mut self, input_map: &InputMap) {
+    if let Err(e) = input_map.save() {
+      self.set_error(format!("failed to save {:?}: {}", input_map.path(), e));
+    }
+  }
+}
+
+fn describe(actions: &[Action]) -> String {
+  actions
+    .iter()
+    .map(|action| action.label())
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// How far a stick has to be pushed to bind it.
+const AXIS_CAPTURE_THRESHOLD: f32 = 0.7;
+
+const STICK_AXES: &[GamepadAxisType] = &[
+  GamepadAxisType::LeftStickX,
+  GamepadAxisType::LeftStickY,
+  GamepadAxisType::RightStickX,
+  GamepadAxisType::RightStickY,
+];
+
+fn capture_binding(
+  keyboard_input: &Input<KeyCode>,
+  mouse_input: &Input<MouseButton>,
+  gamepad_input: &Input<GamepadButton>,
+  gamepad_axes: &Axis<GamepadAxis>,
+  gamepads: &Gamepads,
+) -> Option<Binding> {
+  let stick = || {
+    gamepads.iter().find_map(|gamepad| {
+      STICK_AXES.iter().find_map(|axis| {
+        let value = gamepad_axes.get(GamepadAxis(*gamepad, *axis))?;
+        if value >= AXIS_CAPTURE_THRESHOLD {
+          Some(Binding::GamepadAxis(*axis, AxisDirection::Positive))
+        } else if value <= -AXIS_CAPTURE_THRESHOLD {
+          Some(Binding::GamepadAxis(*axis, AxisDirection::Negative))
+        } else {
+          None
+        }
+      })
+    })
+  };
+
+  keyboard_input
+    .get_just_pressed()
+    .next()
+    .map(|key| Binding::Key(*key))
+    // Mouse buttons bind on release, when egui sees the click, so it can be ignored
+    .or_else(|| {
+      mouse_input
+        .get_just_released()
+        .next()
+        .map(|button| Binding::Mouse(*button))
+    })
+    .or_else(|| {
+      gamepad_input
+        .get_just_pressed()
+        .next()
+        .map(|button| Binding::Gamepad(button.1))
+    })
+    .or_else(stick)
+}
+
+fn controls_system(
+  actions: Res<ActionState>,
+  keyboard_input: Res<Input<KeyCode>>,
+  mouse_input: Res<Input<MouseButton>>,
+  gamepad_input: Res<Input<GamepadButton>>,
+  gamepad_axes: Res<Axis<GamepadAxis>>,
+  gamepads: Res<Gamepads>,
+  egui_context: Res<EguiContext>,
+  mut input_map: ResMut<InputMap>,
+  mut window_manager: ResMut<UiWindowManager>,
+  mut ui_lock: Local<Option<UiLock>>,
+  mut state: Local<ControlsState>,
+) {
+  let was_rebinding = state.rebinding.is_some();
+  if let Some((action, slot)) = state.rebinding {
+    match capture_binding(
+      &keyboard_input,
+      &mouse_input,
+      &gamepad_input,
+      &gamepad_axes,
+      &gamepads,
+    ) {
+      Some(Binding::Key(KeyCode::Escape)) => {
+        state.rebinding = None;
+      }
+      Some(Binding::Key(KeyCode::Delete)) => {
+        input_map.bind(action, slot, None);
+        state.rebinding = None;
+        state.status = None;
+        state.save(&input_map);
+      }
+      Some(binding) => {
+        let conflicts = input_map.bind(action, slot, Some(binding));
+        state.rebinding = None;
+        if conflicts.is_empty() {
+          state.status = None;
+        } else {
+          state.set_error(format!(
+            "{} is also bound to {}",
+            binding,
+            describe(&conflicts)
+          ));
+        }
+        state.save(&input_map);
+      }
+      None => {}
+    }
+  }
+
+  // Don't let the input that was just bound also toggle the window or start another rebind
+  if !was_rebinding && actions.just_pressed(Action::ToggleControls) {
+    match ui_lock.take() {
+      Some(lock) => {
+        window_manager.unshow(lock);
+      }
+      None => {
+        *ui_lock = window_manager.try_show();
+      }
+    }
+  }
+
+  if ui_lock.is_none() {
+    return;
+  }
+
+  let ctx = egui_context.ctx();
+  egui::Window::new("Controls").show(ctx, |ui| {
+    Grid::new("controls").striped(true).show(ui, |ui| {
+      for action in Action::ALL.iter().copied() {
+        let conflicts = input_map
+          .bindings(action)
+          .flat_map(|binding| input_map.conflicts(action, binding))
+          .collect::<Vec<_>>();
+        if conflicts.is_empty() {
+          ui.label(action.label());
+        } else {
+          ui.colored_label(Color32::RED, action.label())
+            .on_hover_text(format!("Shares a binding with {}", describe(&conflicts)));
+        }
+
+        for slot in 0..BINDING_SLOTS {
+          let text = if state.rebinding == Some((action, slot)) {
+            "press a key...".to_owned()
+          } else {
+            match input_map.bindings[&action][slot] {
+              Some(binding) => binding.to_string(),
+              None => "-".to_owned(),
+            }
+          };
+          if ui.button(text).on_hover_text(REBIND_HINT).clicked() && !was_rebinding {
+            state.rebinding = Some((action, slot));
+          }
+        }
+        ui.end_row();
+      }
+    });
+
+    ui.separator();
+    if ui
+      .checkbox(&mut input_map.invert_y, "Invert mouse Y")
+      .changed()
+    {
+      state.save(&input_map);
+    }
+
+    ui.horizontal(|ui| {
+      if ui.button("Reset to defaults").clicked() {
+        input_map.reset();
+        state.set_status("reset to the default controls".to_owned());
+        state.save(&input_map);
+      }
+      if ui.button("Reload").clicked() {
+        let path = input_map.path().to_owned();
+        *input_map = InputMap::load(&path);
+        state.set_status(format!("reloaded {:?}", path));
+      }
+    });
+
+    if let Some((message, is_error)) = &state.status {
+      if *is_error {
+        ui.colored_label(Color32::RED, message);
+      } else {
+        ui.label(message);
+      }
+    }
+  });
+}
+
+pub struct ControlsPlugin;
+impl Plugin for ControlsPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_system(controls_system.system());
+  }
+}
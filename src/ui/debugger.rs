@@ -1,4 +1,8 @@
-use crate::{player::controller::CharacterController, prelude::*};
+use crate::{
+  environment::Environment,
+  player::input_map::{Action, ActionState},
+  prelude::*,
+};
 use bevy_egui::{egui, EguiContext};
 use bevy_inspector_egui::{
   world_inspector::WorldUIContext, Context, Inspectable, InspectableRegistry, WorldInspectorParams,
@@ -7,35 +11,37 @@ use bevy_inspector_egui::{
 use super::{UiLock, UiWindowManager};
 
 fn debugger_system(world: &mut World) {
-  let world_ptr = world as *mut _;
-  let (mut ui_lock, keyboard_input, character_controller, mut ui_window_manager) = unsafe {
-    (
-      world
-        .get_resource_unchecked_mut::<DebuggerUiLock>()
-        .unwrap(),
-      world.get_resource::<Input<KeyCode>>().unwrap(),
-      world.get_resource::<CharacterController>().unwrap(),
-      world
-        .get_resource_unchecked_mut::<UiWindowManager>()
-        .unwrap(),
-    )
-  };
-
-  let key = character_controller.input_map.key_toggle_world_visualizer;
+  let actions = world.get_resource::<ActionState>().unwrap();
+  let (just_pressed, just_released) = (
+    actions.just_pressed(Action::ToggleWorldVisualizer),
+    actions.just_released(Action::ToggleWorldVisualizer),
+  );
 
-  if keyboard_input.just_pressed(key) {
-    ui_lock.0 = ui_window_manager.try_show();
-  } else if keyboard_input.just_released(key) && ui_lock.0.is_some() {
-    let lock = ui_lock.0.take().unwrap();
-    ui_window_manager.unshow(lock);
-  }
+  let showing = world.resource_scope(|world, mut ui_lock: Mut<DebuggerUiLock>| {
+    let mut ui_window_manager = world.get_resource_mut::<UiWindowManager>().unwrap();
+    if just_pressed {
+      ui_lock.0 = ui_window_manager.try_show();
+    } else if just_released && ui_lock.0.is_some() {
+      let lock = ui_lock.0.take().unwrap();
+      ui_window_manager.unshow(lock);
+    }
+    ui_lock.0.is_some()
+  });
 
-  if ui_lock.0.is_some() {
-    let egui_context = world.get_resource::<EguiContext>().unwrap();
-    let ctx = egui_context.ctx();
-    egui::Window::new("Debugger").scroll(true).show(ctx, |ui| {
-      let world: &mut World = unsafe { &mut *world_ptr };
-      let mut ui_context = WorldUIContext::new(world, Some(ctx));
+  if showing {
+    // The context is reference counted, so cloning it lets the inspector borrow the world mutably
+    let ctx = world.get_resource::<EguiContext>().unwrap().ctx().clone();
+    egui::Window::new("Debugger").scroll(true).show(&ctx, |ui| {
+      egui::CollapsingHeader::new("Environment").show(ui, |ui| {
+        // Only write back actual edits, so the environment isn't reapplied every frame
+        let mut environment = world.get_resource::<Environment>().unwrap().clone();
+        environment.ui(ui);
+        let mut current = world.get_resource_mut::<Environment>().unwrap();
+        if *current != environment {
+          *current = environment;
+        }
+      });
+      let mut ui_context = WorldUIContext::new(world, Some(&ctx));
       ui_context.world_ui::<()>(ui, &WorldInspectorParams::default());
     });
   }
@@ -1,6 +1,8 @@
 #![allow(dead_code, unused_mut, unused_variables)]
 
 use super::terminal::EditorResources;
+use crate::scripts::introspection::ScriptDiagnostic;
+use regex::Regex;
 use syntect::{easy::HighlightLines, util::LinesWithEndings};
 
 use bevy_egui::egui::{
@@ -28,6 +30,64 @@ pub struct State {
   // Visual offset when editing singleline text bigger than the width.
   #[cfg_attr(feature = "persistence", serde(skip))]
   singleline_offset: f32,
+
+  /// The dotted name before the cursor that completions are needed for,
+  /// e.g. `world.player` while typing `world.player.te`.
+  pub completion_path: String,
+
+  // Names that matched the last completion, shown until the text changes.
+  #[cfg_attr(feature = "persistence", serde(skip))]
+  completion_candidates: Vec<String>,
+
+  // Cursors besides `cursorp`, added with Ctrl+D or Alt+click.
+  #[cfg_attr(feature = "persistence", serde(skip))]
+  extra_cursors: Vec<CCursorPair>,
+
+  /// The find/replace bar, open while `Some`.
+  #[cfg_attr(feature = "persistence", serde(skip))]
+  pub find: Option<FindState>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FindState {
+  pub query: String,
+  pub replacement: String,
+  pub regex: bool,
+  focus_query: bool,
+  action: Option<FindAction>,
+  /// The query compiled as a regex, kept until the query changes.
+  compiled: Option<(String, Result<Regex, String>)>,
+}
+
+impl FindState {
+  fn compiled_regex(&mut self) -> Result<Regex, String> {
+    let query = &self.query;
+    if self
+      .compiled
+      .as_ref()
+      .map_or(true, |(compiled, _)| compiled != query)
+    {
+      let regex = Regex::new(query).map_err(|err| err.to_string());
+      self.compiled = Some((query.clone(), regex));
+    }
+    self.compiled.as_ref().unwrap().1.clone()
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FindAction {
+  Next,
+  Replace,
+  ReplaceAll,
+}
+
+impl State {
+  /// The selected part of `text` and the zero-based line it starts on, if anything is selected.
+  pub fn selection<'s>(&self, text: &'s str) -> Option<(usize, &'s str)> {
+    let cursorp = self.cursorp.filter(|cursorp| !cursorp.is_empty())?;
+    let [min, _] = cursorp.sorted();
+    Some((min.pcursor.paragraph, selected_str(text, &cursorp)))
+  }
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -121,6 +181,14 @@ impl CCursorPair {
       secondary: min,
     }
   }
+
+  fn sorted(&self) -> [CCursor; 2] {
+    if self.primary.index <= self.secondary.index {
+      [self.primary, self.secondary]
+    } else {
+      [self.secondary, self.primary]
+    }
+  }
 }
 
 /// Trait constraining what types [`TextEdit`] may use as
@@ -247,6 +315,9 @@ pub struct CodeEditor<'t, S: TextBuffer = String> {
   desired_width: Option<f32>,
   desired_height_rows: usize,
   lock_focus: bool,
+  completions: &'t [String],
+  diagnostics: &'t [ScriptDiagnostic],
+  signatures: &'t [(&'static str, &'static str)],
 }
 impl<'t, S: TextBuffer> CodeEditor<'t, S> {
   pub fn cursor(ui: &Ui, id: Id) -> Option<CursorPair> {
@@ -279,6 +350,9 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
       desired_width: None,
       desired_height_rows: 1,
       lock_focus: false,
+      completions: &[],
+      diagnostics: &[],
+      signatures: &[],
     }
   }
 
@@ -298,6 +372,9 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
       desired_width: None,
       desired_height_rows: 4,
       lock_focus: false,
+      completions: &[],
+      diagnostics: &[],
+      signatures: &[],
     }
   }
 
@@ -384,6 +461,24 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
     self.lock_focus = b;
     self
   }
+
+  /// Names that pressing TAB can complete, for the `completion_path` in the editor's [`State`].
+  pub fn completions(mut self, completions: &'t [String]) -> Self {
+    self.completions = completions;
+    self
+  }
+
+  /// Errors to underline, shown when hovering over them.
+  pub fn diagnostics(mut self, diagnostics: &'t [ScriptDiagnostic]) -> Self {
+    self.diagnostics = diagnostics;
+    self
+  }
+
+  /// Pairs of function name and signature, shown while the cursor is inside a call.
+  pub fn signatures(mut self, signatures: &'t [(&'static str, &'static str)]) -> Self {
+    self.signatures = signatures;
+    self
+  }
 }
 
 fn mask_massword(text: &str) -> String {
@@ -399,13 +494,19 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
     editor_state: &mut State,
     editor_resources: &EditorResources,
   ) -> Response {
+    if let Some(find) = &mut editor_state.find {
+      if find_bar(ui, find) {
+        editor_state.find = None;
+      }
+    }
+
     let frame = self.frame;
     let where_to_put_background = ui.painter().add(Shape::Noop);
 
     let margin = Vec2::new(4.0, 2.0);
     let max_rect = ui.available_rect_before_wrap().shrink2(margin);
     let mut content_ui = ui.child_ui(max_rect, *ui.layout());
-    let response = self.content_ui(&mut content_ui, editor_resources);
+    let response = self.content_ui(&mut content_ui, editor_state, editor_resources);
     let id = response.id;
     let frame_rect = response.rect.expand2(margin);
     ui.allocate_rect(frame_rect, Sense::hover());
@@ -441,7 +542,12 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
     response
   }
 
-  fn content_ui(self, ui: &mut Ui, editor_resources: &EditorResources) -> Response {
+  fn content_ui(
+    self,
+    ui: &mut Ui,
+    editor_state: &mut State,
+    editor_resources: &EditorResources,
+  ) -> Response {
     let CodeEditor {
       text,
       hint_text,
@@ -456,6 +562,9 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
       desired_width,
       desired_height_rows,
       lock_focus,
+      completions,
+      diagnostics,
+      signatures,
     } = self;
 
     let mask_if_password = |text: &str| {
@@ -540,13 +649,20 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
           });
         } else if response.hovered() && ui.input().pointer.any_pressed() {
           ui.memory().request_focus(id);
-          if ui.input().modifiers.shift {
+          if ui.input().modifiers.alt && multiline {
+            // Alt+click adds another cursor
+            if let Some(cursorp) = state.cursorp {
+              state.extra_cursors.push(cursorp.as_ccursorp());
+            }
+            state.cursorp = Some(CursorPair::one(cursor_at_pointer));
+          } else if ui.input().modifiers.shift {
             if let Some(cursorp) = &mut state.cursorp {
               cursorp.primary = cursor_at_pointer;
             } else {
               state.cursorp = Some(CursorPair::one(cursor_at_pointer));
             }
           } else {
+            state.extra_cursors.clear();
             state.cursorp = Some(CursorPair::one(cursor_at_pointer));
           }
         } else if ui.input().pointer.any_down() && response.is_pointer_button_down_on() {
@@ -561,6 +677,29 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
       ui.output().cursor_icon = CursorIcon::Text;
     }
 
+    // Buttons in the find bar are handled here, where the cursor state is available
+    let find_action = editor_state
+      .find
+      .as_mut()
+      .and_then(|find| find.action.take());
+    if let (Some(action), Some(find)) = (find_action, &mut editor_state.find) {
+      let text_before_find = text.as_ref().to_owned();
+      let cursor = state
+        .cursorp
+        .map_or_else(CCursorPair::default, |cursorp| cursorp.as_ccursorp());
+      if let Some(found) = apply_find_action(text, find, action, cursor) {
+        if text.as_ref() != text_before_find {
+          response.mark_changed();
+          galley = make_galley(ui, text.as_ref());
+        }
+        state.cursorp = Some(CursorPair {
+          primary: galley.from_ccursor(found.primary),
+          secondary: galley.from_ccursor(found.secondary),
+        });
+        state.extra_cursors.clear();
+      }
+    }
+
     let mut text_cursor = None;
     let prev_text_cursor = state.cursorp;
     if ui.memory().has_focus(id) && enabled {
@@ -591,6 +730,34 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
       );
 
       for event in &ui.input().events {
+        if !state.extra_cursors.is_empty() {
+          if let Some(edit) = MultiCursorEdit::from_event(event, multiline) {
+            let mut cursors = vec![cursorp.as_ccursorp()];
+            cursors.extend(state.extra_cursors.drain(..));
+            let mut cursors =
+              edit_at_cursors(text, &cursors, |text, cursor| edit.apply(text, cursor));
+            let primary = cursors.remove(0);
+            cursors.retain(|cursor| cursor.primary.index != primary.primary.index);
+            state.extra_cursors = cursors;
+
+            response.mark_changed();
+            galley = make_galley(ui, text.as_ref());
+            cursorp = CursorPair {
+              primary: galley.from_ccursor(primary.primary),
+              secondary: galley.from_ccursor(primary.secondary),
+            };
+            continue;
+          }
+
+          // Anything else only applies to the primary cursor
+          let adds_cursor =
+            matches!(event, Event::Key { key: Key::D, modifiers, .. } if modifiers.command);
+          if matches!(event, Event::Key { pressed: true, .. }) && !adds_cursor {
+            state.extra_cursors.clear();
+          }
+        }
+
+        let is_tab = matches!(event, Event::Key { key: Key::Tab, .. });
         let did_mutate_text = match event {
           Event::Copy => {
             if cursorp.is_empty() {
@@ -609,12 +776,17 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
               Some(CCursorPair::one(delete_selected(text, &cursorp)))
             }
           }
+          Event::Text(text_to_insert) if text_to_insert == "/" && ui.input().modifiers.command => {
+            if multiline {
+              Some(toggle_line_comment(text, cursorp.as_ccursorp()))
+            } else {
+              None
+            }
+          }
           Event::Text(text_to_insert) => {
             // Newlines are handled by `Key::Enter`.
             if !text_to_insert.is_empty() && text_to_insert != "\n" && text_to_insert != "\r" {
-              let mut ccursor = delete_selected(text, &cursorp);
-              insert_text(&mut ccursor, text, text_to_insert);
-              Some(CCursorPair::one(ccursor))
+              Some(type_text(text, cursorp.as_ccursorp(), text_to_insert))
             } else {
               None
             }
@@ -631,6 +803,11 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
               if modifiers.shift {
                 // TODO: support removing indentation over a selection?
                 decrease_identation(&mut ccursor, text);
+              } else if let Some((completion, candidates)) =
+                complete_at(text.as_ref(), ccursor, completions)
+              {
+                insert_text(&mut ccursor, text, &completion);
+                state.completion_candidates = candidates;
               } else {
                 insert_text(&mut ccursor, text, "\t");
               }
@@ -646,7 +823,8 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
           } => {
             if multiline {
               let mut ccursor = delete_selected(text, &cursorp);
-              insert_text(&mut ccursor, text, "\n");
+              let indent = newline_indent(text.as_ref(), ccursor);
+              insert_text(&mut ccursor, text, &format!("\n{}", indent));
               Some(CCursorPair::one(ccursor))
             } else {
               ui.memory().surrender_focus(id); // End input with enter
@@ -670,6 +848,52 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
             }
           }
 
+          Event::Key {
+            key: Key::F,
+            pressed: true,
+            modifiers,
+          } if modifiers.command && multiline => {
+            let find = editor_state.find.get_or_insert_with(FindState::default);
+            if !cursorp.is_empty() {
+              find.query = selected_str(text.as_ref(), &cursorp).to_owned();
+            }
+            find.focus_query = true;
+            None
+          }
+
+          Event::Key {
+            key: Key::D,
+            pressed: true,
+            modifiers,
+          } if modifiers.command && multiline => {
+            // Select the word under the cursor, then add a cursor at each next occurrence of it
+            let next = if cursorp.is_empty() {
+              Some(select_word_at(text.as_ref(), cursorp.primary.ccursor))
+            } else {
+              let next =
+                next_occurrence(text.as_ref(), cursorp.as_ccursorp(), &state.extra_cursors);
+              if next.is_some() {
+                state.extra_cursors.push(cursorp.as_ccursorp());
+              }
+              next
+            };
+            if let Some(next) = next {
+              cursorp = CursorPair {
+                primary: galley.from_ccursor(next.primary),
+                secondary: galley.from_ccursor(next.secondary),
+              };
+            }
+            None
+          }
+
+          Event::Key {
+            key,
+            pressed: true,
+            modifiers,
+          } if modifiers.alt && multiline && (*key == Key::ArrowUp || *key == Key::ArrowDown) => {
+            move_lines(text, cursorp.as_ccursorp(), *key == Key::ArrowUp)
+          }
+
           Event::Key {
             key,
             pressed: true,
@@ -708,6 +932,10 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
 
         if let Some(new_ccursorp) = did_mutate_text {
           response.mark_changed();
+          state.extra_cursors.clear();
+          if !is_tab {
+            state.completion_candidates.clear();
+          }
 
           // Layout again to avoid frame delay, and to keep `text` and `galley` in sync.
           galley = make_galley(ui, text.as_ref());
@@ -756,11 +984,35 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
       text_draw_pos -= vec2(offset_x, 0.0);
     }
 
+    if let Some(find) = &mut editor_state.find {
+      let color = Color32::from_rgba_unmultiplied(255, 200, 0, 40);
+      for range in find_matches(text.as_ref(), find).unwrap_or_default() {
+        let min = galley.from_ccursor(CCursor::new(range.start));
+        let max = galley.from_ccursor(CCursor::new(range.end));
+        paint_range(&painter, text_draw_pos, &galley, [min, max], color);
+      }
+    }
+
     if ui.memory().has_focus(id) {
       if let Some(cursorp) = state.cursorp {
         paint_cursor_selection(ui, &painter, text_draw_pos, &galley, &cursorp);
         paint_cursor_end(ui, &painter, text_draw_pos, &galley, &cursorp.primary);
 
+        for extra in state.extra_cursors.iter() {
+          let extra = CursorPair {
+            primary: galley.from_ccursor(extra.primary),
+            secondary: galley.from_ccursor(extra.secondary),
+          };
+          paint_cursor_selection(ui, &painter, text_draw_pos, &galley, &extra);
+          paint_cursor_end(ui, &painter, text_draw_pos, &galley, &extra.primary);
+        }
+
+        if let Some((open, close)) = matching_bracket(text.as_ref(), cursorp.primary.ccursor.index)
+        {
+          paint_bracket_match(ui, &painter, text_draw_pos, &galley, open);
+          paint_bracket_match(ui, &painter, text_draw_pos, &galley, close);
+        }
+
         if enabled {
           ui.ctx().output().text_cursor_pos = Some(
             galley
@@ -770,6 +1022,11 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
           );
         }
       }
+    } else if editor_state.find.is_some() {
+      // Keep showing the current match while the find bar has focus
+      if let Some(cursorp) = state.cursorp {
+        paint_cursor_selection(ui, &painter, text_draw_pos, &galley, &cursorp);
+      }
     }
 
     let text_color = text_color
@@ -778,7 +1035,28 @@ impl<'t, S: TextBuffer> CodeEditor<'t, S> {
       .unwrap_or_else(|| ui.visuals().widgets.inactive.text_color());
 
     // painter.galley(text_draw_pos, galley, text_color);
-    Self::paint_with_highlighting(editor_resources, text, &response, galley, &ui);
+    Self::paint_with_highlighting(editor_resources, text, &response, galley.clone(), &ui);
+
+    for diagnostic in diagnostics {
+      paint_diagnostic(ui, &painter, id, text_draw_pos, &galley, diagnostic);
+    }
+
+    if let (Some(cursorp), true) = (state.cursorp, ui.memory().has_focus(id)) {
+      let ccursor = cursorp.primary.ccursor;
+      editor_state.completion_path = completion_context(text.as_ref(), ccursor).0;
+
+      let hint = if !state.completion_candidates.is_empty() {
+        Some(state.completion_candidates.join("  "))
+      } else {
+        signature_at(text.as_ref(), ccursor, signatures).map(String::from)
+      };
+      if let Some(hint) = hint {
+        let cursor_rect = galley
+          .pos_from_cursor(&cursorp.primary)
+          .translate(text_draw_pos.to_vec2());
+        paint_hint(ui, cursor_rect.left_bottom(), hint);
+      }
+    }
 
     if text.as_ref().is_empty() && !hint_text.is_empty() {
       let galley = if multiline {
@@ -897,7 +1175,16 @@ fn paint_cursor_selection(
   if cursorp.is_empty() {
     return;
   }
-  let [min, max] = cursorp.sorted();
+  paint_range(painter, pos, galley, cursorp.sorted(), color);
+}
+
+fn paint_range(
+  painter: &Painter,
+  pos: Pos2,
+  galley: &Galley,
+  [min, max]: [Cursor; 2],
+  color: Color32,
+) {
   let min = min.rcursor;
   let max = max.rcursor;
 
@@ -952,6 +1239,78 @@ fn paint_cursor_end(ui: &mut Ui, painter: &Painter, pos: Pos2, galley: &Galley,
   }
 }
 
+fn paint_diagnostic(
+  ui: &mut Ui,
+  painter: &Painter,
+  id: Id,
+  pos: Pos2,
+  galley: &Galley,
+  diagnostic: &ScriptDiagnostic,
+) {
+  let start = galley.from_pcursor(PCursor {
+    paragraph: diagnostic.row,
+    offset: diagnostic.column,
+    prefer_next_row: false,
+  });
+  let end = galley.cursor_end_of_row(&start);
+  let start_rect = galley.pos_from_cursor(&start).translate(pos.to_vec2());
+  let end_rect = galley.pos_from_cursor(&end).translate(pos.to_vec2());
+
+  // Underline at least one character so errors at the end of a line are visible
+  let min_width = ui.fonts().row_height(TextStyle::Monospace) / 2.0;
+  let rect = Rect::from_min_max(
+    start_rect.min,
+    pos2(
+      end_rect.max.x.max(start_rect.min.x + min_width),
+      start_rect.max.y,
+    ),
+  );
+
+  let amplitude = 1.5;
+  let mut points = vec![];
+  let mut x = rect.min.x;
+  let mut up = true;
+  while x <= rect.max.x {
+    let offset = if up { -amplitude } else { amplitude };
+    points.push(pos2(x, rect.max.y + offset));
+    x += 2.0 * amplitude;
+    up = !up;
+  }
+  painter.add(Shape::line(points, (1.0, Color32::RED)));
+
+  let hovered = ui
+    .input()
+    .pointer
+    .hover_pos()
+    .map_or(false, |pointer_pos| rect.contains(pointer_pos));
+  if hovered {
+    show_tooltip_text(ui.ctx(), id.with("diagnostic"), &diagnostic.message);
+  }
+}
+
+fn paint_bracket_match(ui: &Ui, painter: &Painter, pos: Pos2, galley: &Galley, index: usize) {
+  let start = galley.pos_from_cursor(&galley.from_ccursor(CCursor::new(index)));
+  let end = galley.pos_from_cursor(&galley.from_ccursor(CCursor::new(index + 1)));
+  let rect = Rect::from_min_max(
+    start.min,
+    pos2(end.min.x.max(start.min.x + 1.0), start.max.y),
+  );
+  painter.rect_stroke(
+    rect.translate(pos.to_vec2()),
+    1.0,
+    (1.0, ui.visuals().weak_text_color()),
+  );
+}
+
+fn paint_hint(ui: &Ui, pos: Pos2, hint: String) {
+  let galley = ui.fonts().layout_single_line(TextStyle::Monospace, hint);
+  let rect = Rect::from_min_size(pos, galley.size).expand(2.0);
+  // Not clipped to the editor so hints on the last row are still readable
+  let painter = ui.painter();
+  painter.rect_filled(rect, 2.0, ui.visuals().faint_bg_color);
+  painter.galley(pos, galley, ui.visuals().weak_text_color());
+}
+
 // ----------------------------------------------------------------------------
 
 fn selected_str<'s>(text: &'s str, cursorp: &CursorPair) -> &'s str {
@@ -999,6 +1358,21 @@ fn delete_previous_char<S: TextBuffer>(text: &mut S, ccursor: CCursor) -> CCurso
   }
 }
 
+/// Deletes the previous character, along with the next one if they are an empty pair like `()`.
+fn delete_previous_char_or_pair<S: TextBuffer>(text: &mut S, ccursor: CCursor) -> CCursor {
+  let mut chars = text.as_ref().chars().skip(ccursor.index.saturating_sub(1));
+  let is_pair = match (ccursor.index, chars.next(), chars.next()) {
+    (0, _, _) => false,
+    (_, Some(prev), Some(next)) => AUTO_CLOSED_PAIRS.contains(&(prev, next)),
+    _ => false,
+  };
+  if is_pair {
+    delete_selected_ccursor_range(text, [ccursor - 1, ccursor + 1])
+  } else {
+    delete_previous_char(text, ccursor)
+  }
+}
+
 fn delete_next_char<S: TextBuffer>(text: &mut S, ccursor: CCursor) -> CCursor {
   delete_selected_ccursor_range(text, [ccursor, ccursor + 1])
 }
@@ -1068,7 +1442,7 @@ fn on_key_press<S: TextBuffer>(
           // alt on mac, ctrl on windows
           delete_previous_word(text, cursor.ccursor)
         } else {
-          delete_previous_char(text, cursor.ccursor)
+          delete_previous_char_or_pair(text, cursor.ccursor)
         }
       } else {
         delete_selected(text, cursorp)
@@ -1266,6 +1640,80 @@ fn is_word_char(c: char) -> bool {
   c.is_ascii_alphanumeric() || c == '_'
 }
 
+/// Splits the dotted name before `ccursor` into the object path and the partially typed name,
+/// e.g. `world.player.te` becomes `("world.player", "te")`.
+fn completion_context(text: &str, ccursor: CCursor) -> (String, String) {
+  let before = text.chars().take(ccursor.index).collect::<Vec<_>>();
+  let start = before
+    .iter()
+    .rposition(|c| !is_word_char(*c) && *c != '.')
+    .map_or(0, |i| i + 1);
+  let dotted = before[start..].iter().collect::<String>();
+  match dotted.rfind('.') {
+    Some(i) => (dotted[..i].to_owned(), dotted[i + 1..].to_owned()),
+    None => (String::new(), dotted),
+  }
+}
+
+/// Returns the text to insert to complete the name before `ccursor`, along with every
+/// completion that matched. When several match, only their common prefix is inserted.
+fn complete_at(
+  text: &str,
+  ccursor: CCursor,
+  completions: &[String],
+) -> Option<(String, Vec<String>)> {
+  let (path, partial) = completion_context(text, ccursor);
+  if path.is_empty() && partial.is_empty() {
+    return None;
+  }
+
+  let candidates = completions
+    .iter()
+    .filter(|name| name.starts_with(&partial))
+    .cloned()
+    .collect::<Vec<_>>();
+  let first = candidates.first()?;
+  let common_len = candidates.iter().fold(first.len(), |len, name| {
+    first
+      .chars()
+      .zip(name.chars())
+      .take_while(|(a, b)| a == b)
+      .count()
+      .min(len)
+  });
+  let completion = first.chars().take(common_len).skip(partial.chars().count());
+  Some((completion.collect(), candidates))
+}
+
+/// Finds the signature of the innermost call that `ccursor` is inside of.
+fn signature_at(
+  text: &str,
+  ccursor: CCursor,
+  signatures: &[(&'static str, &'static str)],
+) -> Option<&'static str> {
+  let before = text.chars().take(ccursor.index).collect::<Vec<_>>();
+  let mut depth = 0;
+  for (i, c) in before.iter().enumerate().rev() {
+    match c {
+      ')' => depth += 1,
+      '(' if depth > 0 => depth -= 1,
+      '(' => {
+        let start = before[..i]
+          .iter()
+          .rposition(|c| !is_word_char(*c))
+          .map_or(0, |j| j + 1);
+        let name = before[start..i].iter().collect::<String>();
+        return signatures
+          .iter()
+          .find(|(function, _)| *function == name)
+          .map(|(_, signature)| *signature);
+      }
+      _ => {}
+    }
+  }
+  None
+}
+
 /// Accepts and returns character offset (NOT byte offset!).
 fn find_line_start(text: &str, current_index: CCursor) -> CCursor {
   // We know that new lines, '\n', are a single byte char, but we have to
@@ -1309,3 +1757,485 @@ fn decrease_identation<S: TextBuffer>(ccursor: &mut CCursor, text: &mut S) {
     }
   }
 }
+
+// ----------------------------------------------------------------------------
+
+const INDENT: &str = "\t";
+
+const BRACKETS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+
+const AUTO_CLOSED_PAIRS: &[(char, char)] =
+  &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
+/// Types `typed` over the selection of `cursor`. Brackets and quotes are closed automatically
+/// (or wrap the selection), and typing a closing character right before the same one steps
+/// over it instead of doubling it.
+fn type_text<S: TextBuffer>(text: &mut S, cursor: CCursorPair, typed: &str) -> CCursorPair {
+  let [min, max] = cursor.sorted();
+  let mut typed_chars = typed.chars();
+  let typed_char = match (typed_chars.next(), typed_chars.next()) {
+    (Some(c), None) => Some(c),
+    _ => None,
+  };
+
+  if let Some(c) = typed_char {
+    let next = text.as_ref().chars().nth(max.index);
+    let prev = min
+      .index
+      .checked_sub(1)
+      .and_then(|index| text.as_ref().chars().nth(index));
+
+    let is_closing = AUTO_CLOSED_PAIRS.iter().any(|&(_, close)| close == c);
+    if min.index == max.index && is_closing && next == Some(c) {
+      return CCursorPair::one(max + 1);
+    }
+
+    if let Some(&(open, close)) = AUTO_CLOSED_PAIRS.iter().find(|&&(open, _)| open == c) {
+      if min.index != max.index {
+        text.insert_text(&close.to_string(), max.index);
+        text.insert_text(&open.to_string(), min.index);
+        return CCursorPair::two(min + 1, max + 1);
+      }
+
+      // Don't close quotes typed as apostrophes, or brackets typed right before other code
+      let should_close = if open == close {
+        !prev.map_or(false, is_word_char) && !next.map_or(false, is_word_char)
+      } else {
+        next.map_or(true, |next| next.is_whitespace() || ")]}:,".contains(next))
+      };
+      if should_close {
+        let mut ccursor = min;
+        insert_text(&mut ccursor, text, &format!("{}{}", open, close));
+        return CCursorPair::one(ccursor - 1);
+      }
+    }
+  }
+
+  let mut ccursor = delete_selected_ccursor_range(text, [min, max]);
+  insert_text(&mut ccursor, text, typed);
+  CCursorPair::one(ccursor)
+}
+
+/// Indentation for a line broken at `ccursor`: the same as the current line, plus one level
+/// after a `:` that opens a block.
+fn newline_indent(text: &str, ccursor: CCursor) -> String {
+  let line_start = find_line_start(text, ccursor);
+  let before_cursor = text
+    .chars()
+    .skip(line_start.index)
+    .take(ccursor.index - line_start.index)
+    .collect::<String>();
+  let indent = before_cursor
+    .chars()
+    .take_while(|c| *c == ' ' || *c == '\t')
+    .collect::<String>();
+  if before_cursor.trim_end().ends_with(':') {
+    indent + INDENT
+  } else {
+    indent
+  }
+}
+
+fn paragraph_at(text: &str, char_index: usize) -> usize {
+  text.chars().take(char_index).filter(|c| *c == '\n').count()
+}
+
+/// Comments out the lines spanned by `cursor`, or uncomments them if they all are already.
+/// Returns a selection of the whole lines.
+fn toggle_line_comment<S: TextBuffer>(text: &mut S, cursor: CCursorPair) -> CCursorPair {
+  let [min, max] = cursor.sorted();
+  let first = paragraph_at(text.as_ref(), min.index);
+  let last = paragraph_at(text.as_ref(), max.index);
+  let mut lines = text
+    .as_ref()
+    .split('\n')
+    .map(String::from)
+    .collect::<Vec<_>>();
+
+  let is_blank = |line: &String| line.trim().is_empty();
+  let span = &mut lines[first..=last];
+  let uncomment = span
+    .iter()
+    .filter(|line| !is_blank(line))
+    .all(|line| line.trim_start().starts_with('#'))
+    && span.iter().any(|line| !is_blank(line));
+  let indent = span
+    .iter()
+    .filter(|line| !is_blank(line))
+    .map(|line| line.len() - line.trim_start().len())
+    .min()
+    .unwrap_or(0);
+
+  for line in span.iter_mut().filter(|line| !line.trim().is_empty()) {
+    if uncomment {
+      let start = line.len() - line.trim_start().len();
+      let marker = if line[start..].starts_with("# ") {
+        2
+      } else {
+        1
+      };
+      line.replace_range(start..start + marker, "");
+    } else {
+      line.insert_str(indent, "# ");
+    }
+  }
+
+  let start = lines[..first]
+    .iter()
+    .map(|line| line.chars().count() + 1)
+    .sum::<usize>();
+  let end = start
+    + lines[first..=last]
+      .iter()
+      .map(|line| line.chars().count() + 1)
+      .sum::<usize>()
+    - 1;
+  text.replace(&lines.join("\n"));
+  CCursorPair::two(CCursor::new(start), CCursor::new(end))
+}
+
+/// Swaps the lines spanned by `cursor` with the line above or below them, keeping the cursor on
+/// the moved lines.
+fn move_lines<S: TextBuffer>(text: &mut S, cursor: CCursorPair, up: bool) -> Option<CCursorPair> {
+  let [min, max] = cursor.sorted();
+  let first = paragraph_at(text.as_ref(), min.index);
+  let last = paragraph_at(text.as_ref(), max.index);
+  let mut lines = text
+    .as_ref()
+    .split('\n')
+    .map(String::from)
+    .collect::<Vec<_>>();
+  if (up && first == 0) || (!up && last + 1 >= lines.len()) {
+    return None;
+  }
+
+  let shift = if up {
+    let line = lines.remove(first - 1);
+    let shift = -(line.chars().count() as isize + 1);
+    lines.insert(last, line);
+    shift
+  } else {
+    let line = lines.remove(last + 1);
+    let shift = line.chars().count() as isize + 1;
+    lines.insert(first, line);
+    shift
+  };
+  text.replace(&lines.join("\n"));
+
+  let shifted = |ccursor: CCursor| CCursor::new((ccursor.index as isize + shift) as usize);
+  Some(CCursorPair {
+    primary: shifted(cursor.primary),
+    secondary: shifted(cursor.secondary),
+  })
+}
+
+/// Finds the bracket matching the one just before or after `index`, returning both positions.
+fn matching_bracket(text: &str, index: usize) -> Option<(usize, usize)> {
+  let chars = text.chars().collect::<Vec<_>>();
+  let candidates = [index.checked_sub(1), Some(index)];
+  for at in candidates.iter().flatten().copied() {
+    let c = match chars.get(at) {
+      Some(c) => *c,
+      None => continue,
+    };
+
+    for &(open, close) in BRACKETS {
+      let found = if c == open {
+        let mut depth = 0;
+        (at..chars.len()).find(|&i| {
+          if chars[i] == open {
+            depth += 1;
+          } else if chars[i] == close {
+            depth -= 1;
+          }
+          depth == 0
+        })
+      } else if c == close {
+        let mut depth = 0;
+        (0..=at).rev().find(|&i| {
+          if chars[i] == close {
+            depth += 1;
+          } else if chars[i] == open {
+            depth -= 1;
+          }
+          depth == 0
+        })
+      } else {
+        continue;
+      };
+
+      if let Some(other) = found {
+        return Some((at.min(other), at.max(other)));
+      }
+    }
+  }
+  None
+}
+
+// ----------------------------------------------------------------------------
+
+/// Edits that are applied at every cursor when there are several.
+enum MultiCursorEdit {
+  Type(String),
+  Backspace,
+  Delete,
+  Newline,
+}
+
+impl MultiCursorEdit {
+  fn from_event(event: &Event, multiline: bool) -> Option<Self> {
+    match event {
+      Event::Text(typed) if !typed.is_empty() && typed != "\n" && typed != "\r" => {
+        Some(MultiCursorEdit::Type(typed.clone()))
+      }
+      Event::Key {
+        key: Key::Backspace,
+        pressed: true,
+        modifiers,
+      } if !modifiers.alt && !modifiers.command => Some(MultiCursorEdit::Backspace),
+      Event::Key {
+        key: Key::Delete,
+        pressed: true,
+        modifiers,
+      } if !modifiers.alt && !modifiers.command => Some(MultiCursorEdit::Delete),
+      Event::Key {
+        key: Key::Enter,
+        pressed: true,
+        ..
+      } if multiline => Some(MultiCursorEdit::Newline),
+      _ => None,
+    }
+  }
+
+  fn apply<S: TextBuffer>(&self, text: &mut S, cursor: CCursorPair) -> CCursorPair {
+    let [min, max] = cursor.sorted();
+    match self {
+      MultiCursorEdit::Type(typed) => type_text(text, cursor, typed),
+      MultiCursorEdit::Backspace if min.index == max.index => {
+        CCursorPair::one(delete_previous_char_or_pair(text, min))
+      }
+      MultiCursorEdit::Delete if min.index == max.index => {
+        CCursorPair::one(delete_next_char(text, min))
+      }
+      MultiCursorEdit::Backspace | MultiCursorEdit::Delete => {
+        CCursorPair::one(delete_selected_ccursor_range(text, [min, max]))
+      }
+      MultiCursorEdit::Newline => {
+        let mut ccursor = delete_selected_ccursor_range(text, [min, max]);
+        let indent = newline_indent(text.as_ref(), ccursor);
+        insert_text(&mut ccursor, text, &format!("\n{}", indent));
+        CCursorPair::one(ccursor)
+      }
+    }
+  }
+}
+
+/// Applies `edit` at each of `cursors`, from the last one in the text to the first so earlier
+/// cursors aren't invalidated, and returns the resulting cursors in the same order.
+fn edit_at_cursors<S: TextBuffer>(
+  text: &mut S,
+  cursors: &[CCursorPair],
+  mut edit: impl FnMut(&mut S, CCursorPair) -> CCursorPair,
+) -> Vec<CCursorPair> {
+  let mut order = (0..cursors.len()).collect::<Vec<_>>();
+  order.sort_by_key(|&i| std::cmp::Reverse(cursors[i].sorted()[0].index));
+
+  let mut edited = cursors.to_vec();
+  let mut done: Vec<usize> = vec![];
+  for i in order {
+    let len_before = text.as_ref().chars().count() as isize;
+    edited[i] = edit(text, cursors[i]);
+    let delta = text.as_ref().chars().count() as isize - len_before;
+
+    // Cursors after this one moved by however much text was inserted or deleted
+    for &j in done.iter() {
+      let shift = |ccursor: CCursor| CCursor::new((ccursor.index as isize + delta).max(0) as usize);
+      edited[j] = CCursorPair {
+        primary: shift(edited[j].primary),
+        secondary: shift(edited[j].secondary),
+      };
+    }
+    done.push(i);
+  }
+  edited
+}
+
+/// Finds the next occurrence of the text selected by `cursor` that isn't selected yet, wrapping
+/// around to the start of `text`.
+fn next_occurrence(
+  text: &str,
+  cursor: CCursorPair,
+  selected: &[CCursorPair],
+) -> Option<CCursorPair> {
+  let [min, max] = cursor.sorted();
+  let chars = text.chars().collect::<Vec<_>>();
+  let needle = chars.get(min.index..max.index)?;
+  let is_selected = |start: usize| {
+    start == min.index
+      || selected
+        .iter()
+        .any(|cursor| cursor.sorted()[0].index == start)
+  };
+
+  (max.index..chars.len())
+    .chain(0..max.index)
+    .filter(|&start| start + needle.len() <= chars.len())
+    .find(|&start| &chars[start..start + needle.len()] == needle && !is_selected(start))
+    .map(|start| CCursorPair::two(CCursor::new(start), CCursor::new(start + needle.len())))
+}
+
+// ----------------------------------------------------------------------------
+
+/// Draws the find/replace bar, returning whether it should be closed.
+fn find_bar(ui: &mut Ui, find: &mut FindState) -> bool {
+  let mut close = false;
+  ui.horizontal(|ui| {
+    let query = ui.add(
+      TextEdit::singleline(&mut find.query)
+        .hint_text("Find")
+        .desired_width(120.0),
+    );
+    if find.focus_query {
+      ui.memory().request_focus(query.id);
+      find.focus_query = false;
+    }
+    if query.lost_focus() && ui.input().key_pressed(Key::Enter) {
+      find.action = Some(FindAction::Next);
+      ui.memory().request_focus(query.id);
+    }
+    ui.checkbox(&mut find.regex, ".*")
+      .on_hover_text("Regular expression");
+
+    ui.add(
+      TextEdit::singleline(&mut find.replacement)
+        .hint_text("Replace")
+        .desired_width(120.0),
+    );
+    if ui.button("Next").clicked() {
+      find.action = Some(FindAction::Next);
+    }
+    if ui.button("Replace").clicked() {
+      find.action = Some(FindAction::Replace);
+    }
+    if ui.button("All").on_hover_text("Replace all").clicked() {
+      find.action = Some(FindAction::ReplaceAll);
+    }
+    close =
+      ui.small_button("x").clicked() || (query.lost_focus() && ui.input().key_pressed(Key::Escape));
+  });
+
+  if let Err(err) = find_matches("", find) {
+    ui.colored_label(Color32::RED, err);
+  }
+  close
+}
+
+/// Character ranges in `text` matching the query of `find`.
+fn find_matches(text: &str, find: &mut FindState) -> Result<Vec<Range<usize>>, String> {
+  if find.query.is_empty() {
+    return Ok(vec![]);
+  }
+
+  let byte_ranges = if find.regex {
+    find
+      .compiled_regex()?
+      .find_iter(text)
+      .map(|m| m.range())
+      .filter(|range| !range.is_empty())
+      .collect::<Vec<_>>()
+  } else {
+    text
+      .match_indices(find.query.as_str())
+      .map(|(start, matched)| start..start + matched.len())
+      .collect()
+  };
+
+  let char_index = |byte_index: usize| text[..byte_index].chars().count();
+  Ok(
+    byte_ranges
+      .into_iter()
+      .map(|range| char_index(range.start)..char_index(range.end))
+      .collect(),
+  )
+}
+
+/// What the match at the character range `range` of `text` should be replaced with, expanding
+/// `$1` style groups for regexes.
+fn find_replacement(find: &mut FindState, text: &str, range: &Range<usize>) -> String {
+  if !find.regex {
+    return find.replacement.clone();
+  }
+  let regex = match find.compiled_regex() {
+    Ok(regex) => regex,
+    Err(_) => return find.replacement.clone(),
+  };
+  // Capture in the whole text, so anchors and lookaround see the same context as the match did
+  let start = text
+    .char_indices()
+    .nth(range.start)
+    .map_or(text.len(), |(index, _)| index);
+  let mut replacement = String::new();
+  if let Some(captures) = regex
+    .captures_iter(text)
+    .find(|captures| captures.get(0).map_or(false, |m| m.start() == start))
+  {
+    captures.expand(&find.replacement, &mut replacement);
+  }
+  replacement
+}
+
+/// Performs `action`, returning the new selection if there is one.
+fn apply_find_action<S: TextBuffer>(
+  text: &mut S,
+  find: &mut FindState,
+  action: FindAction,
+  cursor: CCursorPair,
+) -> Option<CCursorPair> {
+  let matches = find_matches(text.as_ref(), find).ok()?;
+  let [min, max] = cursor.sorted();
+  let select =
+    |range: &Range<usize>| CCursorPair::two(CCursor::new(range.start), CCursor::new(range.end));
+  // Wraps around to the first match after the last one
+  let next_match = |matches: &[Range<usize>], after: usize| {
+    matches
+      .iter()
+      .find(|range| range.start >= after)
+      .or_else(|| matches.first())
+      .map(select)
+  };
+
+  match action {
+    FindAction::Next => next_match(&matches, max.index),
+    FindAction::Replace => {
+      let selected = matches
+        .iter()
+        .find(|range| range.start == min.index && range.end == max.index);
+      let range = match selected {
+        Some(range) => range.clone(),
+        // Select the next match first, so it can be seen before it is replaced
+        None => return next_match(&matches, min.index),
+      };
+
+      let replacement = find_replacement(find, text.as_ref(), &range);
+      text.delete_char_range(range.clone());
+      let end = range.start + text.insert_text(&replacement, range.start);
+
+      let matches = find_matches(text.as_ref(), find).ok()?;
+      next_match(&matches, end).or_else(|| Some(CCursorPair::one(CCursor::new(end))))
+    }
+    FindAction::ReplaceAll => {
+      // Only the highlighted matches are replaced, so e.g. empty regex matches are left alone.
+      // Going backwards keeps the ranges of the earlier matches valid.
+      let replacements = matches
+        .iter()
+        .map(|range| find_replacement(find, text.as_ref(), range))
+        .collect::<Vec<_>>();
+      for (range, replacement) in matches.into_iter().zip(replacements).rev() {
+        text.delete_char_range(range.clone());
+        text.insert_text(&replacement, range.start);
+      }
+      let len = text.as_ref().chars().count();
+      Some(CCursorPair::one(CCursor::new(min.index.min(len))))
+    }
+  }
+}
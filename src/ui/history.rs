@@ -0,0 +1,119 @@
+use crate::prelude::*;
+use std::path::{Path, PathBuf};
+
+const HISTORY_PATH: &str = "config/history.jsonl";
+const MAX_ENTRIES: usize = 1000;
+
+/// Inputs previously entered into the terminal, persisted to disk as one JSON string per line so
+/// multi-line blocks survive a round trip.
+pub struct CommandHistory {
+  entries: Vec<String>,
+  /// Position while browsing with up/down, or `None` when editing a new line.
+  browsing: Option<usize>,
+  /// What was typed before browsing started, restored when browsing past the newest entry.
+  draft: String,
+  path: PathBuf,
+}
+
+impl Default for CommandHistory {
+  fn default() -> Self {
+    CommandHistory::load(Path::new(HISTORY_PATH))
+  }
+}
+
+impl CommandHistory {
+  pub fn load(path: &Path) -> Self {
+    #[cfg(not(target_arch = "wasm32"))]
+    let entries = std::fs::read_to_string(path)
+      .map(|contents| {
+        contents
+          .lines()
+          .filter_map(|line| serde_json::from_str::<String>(line).ok())
+          .collect()
+      })
+      .unwrap_or_default();
+
+    #[cfg(target_arch = "wasm32")]
+    let entries = vec![];
+
+    CommandHistory {
+      entries,
+      browsing: None,
+      draft: String::new(),
+      path: path.to_owned(),
+    }
+  }
+
+  fn save(&self) -> anyhow::Result<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+      if let Some(dir) = self.path.parent() {
+        std::fs::create_dir_all(dir)?;
+      }
+      let lines = self
+        .entries
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+      std::fs::write(&self.path, lines.join("\n"))?;
+    }
+    Ok(())
+  }
+
+  pub fn push(&mut self, entry: String) {
+    self.browsing = None;
+    if entry.trim().is_empty() || self.entries.last() == Some(&entry) {
+      return;
+    }
+
+    self.entries.push(entry);
+    if self.entries.len() > MAX_ENTRIES {
+      let excess = self.entries.len() - MAX_ENTRIES;
+      self.entries.drain(..excess);
+    }
+
+    if let Err(e) = self.save() {
+      warn!("Failed to save terminal history: {}", e);
+    }
+  }
+
+  /// Steps back to an older entry, remembering `current` so it can be restored.
+  pub fn previous(&mut self, current: &str) -> Option<&str> {
+    let index = match self.browsing {
+      None => {
+        self.draft = current.to_owned();
+        self.entries.len().checked_sub(1)?
+      }
+      Some(index) => index.checked_sub(1)?,
+    };
+    self.browsing = Some(index);
+    Some(&self.entries[index])
+  }
+
+  /// Steps forward to a newer entry, or back to the draft after the newest one.
+  pub fn next(&mut self) -> Option<&str> {
+    let index = self.browsing?;
+    if index + 1 < self.entries.len() {
+      self.browsing = Some(index + 1);
+      Some(&self.entries[index + 1])
+    } else {
+      self.browsing = None;
+      Some(&self.draft)
+    }
+  }
+
+  pub fn get(&self, index: usize) -> Option<&str> {
+    self.entries.get(index).map(String::as_str)
+  }
+
+  /// Finds the newest entry older than `before` that contains `query`.
+  pub fn search(&self, query: &str, before: Option<usize>) -> Option<(usize, &str)> {
+    let end = before.unwrap_or(self.entries.len()).min(self.entries.len());
+    self.entries[..end]
+      .iter()
+      .enumerate()
+      .rev()
+      .find(|(_, entry)| entry.contains(query))
+      .map(|(index, entry)| (index, entry.as_str()))
+  }
+}
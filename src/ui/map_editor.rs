@@ -0,0 +1,544 @@
+use crate::{
+  cvars::{AppCvarsExt, Cvar, Cvars},
+  map::{
+    spawn_map_box, spawn_map_light, spawn_spawn_point, CurrentMap, MapBox, MapBoxColor, MapEntity,
+    MapLight, SaveMapEvent,
+  },
+  models::ModelInstance,
+  physics::ColliderParams,
+  player::{
+    input_map::{Action, ActionState},
+    raycast::ViewInfo,
+    spawn::SpawnPoint,
+  },
+  prelude::*,
+};
+use bevy_egui::{egui, EguiContext};
+use bevy_rapier3d::{na::Isometry3, prelude::*, rapier::dynamics::BodyStatus};
+
+use super::UiWindowManager;
+
+const GRID: &str = "editor.grid";
+const ANGLE_SNAP: &str = "editor.angle_snap";
+
+const AXES: [Vec3; 3] = [Vec3::X, Vec3::Y, Vec3::Z];
+const GIZMO_LENGTH: f32 = 2.;
+const GIZMO_WIDTH: f32 = 0.08;
+/// How close the view has to pass to a gizmo handle to grab it.
+const PICK_RADIUS: f32 = 0.2;
+/// Size of the spheres showing where lights and spawn points are.
+const MARKER_RADIUS: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+  Translate,
+  Rotate,
+  /// Only boxes can be scaled, since they're the only thing whose size the map stores.
+  Scale,
+}
+
+impl GizmoMode {
+  pub const ALL: &'static [GizmoMode] =
+    &[GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale];
+}
+
+struct Drag {
+  axis: usize,
+  /// Transform of the entity when the drag started.
+  start: Transform,
+  /// Where the view first grabbed the handle, on its axis or, when rotating, on the plane around it.
+  grab: Vec3,
+}
+
+/// State of the map editor. While it's enabled clicking selects parts of the map instead of grabbing
+/// them, and the spawn menu places static props.
+pub struct MapEditor {
+  pub enabled: bool,
+  pub selected: Option<Entity>,
+  pub mode: GizmoMode,
+  drag: Option<Drag>,
+}
+
+impl Default for MapEditor {
+  fn default() -> Self {
+    MapEditor {
+      enabled: false,
+      selected: None,
+      mode: GizmoMode::Translate,
+      drag: None,
+    }
+  }
+}
+
+struct EditorAssets {
+  handles: [Entity; 3],
+  handle_materials: [Handle<StandardMaterial>; 3],
+  marker_mesh: Handle<Mesh>,
+  light_material: Handle<StandardMaterial>,
+  spawn_point_material: Handle<StandardMaterial>,
+}
+
+struct GizmoHandle;
+
+/// Added to lights and spawn points while editing, pointing at the sphere that shows where they are.
+struct EditorMarker(Entity);
+
+fn axis_color(axis: usize) -> Color {
+  match axis {
+    0 => Color::RED,
+    1 => Color::GREEN,
+    _ => Color::BLUE,
+  }
+}
+
+fn snap(value: f32, step: f32) -> f32 {
+  if step > 0. {
+    (value / step).round() * step
+  } else {
+    value
+  }
+}
+
+/// Where something placed while editing ends up: the point being looked at, snapped to the grid.
+pub fn place_position(view_info: &ViewInfo, cvars: &Cvars) -> Vec3 {
+  let grid = cvars.float(GRID);
+  let point = view_info
+    .hit_point()
+    .unwrap_or_else(|| view_info.ray.point_at(5.))
+    .to_glam_vec3();
+  Vec3::new(
+    snap(point.x, grid),
+    snap(point.y, grid),
+    snap(point.z, grid),
+  )
+}
+
+/// Parameters of the closest points of the lines `a + s * u` and `b + t * v`, if they aren't
+/// parallel.
+fn closest_params(a: Vec3, u: Vec3, b: Vec3, v: Vec3) -> Option<(f32, f32)> {
+  let w = a - b;
+  let (uu, uv, vv) = (u.dot(u), u.dot(v), v.dot(v));
+  let (uw, vw) = (u.dot(w), v.dot(w));
+  let denominator = uu * vv - uv * uv;
+  if denominator.abs() < 1E-6 {
+    return None;
+  }
+  Some((
+    (uv * vw - vv * uw) / denominator,
+    (uu * vw - uv * uw) / denominator,
+  ))
+}
+
+fn ray_plane(origin: Vec3, dir: Vec3, point: Vec3, normal: Vec3) -> Option<Vec3> {
+  let denominator = dir.dot(normal);
+  if denominator.abs() < 1E-6 {
+    return None;
+  }
+  let toi = (point - origin).dot(normal) / denominator;
+  if toi < 0. {
+    return None;
+  }
+  Some(origin + dir * toi)
+}
+
+/// The gizmo handle closest to the view, if the view passes close enough to one.
+fn hovered_axis(origin: Vec3, dir: Vec3, center: Vec3) -> Option<usize> {
+  (0..3)
+    .filter_map(|axis| {
+      let (s, t) = closest_params(origin, dir, center, AXES[axis])?;
+      let distance = (origin + dir * s - (center + AXES[axis] * t)).length();
+      if s > 0. && (0. ..=GIZMO_LENGTH).contains(&t) && distance < PICK_RADIUS {
+        Some((axis, s))
+      } else {
+        None
+      }
+    })
+    .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+    .map(|(axis, _)| axis)
+}
+
+fn grab_point(mode: GizmoMode, origin: Vec3, dir: Vec3, center: Vec3, axis: usize) -> Option<Vec3> {
+  match mode {
+    GizmoMode::Rotate => ray_plane(origin, dir, center, AXES[axis]),
+    _ => closest_params(origin, dir, center, AXES[axis]).map(|(_, t)| center + AXES[axis] * t),
+  }
+}
+
+fn dragged_transform(mode: GizmoMode, drag: &Drag, point: Vec3, cvars: &Cvars) -> Transform {
+  let axis = AXES[drag.axis];
+  let center = drag.start.translation;
+  let grid = cvars.float(GRID);
+  let mut transform = drag.start;
+  match mode {
+    GizmoMode::Translate => {
+      let start = center.dot(axis);
+      let target = snap(start + (point - drag.grab).dot(axis), grid);
+      transform.translation += axis * (target - start);
+    }
+    GizmoMode::Rotate => {
+      let (from, to) = (drag.grab - center, point - center);
+      let angle = from.cross(to).dot(axis).atan2(from.dot(to));
+      let angle = snap(angle.to_degrees(), cvars.float(ANGLE_SNAP)).to_radians();
+      transform.rotation = Quat::from_axis_angle(axis, angle) * drag.start.rotation;
+    }
+    GizmoMode::Scale => {
+      let from = (drag.grab - center).dot(axis);
+      if from.abs() > 1E-3 {
+        // Boxes are cubes of size 2 scaled to their half extents
+        let start = drag.start.scale.dot(axis);
+        let size = snap(start * 2. * (point - center).dot(axis) / from, grid).max(grid.max(0.1));
+        transform.scale += axis * (size / 2. - start);
+      }
+    }
+  }
+  transform
+}
+
+fn init_editor(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+  let mut unlit = |color: Color| {
+    materials.add(StandardMaterial {
+      base_color: color,
+      unlit: true,
+      ..Default::default()
+    })
+  };
+  let handle_materials = [
+    unlit(axis_color(0)),
+    unlit(axis_color(1)),
+    unlit(axis_color(2)),
+  ];
+  let light_material = unlit(Color::YELLOW);
+  let spawn_point_material = unlit(Color::CYAN);
+
+  let mut handles = [Entity::from_bits(0); 3];
+  for (axis, handle) in handles.iter_mut().enumerate() {
+    let size = AXES[axis] * GIZMO_LENGTH + (Vec3::ONE - AXES[axis]) * GIZMO_WIDTH;
+    *handle = commands
+      .spawn_bundle(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Box::new(size.x, size.y, size.z))),
+        material: handle_materials[axis].clone(),
+        visible: Visible {
+          is_visible: false,
+          ..Default::default()
+        },
+        ..Default::default()
+      })
+      .insert_bundle((GizmoHandle, Name::new("gizmo handle")))
+      .id();
+  }
+
+  commands.insert_resource(EditorAssets {
+    handles,
+    handle_materials,
+    marker_mesh: meshes.add(Mesh::from(shape::Icosphere {
+      radius: MARKER_RADIUS,
+      subdivisions: 2,
+    })),
+    light_material,
+    spawn_point_material,
+  });
+}
+
+/// Selects what's clicked and drags the handles of the gizmo.
+fn editor_system(
+  mut commands: Commands,
+  actions: Res<ActionState>,
+  cvars: Res<Cvars>,
+  ui_window_manager: Res<UiWindowManager>,
+  view_info: Res<ViewInfo>,
+  mut editor: ResMut<MapEditor>,
+  editable_query: Query<(), Or<(With<MapEntity>, With<ModelInstance>)>>,
+  marker_query: Query<(Entity, &GlobalTransform), Or<(With<SpawnPoint>, With<PointLight>)>>,
+  box_query: Query<(), With<MapBoxColor>>,
+  mut transform_query: Query<&mut Transform, Without<GizmoHandle>>,
+  mut body_query: Query<&mut RigidBodyPosition>,
+) {
+  if !editor.enabled || ui_window_manager.is_showing() {
+    editor.drag = None;
+    return;
+  }
+
+  let editor = &mut *editor;
+  if let Some(selected) = editor.selected {
+    if transform_query.get_mut(selected).is_err() {
+      editor.selected = None;
+      editor.drag = None;
+    }
+  }
+
+  if actions.just_pressed(Action::Freeze) && editor.drag.is_none() {
+    let index = GizmoMode::ALL
+      .iter()
+      .position(|mode| *mode == editor.mode)
+      .unwrap();
+    editor.mode = GizmoMode::ALL[(index + 1) % GizmoMode::ALL.len()];
+  }
+
+  let origin = view_info.ray.origin.to_glam_vec3();
+  let dir = view_info.ray.dir.to_glam_vec3();
+
+  if actions.just_pressed(Action::Grab) {
+    if let Some(selected) = editor.selected {
+      let start = *transform_query.get_mut(selected).unwrap();
+      let scalable = editor.mode != GizmoMode::Scale || box_query.get(selected).is_ok();
+      let drag = hovered_axis(origin, dir, start.translation)
+        .filter(|_| scalable)
+        .and_then(|axis| {
+          let grab = grab_point(editor.mode, origin, dir, start.translation, axis)?;
+          Some(Drag { axis, start, grab })
+        });
+      if drag.is_some() {
+        editor.drag = drag;
+        return;
+      }
+    }
+
+    // Lights and spawn points don't have colliders, so pick them by how close the view passes
+    let mut picked = view_info
+      .hit
+      .as_ref()
+      .filter(|hit| editable_query.get(hit.entity).is_ok())
+      .map(|hit| (hit.entity, hit.intersection.toi));
+    for (entity, transform) in marker_query.iter() {
+      let offset = transform.translation - origin;
+      let along = offset.dot(dir);
+      let closer = picked.map_or(true, |(_, toi)| along < toi);
+      if along > 0. && closer && (offset - dir * along).length() < MARKER_RADIUS {
+        picked = Some((entity, along));
+      }
+    }
+    editor.selected = picked.map(|(entity, _)| entity);
+  } else if actions.pressed(Action::Grab) {
+    let (selected, drag) = match (editor.selected, editor.drag.as_ref()) {
+      (Some(selected), Some(drag)) => (selected, drag),
+      _ => return,
+    };
+    let point = match grab_point(editor.mode, origin, dir, drag.start.translation, drag.axis) {
+      Some(point) => point,
+      None => return,
+    };
+    let transform = dragged_transform(editor.mode, drag, point, &cvars);
+    *transform_query.get_mut(selected).unwrap() = transform;
+    if let Ok(mut position) = body_query.get_mut(selected) {
+      let isometry = Isometry3::from_parts(
+        transform.translation.to_na_translation(),
+        transform.rotation.to_na_unit_quat(),
+      );
+      position.position = isometry;
+      position.next_position = isometry;
+    }
+  } else if editor.drag.take().is_some() && editor.mode == GizmoMode::Scale {
+    // Rebuild the collider at the new size
+    commands
+      .entity(editor.selected.unwrap())
+      .insert(ColliderParams {
+        body_status: BodyStatus::Static,
+        mass: 10000.0,
+      });
+  }
+}
+
+/// Moves the gizmo to the selected entity, and highlights the handle that's hovered or dragged.
+fn update_gizmo(
+  editor: Res<MapEditor>,
+  view_info: Res<ViewInfo>,
+  assets: Res<EditorAssets>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+  selected_query: Query<&GlobalTransform, Without<GizmoHandle>>,
+  mut handle_query: Query<(&mut Transform, &mut Visible), With<GizmoHandle>>,
+) {
+  let center = editor
+    .selected
+    .filter(|_| editor.enabled)
+    .and_then(|selected| selected_query.get(selected).ok())
+    .map(|transform| transform.translation);
+
+  let active = match (&editor.drag, center) {
+    (Some(drag), _) => Some(drag.axis),
+    (None, Some(center)) => hovered_axis(
+      view_info.ray.origin.to_glam_vec3(),
+      view_info.ray.dir.to_glam_vec3(),
+      center,
+    ),
+    (None, None) => None,
+  };
+
+  for (axis, handle) in assets.handles.iter().enumerate() {
+    let (mut transform, mut visible) = handle_query.get_mut(*handle).unwrap();
+    visible.is_visible = center.is_some();
+    if let Some(center) = center {
+      transform.translation = center + AXES[axis] * GIZMO_LENGTH / 2.;
+    }
+
+    let color = if active == Some(axis) {
+      Color::YELLOW
+    } else {
+      axis_color(axis)
+    };
+    let material = materials.get_mut(&assets.handle_materials[axis]).unwrap();
+    if material.base_color != color {
+      material.base_color = color;
+    }
+  }
+}
+
+/// Shows lights and spawn points as spheres while editing, so they can be seen and clicked.
+fn update_markers(
+  mut commands: Commands,
+  editor: Res<MapEditor>,
+  assets: Res<EditorAssets>,
+  unmarked_query: Query<
+    (Entity, Option<&PointLight>),
+    (
+      Or<(With<SpawnPoint>, With<PointLight>)>,
+      Without<EditorMarker>,
+    ),
+  >,
+  marked_query: Query<(Entity, &EditorMarker)>,
+) {
+  if editor.enabled {
+    for (entity, light) in unmarked_query.iter() {
+      let material = if light.is_some() {
+        assets.light_material.clone()
+      } else {
+        assets.spawn_point_material.clone()
+      };
+      let marker = commands
+        .spawn_bundle(PbrBundle {
+          mesh: assets.marker_mesh.clone(),
+          material,
+          ..Default::default()
+        })
+        .insert(Parent(entity))
+        .id();
+      commands.entity(entity).insert(EditorMarker(marker));
+    }
+  } else {
+    for (entity, marker) in marked_query.iter() {
+      commands.entity(marker.0).despawn_recursive();
+      commands.entity(entity).remove::<EditorMarker>();
+    }
+  }
+}
+
+/// Window shown along with the spawn menu, for switching the editor on and adding to the map.
+fn editor_ui_system(
+  mut commands: Commands,
+  actions: Res<ActionState>,
+  cvars: Res<Cvars>,
+  ui_window_manager: Res<UiWindowManager>,
+  view_info: Res<ViewInfo>,
+  current_map: Res<CurrentMap>,
+  mut egui_context: ResMut<EguiContext>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+  mut save_map_events: EventWriter<SaveMapEvent>,
+  mut editor: ResMut<MapEditor>,
+  name_query: Query<&Name>,
+) {
+  if !ui_window_manager.is_showing() || !actions.pressed(Action::ShowUi) {
+    return;
+  }
+
+  let editor = &mut *editor;
+  let ctx = egui_context.ctx();
+  egui::Window::new("Map editor").show(ctx, |ui| {
+    ui.checkbox(&mut editor.enabled, "Edit map");
+    if !editor.enabled {
+      editor.selected = None;
+      return;
+    }
+
+    ui.horizontal(|ui| {
+      for mode in GizmoMode::ALL {
+        ui.radio_value(&mut editor.mode, *mode, format!("{:?}", mode));
+      }
+    });
+
+    let selected = editor
+      .selected
+      .map(|entity| {
+        name_query
+          .get(entity)
+          .map_or_else(|_| format!("{:?}", entity), |name| name.as_str().to_owned())
+      })
+      .unwrap_or_else(|| "nothing".to_owned());
+    ui.label(format!("Selected: {}", selected));
+
+    let position = place_position(&view_info, &cvars);
+    ui.horizontal(|ui| {
+      if ui.button("Add box").clicked() {
+        let map_box = MapBox {
+          position: position + Vec3::Y,
+          size: Vec3::splat(2.),
+          rotation: Vec3::ZERO,
+          color: [1., 1., 1.],
+        };
+        editor.selected = Some(spawn_map_box(
+          &mut commands,
+          &mut meshes,
+          &mut materials,
+          &map_box,
+        ));
+      }
+      if ui.button("Add light").clicked() {
+        let light = MapLight {
+          position: position + Vec3::Y * 2.,
+          color: [1., 1., 1.],
+          intensity: 200.,
+          range: 20.,
+        };
+        editor.selected = Some(spawn_map_light(&mut commands, &light));
+      }
+      if ui.button("Add spawn point").clicked() {
+        editor.selected = Some(spawn_spawn_point(&mut commands, position + Vec3::Y));
+      }
+    });
+
+    if let Some(selected) = editor.selected {
+      if ui.button("Delete selected").clicked() {
+        commands.entity(selected).despawn_recursive();
+        editor.selected = None;
+      }
+    }
+
+    if ui.button(format!("Save {}", current_map.0)).clicked() {
+      save_map_events.send(SaveMapEvent {
+        name: current_map.0.clone(),
+      });
+    }
+  });
+}
+
+pub struct MapEditorPlugin;
+impl Plugin for MapEditorPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<MapEditor>()
+      .add_cvar(
+        Cvar::float(
+          GRID,
+          "Grid the map editor snaps positions and sizes to",
+          0.5,
+        )
+        .range(0., 100.),
+      )
+      .add_cvar(
+        Cvar::float(
+          ANGLE_SNAP,
+          "Angle in degrees the map editor snaps rotations to",
+          15.,
+        )
+        .range(0., 180.),
+      )
+      .add_startup_system(init_editor.system())
+      .add_system(editor_system.system())
+      .add_system(update_gizmo.system())
+      .add_system(update_markers.system())
+      .add_system(editor_ui_system.system());
+  }
+}
@@ -5,9 +5,13 @@ use bevy_egui::{
 };
 use std::collections::HashMap;
 
+mod controls;
 mod debugger;
 mod editor;
+mod history;
+pub mod map_editor;
 mod spawnmenu;
+mod tabs;
 mod terminal;
 
 #[derive(Default)]
@@ -119,7 +123,9 @@ impl Plugin for UiPlugin {
       .add_system(ui_window_system.system())
       .add_system(configure_fonts.system())
       // Individual UI plugins
+      .add_plugin(controls::ControlsPlugin)
       .add_plugin(debugger::DebuggerPlugin)
+      .add_plugin(map_editor::MapEditorPlugin)
       .add_plugin(spawnmenu::SpawnmenuPlugin)
       .add_plugin(terminal::TerminalPlugin);
   }
@@ -1,6 +1,10 @@
 use crate::{
+  cvars::Cvars,
   models::{ModelInfo, SpawnModelEvent, Thumbnail},
-  player::{controller::CharacterController, raycast::ViewInfo},
+  player::{
+    input_map::{Action, ActionState},
+    raycast::ViewInfo,
+  },
   prelude::*,
 };
 
@@ -11,7 +15,10 @@ use bevy_rapier3d::{
   rapier::dynamics::BodyStatus,
 };
 
-use super::{InternedTextures, UiLock, UiWindowManager};
+use super::{
+  map_editor::{self, MapEditor},
+  InternedTextures, UiLock, UiWindowManager,
+};
 
 fn load_assets(
   mut egui_context: ResMut<EguiContext>,
@@ -24,57 +31,84 @@ fn load_assets(
   }
 }
 
+fn spawn_position(view_info: &ViewInfo) -> Isometry3<f32> {
+  // let aabb = AABB::new_invalid();
+  // // let aabb = decomp.aabb();
+  // let half_height = aabb.half_extents().y;
+  let half_height = 5.;
+  let mut translation = view_info
+    .hit_point()
+    .unwrap_or_else(|| view_info.ray.point_at(half_height));
+  translation += Vector3::new(0., half_height, 0.);
+  Isometry3::from_parts(
+    Translation3::from(translation.coords),
+    UnitQuaternion::identity(),
+  )
+}
+
 fn spawn_ui_system(
-  controller: Res<CharacterController>,
-  keyboard_input: Res<Input<KeyCode>>,
+  actions: Res<ActionState>,
   mut egui_context: ResMut<EguiContext>,
   interned_textures: Res<InternedTextures>,
   mut spawn_model_events: ResMut<Events<SpawnModelEvent>>,
   model_query: Query<(Entity, &ModelInfo)>,
   view_info: Res<ViewInfo>,
+  map_editor: Res<MapEditor>,
+  cvars: Res<Cvars>,
   mut ui_window_manager: ResMut<UiWindowManager>,
   mut ui_lock: Local<Option<UiLock>>,
+  // Model picked with the d-pad or arrow keys
+  mut selected: Local<usize>,
 ) {
-  let key = controller.input_map.key_show_ui;
-  if keyboard_input.just_pressed(key) {
+  if actions.just_pressed(Action::ShowUi) {
     *ui_lock = ui_window_manager.try_show();
-  } else if keyboard_input.just_released(key) && ui_lock.is_some() {
+  } else if actions.just_released(Action::ShowUi) && ui_lock.is_some() {
     let lock = ui_lock.take().unwrap();
     ui_window_manager.unshow(lock);
   }
 
-  if keyboard_input.pressed(key) {
+  if actions.pressed(Action::ShowUi) {
+    let count = model_query.iter().count();
+    if count > 0 {
+      if actions.just_pressed(Action::SpawnMenuNext) {
+        *selected += 1;
+      }
+      if actions.just_pressed(Action::SpawnMenuPrevious) {
+        *selected += count - 1;
+      }
+      *selected %= count;
+    }
+
     let ctx = egui_context.ctx();
     egui::Window::new("Spawn window").show(ctx, |ui| {
-      for (model, model_info) in model_query.iter() {
+      for (i, (model, model_info)) in model_query.iter().enumerate() {
         let texture_id = if let Some(texture_id) = interned_textures.get_egui_id(&model_info.name) {
           texture_id
         } else {
           interned_textures.null_texture()
         };
 
-        let thumbnail = ui.add(egui::widgets::ImageButton::new(
-          egui::TextureId::User(texture_id),
-          [100.0, 100.0],
-        ));
+        let is_selected = i == *selected;
+        let thumbnail = ui.add(
+          egui::widgets::ImageButton::new(egui::TextureId::User(texture_id), [100.0, 100.0])
+            .selected(is_selected),
+        );
 
-        if thumbnail.clicked() {
-          // let aabb = AABB::new_invalid();
-          // // let aabb = decomp.aabb();
-          // let half_height = aabb.half_extents().y;
-          let half_height = 5.;
-          let mut translation = view_info
-            .hit_point()
-            .unwrap_or_else(|| view_info.ray.point_at(half_height));
-          translation += Vector3::new(0., half_height, 0.);
-          let position = Isometry3::from_parts(
-            Translation3::from(translation.coords),
-            UnitQuaternion::identity(),
-          );
+        if thumbnail.clicked() || (is_selected && actions.just_pressed(Action::SpawnMenuSelect)) {
+          // While editing the map, props are placed as part of it instead of dropped in
+          let (position, body_status) = if map_editor.enabled {
+            let translation = map_editor::place_position(&view_info, &cvars);
+            (
+              Isometry3::from_parts(translation.to_na_translation(), UnitQuaternion::identity()),
+              BodyStatus::Static,
+            )
+          } else {
+            (spawn_position(&view_info), BodyStatus::Dynamic)
+          };
           spawn_model_events.send(SpawnModelEvent {
             model,
             position,
-            body_status: BodyStatus::Dynamic,
+            body_status,
           });
         }
       }
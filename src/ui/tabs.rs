@@ -0,0 +1,128 @@
+use super::editor;
+use anyhow::{anyhow, bail};
+
+const ASSETS_DIR: &str = "assets";
+const SCRIPTS_DIR: &str = "scripts";
+
+/// A script open in the terminal's editor.
+#[derive(Default)]
+pub struct EditorTab {
+  /// Asset path of the file, e.g. `scripts/thruster.py`, or `None` until it is first saved.
+  pub path: Option<String>,
+  pub code: String,
+  /// Contents of the file as of the last open or save, to tell whether there are unsaved edits.
+  saved_code: String,
+  pub editor_state: editor::State,
+}
+
+impl EditorTab {
+  pub fn open(path: String) -> anyhow::Result<Self> {
+    let code = read_asset(&path)?;
+    Ok(EditorTab {
+      path: Some(path),
+      saved_code: code.clone(),
+      code,
+      editor_state: editor::State::default(),
+    })
+  }
+
+  pub fn save(&mut self) -> anyhow::Result<()> {
+    let path = self
+      .path
+      .as_ref()
+      .ok_or_else(|| anyhow!("the script doesn't have a file name yet"))?;
+    write_asset(path, &self.code)?;
+    self.saved_code = self.code.clone();
+    Ok(())
+  }
+
+  /// Saves to `name` within the scripts directory, adding a `.py` extension if it is missing.
+  pub fn save_as(&mut self, name: &str) -> anyhow::Result<()> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(|c| c == '/' || c == '\\') || name.starts_with('.') {
+      bail!("'{}' is not a valid script name", name);
+    }
+    let name = if name.ends_with(".py") {
+      name.to_owned()
+    } else {
+      format!("{}.py", name)
+    };
+
+    let previous = self.path.replace(format!("{}/{}", SCRIPTS_DIR, name));
+    let result = self.save();
+    if result.is_err() {
+      self.path = previous;
+    }
+    result
+  }
+
+  pub fn is_dirty(&self) -> bool {
+    self.code != self.saved_code
+  }
+
+  /// The name scripts run from this tab are compiled with, so tracebacks point back to it.
+  pub fn filename(&self) -> &str {
+    self.path.as_deref().unwrap_or("<untitled>")
+  }
+
+  pub fn title(&self) -> String {
+    let name = self
+      .path
+      .as_ref()
+      .and_then(|path| path.rsplit('/').next())
+      .unwrap_or("untitled");
+    if self.is_dirty() {
+      format!("{}*", name)
+    } else {
+      name.to_owned()
+    }
+  }
+}
+
+/// Asset paths of the Python files in the scripts directory.
+pub fn script_files() -> Vec<String> {
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    let dir = std::path::Path::new(ASSETS_DIR).join(SCRIPTS_DIR);
+    let mut files = std::fs::read_dir(dir)
+      .map(|entries| {
+        entries
+          .filter_map(|entry| entry.ok())
+          .filter_map(|entry| entry.file_name().into_string().ok())
+          .filter(|name| name.ends_with(".py"))
+          .map(|name| format!("{}/{}", SCRIPTS_DIR, name))
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_default();
+    files.sort();
+    files
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  vec![]
+}
+
+fn read_asset(path: &str) -> anyhow::Result<String> {
+  #[cfg(not(target_arch = "wasm32"))]
+  return Ok(std::fs::read_to_string(
+    std::path::Path::new(ASSETS_DIR).join(path),
+  )?);
+
+  #[cfg(target_arch = "wasm32")]
+  bail!("opening files isn't supported on the web");
+}
+
+fn write_asset(path: &str, contents: &str) -> anyhow::Result<()> {
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    let path = std::path::Path::new(ASSETS_DIR).join(path);
+    if let Some(dir) = path.parent() {
+      std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, contents)?;
+    return Ok(());
+  }
+
+  #[cfg(target_arch = "wasm32")]
+  bail!("saving files isn't supported on the web");
+}
@@ -1,51 +1,444 @@
 use super::{
-  editor::{self, CodeEditor},
+  editor::CodeEditor,
+  history::CommandHistory,
+  tabs::{self, EditorTab},
   UiLock, UiWindowManager,
 };
 use crate::{
-  player::controller::CharacterController,
+  console::{
+    AppConsoleExt, ConsoleCommand, ConsoleCommandEvent, ConsoleCompletionRequestEvent,
+    ConsoleCompletions,
+  },
+  player::input_map::{Action, ActionState},
   prelude::*,
-  scripts::{pymod::ScriptOutputEvent, RunScriptEvent},
+  scripts::{
+    introspection::{
+      CheckScriptEvent, CompletionRequestEvent, LastScriptError, ScriptCompletions,
+      ScriptDiagnostic, ScriptDiagnostics,
+    },
+    pymod::{ScriptOutputEvent, ScriptOutputLevel, METHOD_SIGNATURES},
+    sandbox::ScriptTrust,
+    RunScriptEvent,
+  },
 };
 use bevy_egui::{egui, EguiContext};
-use egui::{widgets, Align, Key, Layout, ScrollArea, TextStyle, Ui};
-use itertools::Itertools;
+use egui::{widgets, Align, Color32, Key, Layout, ScrollArea, TextStyle, Ui};
+use rustpython_parser::error::{LexicalErrorType, ParseErrorType};
+use rustpython_vm::compile::{self, CompileError, CompileErrorType, Mode};
 use syntect::{highlighting::ThemeSet, parsing::SyntaxSet};
 
+const MAX_SCROLLBACK: usize = 1000;
+const INDENT: &str = "    ";
+
+#[derive(Default)]
+struct ReverseSearch {
+  query: String,
+  found: Option<usize>,
+}
+
+enum FileDialog {
+  Open { files: Vec<String> },
+  SaveAs { name: String },
+  ConfirmClose,
+}
+
 #[derive(Default)]
 struct TerminalState {
   input: String,
-  logs: Vec<String>,
-  code: String,
-  editor_state: editor::State,
+  /// Lines of an incomplete block, e.g. the body of a `def` that is still being typed.
+  pending: Vec<String>,
+  history: CommandHistory,
+  search: Option<ReverseSearch>,
+  logs: Vec<(ScriptOutputLevel, String)>,
+  scroll_to_bottom: bool,
+  tabs: Vec<EditorTab>,
+  active_tab: usize,
+  file_dialog: Option<FileDialog>,
+  requested_completion_path: Option<String>,
+  /// The console command line completions were last requested for.
+  requested_console_line: Option<String>,
 }
 
-fn repl(state: &mut TerminalState, ui: &mut Ui, run_script_events: &mut Events<RunScriptEvent>) {
+impl TerminalState {
+  /// Appends to the last entry when it is the same level and an unfinished line, so that
+  /// e.g. the separate writes `print` makes for its text and newline end up in one entry.
+  fn push_log(&mut self, level: ScriptOutputLevel, output: &str) {
+    match self.logs.last_mut() {
+      Some((last_level, last)) if *last_level == level && !last.ends_with('\n') => {
+        last.push_str(output);
+      }
+      _ => {
+        self.logs.push((level, output.to_owned()));
+      }
+    }
+
+    if self.logs.len() > MAX_SCROLLBACK {
+      let excess = self.logs.len() - MAX_SCROLLBACK;
+      self.logs.drain(..excess);
+    }
+    self.scroll_to_bottom = true;
+  }
+
+  /// Handles a line entered into the REPL, running it once it completes a statement. Lines
+  /// starting with `/` are console commands rather than Python.
+  fn submit(
+    &mut self,
+    line: String,
+    run_script_events: &mut Events<RunScriptEvent>,
+    console_command_events: &mut Events<ConsoleCommandEvent>,
+  ) {
+    let prompt = if self.pending.is_empty() {
+      ">>>"
+    } else {
+      "..."
+    };
+    self.push_log(ScriptOutputLevel::Output, &format!("{} {}\n", prompt, line));
+
+    if self.pending.is_empty() && line.starts_with('/') {
+      console_command_events.send(ConsoleCommandEvent {
+        line: line[1..].to_owned(),
+      });
+      self.history.push(line);
+      self.input = String::new();
+      return;
+    }
+
+    self.pending.push(line.clone());
+    let code = self.pending.join("\n");
+
+    // Like the Python REPL, compound statements end with an empty line, since more clauses could
+    // follow, while anything else runs as soon as it's complete
+    let complete = if starts_compound_statement(&self.pending[0]) {
+      line.trim().is_empty()
+    } else {
+      !needs_continuation(&code)
+    };
+
+    if complete {
+      self.pending.clear();
+      self.history.push(code.clone());
+      self.input = String::new();
+      run_script_events.send(RunScriptEvent {
+        code: code + "\n",
+        mode: Mode::Single,
+        trust: ScriptTrust::Trusted,
+        filename: "<stdin>".to_owned(),
+      });
+    } else {
+      self.input = continuation_indent(&line);
+    }
+  }
+
+  fn open_tab(&mut self, path: String) {
+    if let Some(index) = self
+      .tabs
+      .iter()
+      .position(|tab| tab.path.as_ref() == Some(&path))
+    {
+      self.active_tab = index;
+      return;
+    }
+
+    match EditorTab::open(path.clone()) {
+      Ok(tab) => {
+        self.tabs.push(tab);
+        self.active_tab = self.tabs.len() - 1;
+      }
+      Err(e) => {
+        self.push_log(
+          ScriptOutputLevel::Error,
+          &format!("Failed to open {}: {}\n", path, e),
+        );
+      }
+    }
+  }
+
+  fn save_tab(&mut self) {
+    let tab = &mut self.tabs[self.active_tab];
+    if tab.path.is_none() {
+      self.file_dialog = Some(FileDialog::SaveAs {
+        name: String::new(),
+      });
+      return;
+    }
+
+    let filename = tab.filename().to_owned();
+    if let Err(e) = tab.save() {
+      self.push_log(
+        ScriptOutputLevel::Error,
+        &format!("Failed to save {}: {}\n", filename, e),
+      );
+    }
+  }
+
+  fn save_tab_as(&mut self, name: &str) {
+    if let Err(e) = self.tabs[self.active_tab].save_as(name) {
+      self.push_log(
+        ScriptOutputLevel::Error,
+        &format!("Failed to save {}: {}\n", name, e),
+      );
+    }
+  }
+
+  fn close_tab(&mut self) {
+    self.tabs.remove(self.active_tab);
+    if self.tabs.is_empty() {
+      self.tabs.push(EditorTab::default());
+    }
+    self.active_tab = self.active_tab.min(self.tabs.len() - 1);
+  }
+}
+
+/// Removes the indentation shared by all non-empty lines, so an indented selection can run on
+/// its own.
+fn dedent(code: &str) -> String {
+  let indent = code
+    .lines()
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| line.len() - line.trim_start().len())
+    .min()
+    .unwrap_or(0);
+  code
+    .lines()
+    .map(|line| line.get(indent..).unwrap_or_else(|| line.trim_start()))
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Whether `code` is the start of a statement, e.g. with a bracket left open, rather than either
+/// complete or wrong.
+fn needs_continuation(code: &str) -> bool {
+  let result = compile::compile(
+    &format!("{}\n", code),
+    Mode::Single,
+    "<stdin>".to_owned(),
+    Default::default(),
+  );
+  matches!(
+    result,
+    Err(CompileError {
+      error: CompileErrorType::Parse(ParseErrorType::Eof),
+      ..
+    }) | Err(CompileError {
+      error: CompileErrorType::Parse(ParseErrorType::Lexical(LexicalErrorType::Eof)),
+      ..
+    })
+  )
+}
+
+/// Whether `line` starts a statement like `if` or `def` that has a block.
+fn starts_compound_statement(line: &str) -> bool {
+  let line = line.trim_start();
+  let keyword = line
+    .split(|c: char| !c.is_alphanumeric() && c != '_')
+    .next()
+    .unwrap_or_default();
+  line.starts_with('@')
+    || ["if", "while", "for", "try", "with", "def", "class", "async"].contains(&keyword)
+}
+
+fn continuation_indent(line: &str) -> String {
+  let indent = line
+    .chars()
+    .take_while(|c| c.is_whitespace())
+    .collect::<String>();
+  if line.trim_end().ends_with(':') {
+    indent + INDENT
+  } else {
+    indent
+  }
+}
+
+/// Longest prefix shared by all `candidates`, ignoring case like console completion does.
+fn common_prefix(candidates: &[String]) -> &str {
+  let first = match candidates.first() {
+    Some(first) => first,
+    None => return "",
+  };
+  let mut len = first.len();
+  for candidate in candidates[1..].iter() {
+    len = first[..len]
+      .char_indices()
+      .zip(candidate.chars())
+      .take_while(|((_, a), b)| a.eq_ignore_ascii_case(b))
+      .last()
+      .map(|((i, a), _)| i + a.len_utf8())
+      .unwrap_or(0);
+  }
+  &first[..len]
+}
+
+fn level_color(level: ScriptOutputLevel) -> Option<Color32> {
+  match level {
+    ScriptOutputLevel::Output => None,
+    ScriptOutputLevel::Debug => Some(Color32::GRAY),
+    ScriptOutputLevel::Info => Some(Color32::LIGHT_BLUE),
+    ScriptOutputLevel::Warning => Some(Color32::YELLOW),
+    ScriptOutputLevel::Error => Some(Color32::LIGHT_RED),
+  }
+}
+
+fn repl(
+  state: &mut TerminalState,
+  ui: &mut Ui,
+  run_script_events: &mut Events<RunScriptEvent>,
+  console_completions: &ConsoleCompletions,
+  console_command_events: &mut Events<ConsoleCommandEvent>,
+  console_completion_request_events: &mut Events<ConsoleCompletionRequestEvent>,
+) {
   ui.with_layout(Layout::bottom_up(Align::left()), |ui| {
-    let input_field =
-      ui.add(widgets::TextEdit::singleline(&mut state.input).text_style(TextStyle::Monospace));
-
-    let pressed_enter = ui.input().events.iter().any(|e| match e {
-      egui::Event::Key {
-        key: Key::Enter,
-        pressed: true,
-        ..
-      } => true,
-      _ => false,
-    });
+    let (
+      pressed_enter,
+      pressed_tab,
+      pressed_up,
+      pressed_down,
+      pressed_escape,
+      pressed_search,
+      pressed_clear,
+    ) = {
+      let input = ui.input();
+      (
+        input.key_pressed(Key::Enter),
+        input.key_pressed(Key::Tab),
+        input.key_pressed(Key::ArrowUp),
+        input.key_pressed(Key::ArrowDown),
+        input.key_pressed(Key::Escape),
+        input.modifiers.ctrl && input.key_pressed(Key::R),
+        input.modifiers.ctrl && input.key_pressed(Key::L),
+      )
+    };
+
+    let prompt = if state.search.is_some() {
+      "(reverse-i-search)"
+    } else if state.pending.is_empty() {
+      ">>>"
+    } else {
+      "..."
+    };
+    let input_field = ui
+      .horizontal(|ui| {
+        ui.add(widgets::Label::new(prompt).monospace());
+        let text = match &mut state.search {
+          Some(search) => &mut search.query,
+          None => &mut state.input,
+        };
+        ui.add(
+          widgets::TextEdit::singleline(text)
+            .text_style(TextStyle::Monospace)
+            .desired_width(f32::INFINITY),
+        )
+      })
+      .inner;
+    let has_focus = input_field.has_focus();
+
+    let console_line = if state.search.is_none() && state.pending.is_empty() {
+      state.input.strip_prefix('/').map(|line| line.to_owned())
+    } else {
+      None
+    };
+    if let Some(line) = &console_line {
+      if state.requested_console_line.as_ref() != Some(line) {
+        console_completion_request_events
+          .send(ConsoleCompletionRequestEvent { line: line.clone() });
+        state.requested_console_line = Some(line.clone());
+      }
 
-    if pressed_enter && input_field.lost_kb_focus() {
-      let code = state.input.clone();
-      state.logs.push(format!(">>> {}\n", code));
-      state.input = String::new();
+      let candidates = if console_completions.line.as_ref() == Some(line) {
+        &console_completions.candidates[..]
+      } else {
+        &[]
+      };
+      if !candidates.is_empty() {
+        ui.add(
+          widgets::Label::new(candidates.join("  "))
+            .monospace()
+            .weak(),
+        );
+      }
+
+      if has_focus && pressed_tab {
+        // Tab moves focus to the next widget, so take it back
+        ui.memory().request_focus(input_field.id);
+        let prefix = common_prefix(candidates);
+        if !prefix.is_empty() {
+          let start = state
+            .input
+            .rfind(char::is_whitespace)
+            .map(|i| i + 1)
+            .unwrap_or(1);
+          state.input.replace_range(start.., prefix);
+          if candidates.len() == 1 {
+            state.input.push(' ');
+          }
+        }
+      }
+    }
+
+    if let Some(search) = &mut state.search {
+      if input_field.changed() {
+        search.found = state
+          .history
+          .search(&search.query, None)
+          .map(|(index, _)| index);
+      }
+      if pressed_search {
+        // Search again for an older match, keeping the current one if there isn't any
+        if let Some((index, _)) = state.history.search(&search.query, search.found) {
+          search.found = Some(index);
+        }
+      }
+
+      let found = search.found.and_then(|index| state.history.get(index));
+      ui.add(
+        widgets::Label::new(format!("`{}': {}", search.query, found.unwrap_or("")))
+          .monospace()
+          .weak(),
+      );
+
+      if pressed_enter && input_field.lost_focus() {
+        state.input = found.unwrap_or("").to_owned();
+        state.search = None;
+        ui.memory().request_focus(input_field.id);
+      } else if pressed_escape {
+        state.search = None;
+        ui.memory().request_focus(input_field.id);
+      }
+    } else if has_focus && pressed_search {
+      state.search = Some(ReverseSearch::default());
+    } else if has_focus && pressed_up {
+      if let Some(entry) = state.history.previous(&state.input) {
+        state.input = entry.to_owned();
+      }
+    } else if has_focus && pressed_down {
+      if let Some(entry) = state.history.next() {
+        state.input = entry.to_owned();
+      }
+    } else if pressed_enter && input_field.lost_focus() {
+      let line = std::mem::take(&mut state.input);
+      state.submit(line, run_script_events, console_command_events);
       ui.memory().request_focus(input_field.id);
+    }
 
-      run_script_events.send(RunScriptEvent { code });
+    if has_focus && pressed_clear {
+      state.logs.clear();
     }
 
-    //ScrollArea::auto_sized().id_source("repl").show(ui, |ui| {
-    ui.add(widgets::Label::new(state.logs.iter().join("")).monospace());
-    //});
+    ScrollArea::auto_sized().id_source("repl").show(ui, |ui| {
+      ui.with_layout(Layout::top_down(Align::left()), |ui| {
+        for (level, output) in state.logs.iter() {
+          let label = widgets::Label::new(output.trim_end_matches('\n')).monospace();
+          ui.add(match level_color(*level) {
+            Some(color) => label.text_color(color),
+            None => label,
+          });
+        }
+
+        if state.scroll_to_bottom {
+          ui.scroll_to_cursor(Align::Max);
+          state.scroll_to_bottom = false;
+        }
+      });
+    });
   });
 }
 
@@ -63,30 +456,215 @@ impl Default for EditorResources {
   }
 }
 
+fn file_dialog(state: &mut TerminalState, ui: &mut Ui) {
+  let mut open = None;
+  let mut save_as = None;
+  let mut discard = false;
+  let mut close = false;
+
+  match &mut state.file_dialog {
+    Some(FileDialog::Open { files }) => {
+      if files.is_empty() {
+        ui.label("There are no scripts in assets/scripts.");
+      }
+      for file in files.iter() {
+        if ui.button(file).clicked() {
+          open = Some(file.clone());
+        }
+      }
+      close = ui.button("Cancel").clicked();
+    }
+    Some(FileDialog::SaveAs { name }) => {
+      ui.horizontal(|ui| {
+        ui.label("Save as scripts/");
+        let response = ui.text_edit_singleline(name);
+        if ui.button("Save").clicked()
+          || (response.lost_focus() && ui.input().key_pressed(Key::Enter))
+        {
+          save_as = Some(name.clone());
+        }
+        close = ui.button("Cancel").clicked();
+      });
+    }
+    Some(FileDialog::ConfirmClose) => {
+      ui.horizontal(|ui| {
+        ui.label("Discard unsaved changes?");
+        discard = ui.button("Discard").clicked();
+        close = ui.button("Cancel").clicked();
+      });
+    }
+    None => {}
+  }
+
+  if let Some(path) = open {
+    state.open_tab(path);
+    close = true;
+  }
+  if let Some(name) = save_as {
+    state.save_tab_as(&name);
+    close = true;
+  }
+  if discard {
+    state.close_tab();
+    close = true;
+  }
+  if close {
+    state.file_dialog = None;
+  }
+}
+
 fn editor(
   state: &mut TerminalState,
   ui: &mut Ui,
   editor_resources: &EditorResources,
   run_script_events: &mut Events<RunScriptEvent>,
+  script_completions: &ScriptCompletions,
+  script_diagnostics: &ScriptDiagnostics,
+  last_error: &LastScriptError,
+  check_script_events: &mut Events<CheckScriptEvent>,
+  completion_request_events: &mut Events<CompletionRequestEvent>,
 ) {
+  if state.tabs.is_empty() {
+    state.tabs.push(EditorTab::default());
+  }
+  let previous_tab = state.active_tab;
+
+  let pressed_save = {
+    let input = ui.input();
+    input.modifiers.ctrl && input.key_pressed(Key::S)
+  };
+  if pressed_save {
+    state.save_tab();
+  }
+
   ui.with_layout(Layout::top_down(Align::left()), |ui| {
+    ui.horizontal_wrapped(|ui| {
+      for (index, tab) in state.tabs.iter().enumerate() {
+        if ui
+          .selectable_label(index == state.active_tab, tab.title())
+          .clicked()
+        {
+          state.active_tab = index;
+        }
+      }
+      if ui.small_button("+").clicked() {
+        state.tabs.push(EditorTab::default());
+        state.active_tab = state.tabs.len() - 1;
+      }
+    });
+
+    ui.horizontal(|ui| {
+      if ui.button("Open").clicked() {
+        state.file_dialog = Some(FileDialog::Open {
+          files: tabs::script_files(),
+        });
+      }
+      if ui.button("Save").clicked() {
+        state.save_tab();
+      }
+      if ui.button("Save as").clicked() {
+        state.file_dialog = Some(FileDialog::SaveAs {
+          name: String::new(),
+        });
+      }
+      if ui.button("Close").clicked() {
+        if state.tabs[state.active_tab].is_dirty() {
+          state.file_dialog = Some(FileDialog::ConfirmClose);
+        } else {
+          state.close_tab();
+        }
+      }
+    });
+
+    file_dialog(state, ui);
+
+    let tab = &mut state.tabs[state.active_tab];
+
+    // Only offer completions that were computed for what is being typed now
+    let completions: &[String] = match &script_completions.path {
+      Some(path) if *path == tab.editor_state.completion_path => {
+        script_completions.names.as_slice()
+      }
+      _ => &[],
+    };
+
+    let error = last_error
+      .0
+      .as_ref()
+      .filter(|error| error.filename == tab.filename());
+    let mut diagnostics = script_diagnostics.0.clone();
+    diagnostics.extend(error.map(|error| ScriptDiagnostic {
+      row: error.row,
+      column: 0,
+      message: error.message.clone(),
+    }));
+
     ScrollArea::auto_sized().id_source("editor").show(ui, |ui| {
-      CodeEditor::multiline(&mut state.code)
+      let response = CodeEditor::multiline(&mut tab.code)
         .text_style(TextStyle::Monospace)
-        .ui(ui, &mut state.editor_state, editor_resources);
+        .completions(completions)
+        .diagnostics(&diagnostics)
+        .signatures(METHOD_SIGNATURES)
+        .ui(ui, &mut tab.editor_state, editor_resources);
 
-      if ui.button("Run").clicked() {
-        run_script_events.send(RunScriptEvent {
-          code: state.code.clone(),
+      if response.changed() {
+        check_script_events.send(CheckScriptEvent {
+          code: tab.code.clone(),
         });
       }
+
+      if let Some(error) = error {
+        ui.add(
+          widgets::Label::new(format!("Line {}: {}", error.row + 1, error.message))
+            .text_color(Color32::LIGHT_RED),
+        );
+      }
+
+      let selection = tab.editor_state.selection(&tab.code);
+      ui.horizontal(|ui| {
+        if ui.button("Run file").clicked() {
+          run_script_events.send(RunScriptEvent {
+            code: tab.code.clone(),
+            mode: Mode::Exec,
+            trust: ScriptTrust::Trusted,
+            filename: tab.filename().to_owned(),
+          });
+        }
+
+        if ui
+          .add(widgets::Button::new("Run selection").enabled(selection.is_some()))
+          .clicked()
+        {
+          if let Some((row, code)) = selection {
+            // Pad with blank lines so line numbers in tracebacks match the file
+            run_script_events.send(RunScriptEvent {
+              code: "\n".repeat(row) + &dedent(code),
+              mode: Mode::Exec,
+              trust: ScriptTrust::Trusted,
+              filename: tab.filename().to_owned(),
+            });
+          }
+        }
+      });
     });
   });
+
+  let tab = &state.tabs[state.active_tab];
+  if state.active_tab != previous_tab {
+    check_script_events.send(CheckScriptEvent {
+      code: tab.code.clone(),
+    });
+  }
+
+  let path = &tab.editor_state.completion_path;
+  if state.requested_completion_path.as_ref() != Some(path) {
+    completion_request_events.send(CompletionRequestEvent { path: path.clone() });
+    state.requested_completion_path = Some(path.clone());
+  }
 }
 
 fn terminal_system(
-  controller: Res<CharacterController>,
-  keyboard_input: Res<Input<KeyCode>>,
+  actions: Res<ActionState>,
   egui_context: Res<EguiContext>,
   mut window_manager: ResMut<UiWindowManager>,
   mut ui_lock: Local<Option<UiLock>>,
@@ -95,8 +673,24 @@ fn terminal_system(
   mut script_output_events: EventReader<ScriptOutputEvent>,
   windows: Res<Windows>,
   editor_resources: Res<EditorResources>,
+  (
+    console_completions,
+    mut console_command_events,
+    mut console_completion_request_events,
+    mut clear_terminal_events,
+  ): (
+    Res<ConsoleCompletions>,
+    ResMut<Events<ConsoleCommandEvent>>,
+    ResMut<Events<ConsoleCompletionRequestEvent>>,
+    EventReader<ClearTerminalEvent>,
+  ),
+  script_completions: Res<ScriptCompletions>,
+  script_diagnostics: Res<ScriptDiagnostics>,
+  last_error: Res<LastScriptError>,
+  mut check_script_events: ResMut<Events<CheckScriptEvent>>,
+  mut completion_request_events: ResMut<Events<CompletionRequestEvent>>,
 ) {
-  let just_pressed = keyboard_input.just_pressed(controller.input_map.key_toggle_terminal);
+  let just_pressed = actions.just_pressed(Action::ToggleTerminal);
   if just_pressed {
     match ui_lock.take() {
       Some(lock) => {
@@ -109,7 +703,10 @@ fn terminal_system(
   }
 
   for event in script_output_events.iter() {
-    state.logs.push(event.output.clone());
+    state.push_log(event.level, &event.output);
+  }
+  if clear_terminal_events.iter().count() > 0 {
+    state.logs.clear();
   }
 
   if ui_lock.is_some() {
@@ -122,23 +719,50 @@ fn terminal_system(
       .default_height(height - 100.)
       .show(ctx, |ui| {
         ui.columns(2, |columns| {
-          repl(&mut state, &mut columns[0], &mut run_script_events);
+          repl(
+            &mut state,
+            &mut columns[0],
+            &mut run_script_events,
+            &console_completions,
+            &mut console_command_events,
+            &mut console_completion_request_events,
+          );
           editor(
             &mut state,
             &mut columns[1],
             &editor_resources,
             &mut run_script_events,
+            &script_completions,
+            &script_diagnostics,
+            &last_error,
+            &mut check_script_events,
+            &mut completion_request_events,
           );
         });
       });
   }
 }
 
+/// Clears the terminal's scrollback.
+pub struct ClearTerminalEvent;
+
+fn clear_command() -> ConsoleCommand {
+  ConsoleCommand::new("clear", "Clears the terminal", |world, _| {
+    world
+      .get_resource_mut::<Events<ClearTerminalEvent>>()
+      .unwrap()
+      .send(ClearTerminalEvent);
+    Ok(None)
+  })
+}
+
 pub struct TerminalPlugin;
 impl Plugin for TerminalPlugin {
   fn build(&self, app: &mut App) {
     app
       .init_resource::<EditorResources>()
-      .add_system(terminal_system.system());
+      .add_event::<ClearTerminalEvent>()
+      .add_system(terminal_system.system())
+      .add_console_command(clear_command());
   }
 }
@@ -0,0 +1,452 @@
+use crate::{
+  console::{AppConsoleExt, ArgKind, ConsoleCommand},
+  cvars::{AppCvarsExt, Cvar, Cvars},
+  interaction::{Usable, UseEvent, USE_SYSTEM},
+  player::{
+    camera::{CameraMode, ORBIT_DISTANCE},
+    controller::CharacterController,
+    input_map::{Action, ActionState, InputMap},
+    raycast::ViewInfo,
+    spawn::Player,
+  },
+  prelude::*,
+  ui::UiWindowManager,
+};
+use anyhow::anyhow;
+use bevy_rapier3d::{
+  na::{Isometry3, Point3, Unit, UnitQuaternion, Vector3},
+  physics::{JointBuilderComponent, JointHandleComponent},
+  prelude::*,
+  rapier::dynamics::{BodyStatus, FixedJoint, JointParams, JointSet, RevoluteJoint},
+};
+
+/// A wheel spun by the motor of the revolute joint that holds it to its chassis.
+pub struct Wheel {
+  pub chassis: Entity,
+  /// Entity of the joint, see `JointHandleComponent`.
+  pub joint: Entity,
+}
+
+/// Pushes its body along `axis`, in the body's local space, while `action` is held.
+pub struct Thruster {
+  /// One of `Action::THRUSTERS`.
+  pub action: Action,
+  pub axis: Vec3,
+  pub force: f32,
+}
+
+/// A body the player can get into with the use key to drive the wheels attached to it.
+pub struct Seat;
+
+/// Throttle and steering from the player driving `seat`, from -1 to 1.
+pub struct DriveEvent {
+  pub seat: Entity,
+  pub forward: f32,
+  pub turn: f32,
+}
+
+pub struct ThrustEvent {
+  pub thruster: Entity,
+  /// How far the thruster's action is held, from 0 to 1.
+  pub throttle: f32,
+}
+
+pub const WHEEL_SPEED: &str = "vehicle.wheel_speed";
+pub const WHEEL_TORQUE: &str = "vehicle.wheel_torque";
+
+const DEFAULT_WHEEL_RADIUS: f32 = 1.;
+const DEFAULT_THRUST: f32 = 50.;
+const THRUSTER_SIZE: f32 = 0.5;
+/// Space left between a part and the surface it is attached to, so they don't start out touching.
+const PART_GAP: f32 = 0.05;
+const MOTOR_FACTOR: f32 = 0.5;
+
+fn drive_input(
+  actions: Res<ActionState>,
+  cvars: Res<Cvars>,
+  controller: Res<CharacterController>,
+  player: Res<Player>,
+  ui_window_manager: Res<UiWindowManager>,
+  mut camera_mode: ResMut<CameraMode>,
+  mut use_events: EventReader<UseEvent>,
+  mut drive_events: EventWriter<DriveEvent>,
+  seat_query: Query<&GlobalTransform, With<Seat>>,
+  mut body_query: Query<(&mut RigidBodyPosition, &mut RigidBodyVelocity)>,
+) {
+  let seat = match *camera_mode {
+    CameraMode::Driving { seat, .. } => seat,
+    _ => {
+      // Get in when the player uses a seat
+      let seat = use_events
+        .iter()
+        .map(|event| event.target)
+        .find(|target| seat_query.get(*target).is_ok());
+      if let Some(seat) = seat {
+        *camera_mode = CameraMode::Driving {
+          seat,
+          distance: cvars.float(ORBIT_DISTANCE),
+        };
+      }
+      return;
+    }
+  };
+  let seat_transform = match seat_query.get(seat) {
+    Ok(transform) => transform,
+    Err(_) => return,
+  };
+
+  if ui_window_manager.is_showing() {
+    drive_events.send(DriveEvent {
+      seat,
+      forward: 0.,
+      turn: 0.,
+    });
+    return;
+  }
+
+  if actions.just_pressed(Action::Use) {
+    // Get out on top of the vehicle, which brakes its wheels
+    *camera_mode = CameraMode::Attached;
+    drive_events.send(DriveEvent {
+      seat,
+      forward: 0.,
+      turn: 0.,
+    });
+    if let Ok((mut position, mut velocity)) = body_query.get_mut(player.body) {
+      let exit = seat_transform.translation + Vec3::Y * controller.height;
+      let isometry = Isometry3::from_parts(exit.to_na_translation(), position.position.rotation);
+      position.position = isometry;
+      position.next_position = isometry;
+      velocity.linvel = Vector3::zeros();
+    }
+    return;
+  }
+
+  drive_events.send(DriveEvent {
+    seat,
+    forward: actions.axis(Action::Forward, Action::Backward),
+    turn: actions.axis(Action::Right, Action::Left),
+  });
+}
+
+/// Sets the motors of the wheels of driven vehicles. Wheels on either side spin at different
+/// speeds to turn, like a tank.
+fn apply_drive(
+  time: Res<Time>,
+  cvars: Res<Cvars>,
+  mut drive_events: EventReader<DriveEvent>,
+  mut joint_set: ResMut<JointSet>,
+  transform_query: Query<&GlobalTransform>,
+  wheel_query: Query<(Entity, &Wheel, &GlobalTransform)>,
+  joint_query: Query<&JointHandleComponent>,
+  mut activation_query: Query<&mut RigidBodyActivation>,
+) {
+  for event in drive_events.iter() {
+    let chassis = match transform_query.get(event.seat) {
+      Ok(transform) => transform,
+      Err(_) => continue,
+    };
+    let forward = chassis.rotation * -Vec3::Z;
+    let right = chassis.rotation * Vec3::X;
+
+    for (entity, wheel, wheel_transform) in wheel_query.iter() {
+      if wheel.chassis != event.seat {
+        continue;
+      }
+      let joint = match joint_query
+        .get(wheel.joint)
+        .ok()
+        .and_then(|joint| joint_set.get_mut(joint.handle()))
+      {
+        Some(joint) => joint,
+        None => continue,
+      };
+
+      if let JointParams::RevoluteJoint(revolute) = &mut joint.params {
+        // Spin the way that makes the bottom of the wheel push the chassis forward
+        let axis = wheel_transform.rotation * Vec3::Y;
+        let rolling = axis.cross(Vec3::Y).dot(forward).signum();
+        let side = (wheel_transform.translation - chassis.translation)
+          .dot(right)
+          .signum();
+        let throttle = (event.forward - event.turn * side).clamp(-1., 1.);
+        revolute
+          .configure_motor_velocity(throttle * rolling * cvars.float(WHEEL_SPEED), MOTOR_FACTOR);
+        revolute.motor_max_impulse = cvars.float(WHEEL_TORQUE) * time.delta_seconds();
+      }
+
+      if event.forward != 0. || event.turn != 0. {
+        for body in [entity, event.seat].iter() {
+          if let Ok(mut activation) = activation_query.get_mut(*body) {
+            activation.wake_up(true);
+          }
+        }
+      }
+    }
+  }
+}
+
+fn thruster_input(
+  actions: Res<ActionState>,
+  ui_window_manager: Res<UiWindowManager>,
+  mut thrust_events: EventWriter<ThrustEvent>,
+  thruster_query: Query<(Entity, &Thruster)>,
+) {
+  if ui_window_manager.is_showing() {
+    return;
+  }
+  for (entity, thruster) in thruster_query.iter() {
+    let throttle = actions.value(thruster.action);
+    if throttle > 0. {
+      thrust_events.send(ThrustEvent {
+        thruster: entity,
+        throttle,
+      });
+    }
+  }
+}
+
+fn apply_thrust(
+  time: Res<Time>,
+  mut thrust_events: EventReader<ThrustEvent>,
+  mut thruster_query: Query<(
+    &Thruster,
+    &RigidBodyPosition,
+    &RigidBodyMassProps,
+    &mut RigidBodyVelocity,
+    &mut RigidBodyActivation,
+  )>,
+) {
+  for event in thrust_events.iter() {
+    if let Ok((thruster, position, mass_props, mut velocity, mut activation)) =
+      thruster_query.get_mut(event.thruster)
+    {
+      let direction = position.position.rotation * thruster.axis.to_na_vector3();
+      velocity.apply_impulse(
+        mass_props,
+        direction * thruster.force * event.throttle * time.delta_seconds(),
+      );
+      activation.wake_up(true);
+    }
+  }
+}
+
+/// The body the player is looking at, where they are looking at it and the surface normal there.
+fn looked_at_body(world: &mut World) -> anyhow::Result<(Entity, Point3<f32>, Vector3<f32>)> {
+  let view_info = world.get_resource::<ViewInfo>().unwrap();
+  let hit = view_info
+    .hit
+    .as_ref()
+    .ok_or_else(|| anyhow!("look at something to attach it to"))?;
+  let entity = hit.entity;
+  let point = view_info.hit_point().unwrap();
+  let normal = hit.intersection.normal;
+  if world.get::<RigidBodyPosition>(entity).is_none() {
+    anyhow::bail!("only props can have parts attached");
+  }
+  Ok((entity, point, normal))
+}
+
+/// Turns a part's local Y axis towards `normal`.
+fn facing(normal: &Vector3<f32>) -> UnitQuaternion<f32> {
+  UnitQuaternion::rotation_between(&Vector3::y(), normal)
+    .unwrap_or_else(|| UnitQuaternion::from_axis_angle(&Vector3::x_axis(), std::f32::consts::PI))
+}
+
+fn spawn_part(
+  world: &mut World,
+  mesh: Mesh,
+  shape: ColliderShape,
+  position: Isometry3<f32>,
+  name: &str,
+) -> Entity {
+  let mesh = world.get_resource_mut::<Assets<Mesh>>().unwrap().add(mesh);
+  let material = world
+    .get_resource_mut::<Assets<StandardMaterial>>()
+    .unwrap()
+    .add(Color::rgb(0.2, 0.2, 0.2).into());
+  world
+    .spawn()
+    .insert_bundle(PbrBundle {
+      mesh,
+      material,
+      transform: Transform {
+        translation: position.translation.vector.to_glam_vec3(),
+        rotation: position.rotation.to_glam_quat(),
+        ..Default::default()
+      },
+      ..Default::default()
+    })
+    .insert_bundle(RigidBodyBundle {
+      body_type: BodyStatus::Dynamic,
+      position: position.into(),
+      ..Default::default()
+    })
+    .insert_bundle(ColliderBundle {
+      shape,
+      mass_properties: ColliderMassProps::Density(1.0),
+      material: ColliderMaterial {
+        friction: 1.0,
+        ..Default::default()
+      },
+      ..Default::default()
+    })
+    .insert(RigidBodyPositionSync::Discrete)
+    .insert(Name::new(name.to_owned()))
+    .id()
+}
+
+fn wheel_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "wheel",
+    "Attaches a motorized wheel to what you are looking at",
+    |world, args| {
+      let radius = args.float(0).unwrap_or(DEFAULT_WHEEL_RADIUS);
+      if radius <= 0. {
+        anyhow::bail!("the radius must be positive");
+      }
+      let (chassis, point, normal) = looked_at_body(world)?;
+      let chassis_position = world.get::<RigidBodyPosition>(chassis).unwrap().position;
+
+      let half_width = radius * 0.4;
+      let center = point + normal * (half_width + PART_GAP);
+      let mesh = Mesh::from(shape::Torus {
+        radius: radius - half_width,
+        ring_radius: half_width,
+        subdivisions_segments: 24,
+        subdivisions_sides: 12,
+      });
+      let wheel = spawn_part(
+        world,
+        mesh,
+        ColliderShape::cylinder(half_width, radius),
+        Isometry3::from_parts(center.coords.into(), facing(&normal)),
+        "wheel",
+      );
+
+      let joint = RevoluteJoint::new(
+        chassis_position.inverse_transform_point(&center),
+        Unit::new_normalize(chassis_position.rotation.inverse() * normal),
+        Point3::origin(),
+        Vector3::y_axis(),
+      );
+      let joint = world
+        .spawn()
+        .insert(JointBuilderComponent::new(joint, chassis, wheel))
+        .id();
+      world.entity_mut(wheel).insert(Wheel { chassis, joint });
+      Ok(None)
+    },
+  )
+  .optional_arg("radius", ArgKind::Float)
+}
+
+fn thruster_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "thruster",
+    "Attaches a thruster that pushes what you are looking at while the controls of its group \
+     are held",
+    |world, args| {
+      let group = args.int(0).unwrap();
+      if group < 1 || group > Action::THRUSTERS.len() as i64 {
+        anyhow::bail!(
+          "the group must be between 1 and {}",
+          Action::THRUSTERS.len()
+        );
+      }
+      let action = Action::THRUSTERS[group as usize - 1];
+      let force = args.float(1).unwrap_or(DEFAULT_THRUST);
+      let (body, point, normal) = looked_at_body(world)?;
+      let body_position = world.get::<RigidBodyPosition>(body).unwrap().position;
+
+      let half_size = THRUSTER_SIZE / 2.;
+      let center = point + normal * (half_size + PART_GAP);
+      let position = Isometry3::from_parts(center.coords.into(), facing(&normal));
+      let thruster = spawn_part(
+        world,
+        Mesh::from(shape::Cube {
+          size: THRUSTER_SIZE,
+        }),
+        ColliderShape::cuboid(half_size, half_size, half_size),
+        position,
+        "thruster",
+      );
+
+      let joint = FixedJoint::new(body_position.inverse() * position, Isometry3::identity());
+      world
+        .spawn()
+        .insert(JointBuilderComponent::new(joint, body, thruster));
+      // Push into the surface it's attached to
+      world.entity_mut(thruster).insert(Thruster {
+        action,
+        axis: -Vec3::Y,
+        force,
+      });
+
+      let bindings = world
+        .get_resource::<InputMap>()
+        .unwrap()
+        .bindings(action)
+        .map(|binding| binding.to_string())
+        .collect::<Vec<_>>();
+      Ok(Some(if bindings.is_empty() {
+        format!("thruster in group {}, which has no controls bound", group)
+      } else {
+        format!("thruster in group {} on {}", group, bindings.join(" or "))
+      }))
+    },
+  )
+  .arg("group", ArgKind::Int)
+  .optional_arg("force", ArgKind::Float)
+}
+
+fn seat_command() -> ConsoleCommand {
+  ConsoleCommand::new(
+    "seat",
+    "Makes what you are looking at into a seat that drives its wheels, or back",
+    |world, _args| {
+      let (body, _, _) = looked_at_body(world)?;
+      let mut entity = world.entity_mut(body);
+      if entity.get::<Seat>().is_some() {
+        entity.remove::<Seat>();
+        entity.remove::<Usable>();
+        Ok(Some("removed seat".to_owned()))
+      } else {
+        entity.insert_bundle((Seat, Usable::new("Drive")));
+        Ok(Some("added seat".to_owned()))
+      }
+    },
+  )
+}
+
+pub struct VehiclePlugin;
+impl Plugin for VehiclePlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_event::<DriveEvent>()
+      .add_event::<ThrustEvent>()
+      .add_cvar(
+        Cvar::float(
+          WHEEL_SPEED,
+          "Top speed of vehicle wheels, in radians per second",
+          20.,
+        )
+        .range(0., 1000.),
+      )
+      .add_cvar(
+        Cvar::float(
+          WHEEL_TORQUE,
+          "Strongest torque vehicle wheel motors apply",
+          50.,
+        )
+        .range(0., 100000.),
+      )
+      .add_system(drive_input.system().after(USE_SYSTEM))
+      .add_system(apply_drive.system())
+      .add_system(thruster_input.system())
+      .add_system(apply_thrust.system())
+      .add_console_command(wheel_command())
+      .add_console_command(thruster_command())
+      .add_console_command(seat_command());
+  }
+}